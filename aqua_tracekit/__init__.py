@@ -1,4 +1,17 @@
-from aqua_tracekit._core import SdtModel, Aggregation  
+from aqua_tracekit._core import (
+    SdtModel,
+    Aggregation,
+    TraceResult,
+    ValidationError,
+    VisualizationConfig,
+)
 from .schema import SdtSchema
 
-__all__ = ["SdtModel", "SdtSchema", "Aggregation"]
\ No newline at end of file
+__all__ = [
+    "SdtModel",
+    "SdtSchema",
+    "Aggregation",
+    "TraceResult",
+    "ValidationError",
+    "VisualizationConfig",
+]
\ No newline at end of file
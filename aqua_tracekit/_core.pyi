@@ -1,17 +1,43 @@
 """Type stubs for aqua-tracekit._core (Rust extension module)."""
 
-from typing import Any, Callable, Optional
+from typing import Any, Callable, Iterator, Literal, Optional, overload
 from datetime import datetime
 import polars as pl
 
 class SdtModel:
     """Main model for aqua-tracekit with fishgroup segments, transfers, and containers."""
     
-    def __init__(self, base_path: str) -> None:
+    def __init__(
+        self,
+        base_path: str,
+        trace_cache_size: int = 8,
+        interval_closed: Literal["left", "right", "both", "neither"] = "left",
+        filenames: Optional[dict[str, str]] = None,
+        categorical_ids: bool = False,
+    ) -> None:
         """Initialize model with base path for CSV files.
         
         Args:
-            base_path: Directory path containing CSV files
+            base_path: Directory path containing CSV files, or an object-store
+                       URL (s3://..., gs://...) when built with the `cloud` feature
+            trace_cache_size: Number of trace_segments results kept for reuse;
+                       0 disables caching (default: 8)
+            interval_closed: Which endpoints of a segment's [start_time, end_time]
+                       interval count as active, for every method matching a time
+                       to segments. "left" (default) is start_time <= t < end_time,
+                       so a reading at a transfer instant belongs to the segment
+                       starting there. A null end_time is open-ended. A
+                       zero-duration segment (start_time == end_time) always
+                       matches exactly that instant.
+            filenames: File read by load_transfers, load_segments and
+                       load_containers when called without a filename, keyed
+                       "transfers", "segments" and "containers", e.g.
+                       {"transfers": "fish_moves.csv"}. Loaders left out read
+                       transfers.csv / segments.csv / containers.csv
+            categorical_ids: Keep the loaded transfers' source_segment_id and
+                       dest_segment_id as Categorical to cut the memory held for
+                       large transfer tables (default: False). Every returned
+                       frame, including transfers_df, still has String ids
         """
         ...
     
@@ -21,19 +47,47 @@ class SdtModel:
         self,
         filename: str,
         rename: Optional[dict[str, str]] = None,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+        has_header: bool = True,
+        column_names: Optional[list[str]] = None,
     ) -> pl.DataFrame:
         """Load any CSV into a Polars DataFrame with all columns as strings.
         
+        Arrow IPC files (.arrow, .feather, .ipc) are read as well.
+        
         Args:
             filename: CSV filename relative to base_path
             rename: Optional dictionary mapping old column names to new names
+            preserve_dtypes: Keep Arrow column types instead of converting to strings
+                             (id columns are always strings; ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            has_header: Whether the first CSV row holds the column names. Headerless
+                        files get column_1, column_2, ... (default: True)
+            column_names: Names for every column in the file, replacing the header
+                          or the generated names; applied before rename
             
         Returns:
             DataFrame with all columns as strings
         """
         ...
     
-    def load_transfers(self, filename: Optional[str] = None) -> pl.DataFrame:
+    def load_transfers(
+        self,
+        filename: Optional[str] = None,
+        create_missing_stock: bool = True,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+        forward_group_cols: Optional[list[str]] = None,
+        backward_group_cols: Optional[list[str]] = None,
+        clamp_negative_factors: bool = False,
+        validate_only: bool = False,
+        factors_as_percent: bool = False,
+    ) -> pl.DataFrame:
         """Load transfers CSV.
         
         Required columns: source_segment_id, dest_segment_id
@@ -41,67 +95,307 @@ class SdtModel:
         or factor columns (share_count_forward, share_biomass_forward, 
                           share_count_backward, share_biomass_backward)
         
+        Weighted aggregations only use the factor columns, so a factor-only
+        file works the same as one with stock columns.
+        
         Args:
-            filename: CSV filename (default: "transfers.csv")
+            filename: CSV filename (default: the filenames["transfers"] given to
+                      the model, else "transfers.csv")
+            create_missing_stock: Add null stock columns when the file has none
+                                  (default: True). Set to False to leave them out;
+                                  flow_into, flow_out_of and biomass_flow_matrix
+                                  then raise.
+            preserve_dtypes: Keep Arrow column types (ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            forward_group_cols: Columns to normalize stock over when calculating
+                                forward factors (default: ["source_segment_id"])
+            backward_group_cols: Columns to normalize stock over when calculating
+                                 backward factors (default: ["dest_segment_id"])
+            clamp_negative_factors: Set negative factors to 0.0 and report how many
+                                    with a UserWarning. NaN or infinite factors are
+                                    then rejected, so all factors are finite and
+                                    non-negative (default: False)
+            validate_only: Check and process the file and return the result without
+                           storing it in the model (default: False)
+            factors_as_percent: Factor columns in the file are percentages (0-100);
+                                divide them by 100 (default: False)
             
         Returns:
             DataFrame with transfers and calculated share factors
+            
+        Raises:
+            ValidationError: When a factor column has null (or, with
+                             clamp_negative_factors, NaN or infinite) values;
+                             column names it and rows holds the offending transfers
         """
         ...
     
-    def load_containers(self, filename: Optional[str] = None) -> pl.DataFrame:
+    def load_transfers_merge(
+        self,
+        filenames: list[str],
+        create_missing_stock: bool = True,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+    ) -> pl.DataFrame:
+        """Load several transfer files as one logical graph.
+        
+        For phases kept in separate files (e.g. freshwater, seawater and the
+        "move to sea" transfers linking them). Files are read in order and
+        concatenated, then share factors are derived once over the union.
+        Replaces any loaded transfers.
+        
+        Args:
+            filenames: Transfer files relative to base_path, in order
+            create_missing_stock: Add null stock columns when no file has them (default: True)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            
+        Returns:
+            DataFrame with the combined transfers and share factors
+        """
+        ...
+    
+    def append_transfers(
+        self,
+        filename: str,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+    ) -> pl.DataFrame:
+        """Append transfer rows from another CSV to the loaded transfers.
+        
+        The file is validated like load_transfers. Missing share factors are
+        calculated from the stock within the appended file, or from the stock
+        of both when appended rows share a source or dest segment with the
+        loaded transfers. An already built tracer is extended in place instead
+        of being rebuilt (it is rebuilt on next use if factors were
+        recalculated). Rows that would introduce a cycle are rejected and the
+        model is left unchanged.
+        
+        Args:
+            filename: CSV filename relative to base_path
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            
+        Returns:
+            DataFrame with the appended transfers
+            
+        Raises:
+            ValidationError: If appended rows share a segment with the loaded
+                transfers but either side read its share factors from file, so
+                they can't be recalculated; rows lists the appended rows
+        """
+        ...
+    
+    def diff_transfers(
+        self,
+        filename: str,
+        tolerance: float = 1e-9,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+    ) -> pl.DataFrame:
+        """Compare another transfers file against the loaded transfers.
+        
+        The file is processed like load_transfers and matched to the loaded
+        rows on (source_segment_id, dest_segment_id). Stock columns are only
+        compared when both the loaded transfers and the file provide them.
+        The model is not modified.
+        
+        Args:
+            filename: CSV filename relative to base_path
+            tolerance: Absolute difference below which values count as equal
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            
+        Returns:
+            DataFrame with source_segment_id, dest_segment_id, change
+            ("added", "removed" or "changed") and changed_columns (comma-separated
+            stock/factor columns that differ; null unless changed)
+            
+        Raises:
+            ValidationError: If the loaded transfers or the file repeat a
+                (source_segment_id, dest_segment_id) pair; rows lists them
+        """
+        ...
+    
+    def merge_segments(self, mapping: dict[str, str]) -> pl.DataFrame:
+        """Collapse relabeled segments into one node before tracing.
+        
+        Transfer source/dest ids and segment ids are rewritten according to
+        the mapping. Transfers that become self-loops are dropped and parallel
+        transfers are combined: stock is summed and share factors recalculated
+        when stock is complete, otherwise the factors are summed. Merged
+        segments span the earliest start to the latest end time, and stay open
+        (null end_time) if any part is. The model is left unchanged if the
+        merge fails.
+        
+        Args:
+            mapping: Old segment id -> segment id to merge it into
+            
+        Returns:
+            DataFrame with the merged transfers
+            
+        Raises:
+            ValidationError: If stock is incomplete and the summed factors of a
+                source (forward) or dest (backward) would exceed 1; rows lists
+                the merged transfers involved
+            RuntimeError: If the merge would create a cycle
+        """
+        ...
+    
+    def merge_containers(self, mapping: dict[str, str]) -> None:
+        """Treat several containers as one, e.g. tanks reported as a single unit.
+        
+        container_id is rewritten in the loaded containers and segments, so
+        visualization lanes and container-data mapping see one container.
+        Merged container rows keep the first row's other columns. Transfers and
+        segment ids are unchanged, so tracing is not affected.
+        
+        Args:
+            mapping: Old container id -> container id to merge it into
+            
+        Raises:
+            ValidationError: If segments of a merged container would overlap in
+                time under the model's interval_closed (with "both", segments
+                touching at a transfer instant overlap); rows lists the
+                overlapping consecutive pairs (container_id,
+                prev_segment_id, next_segment_id, prev_end_time,
+                next_start_time). The model is left unchanged.
+        """
+        ...
+    
+    def load_containers(
+        self,
+        filename: Optional[str] = None,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+        validate_only: bool = False,
+    ) -> pl.DataFrame:
         """Load containers CSV.
         
         Required columns: container_id
         
         Args:
-            filename: CSV filename (default: "containers.csv")
+            filename: CSV filename (default: the filenames["containers"] given to
+                      the model, else "containers.csv")
+            preserve_dtypes: Keep Arrow column types (ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            validate_only: Check and process the file and return the result without
+                           storing it in the model (default: False)
             
         Returns:
             DataFrame with containers
         """
         ...
     
-    def load_segments(self, filename: Optional[str] = None) -> pl.DataFrame:
+    def load_segments(
+        self,
+        filename: Optional[str] = None,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+        validate_only: bool = False,
+        time_format: Optional[str] = None,
+        infer_end_time: bool = False,
+    ) -> pl.DataFrame:
         """Load segments CSV.
         
         Required columns: segment_id, container_id, start_time, end_time
-        Datetime columns are parsed using format "%Y-%m-%d %H:%M:%S"
+        Datetime columns are parsed using format "%Y-%m-%d %H:%M:%S" or
+        time_format; Datetime columns of an Arrow file are used as they are,
+        with or without preserve_dtypes
         
         Args:
-            filename: CSV filename (default: "segments.csv")
+            filename: CSV filename (default: the filenames["segments"] given to
+                      the model, else "segments.csv")
+            preserve_dtypes: Keep Arrow column types (ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
+            validate_only: Check and process the file and return the result without
+                           storing it in the model (default: False)
+            time_format: strptime format for start_time/end_time, or "epoch_s",
+                         "epoch_ms" or "epoch_us" for Unix epoch integers
+                         (default: "%Y-%m-%d %H:%M:%S")
+            infer_end_time: Fill null end_time values with the start_time of the
+                            next segment in the same container; the last one stays
+                            null (still active). The end_time column may then be
+                            left out of the file (default: False)
             
         Returns:
             DataFrame with segments
         """
         ...
     
-    def load_segment_timeseries(self, filename: str) -> pl.DataFrame:
+    def load_segment_timeseries(
+        self,
+        filename: str,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+    ) -> pl.DataFrame:
         """Load segment-level timeseries CSV.
         
         Required columns: segment_id, date_time
         
         Args:
             filename: CSV filename
+            preserve_dtypes: Keep Arrow column types (ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
             
         Returns:
             DataFrame with segment timeseries
         """
         ...
     
-    def load_container_timeseries(self, filename: str) -> pl.DataFrame:
+    def load_container_timeseries(
+        self,
+        filename: str,
+        preserve_dtypes: bool = False,
+        encoding: Optional[str] = None,
+        trim_values: bool = False,
+    ) -> pl.DataFrame:
         """Load container-level timeseries CSV.
         
         Required columns: container_id, date_time
         
         Args:
             filename: CSV filename
+            preserve_dtypes: Keep Arrow column types (ignored for CSV)
+            encoding: CSV file encoding, e.g. "latin1" or "cp1252" (default: UTF-8)
+            trim_values: Strip leading/trailing whitespace from every string cell.
+                         Id columns are always stripped (default: False)
             
         Returns:
             DataFrame with container timeseries
         """
         ...
     
+    def export(
+        self,
+        df: pl.DataFrame,
+        filename: str,
+        format: Optional[str] = None,
+    ) -> None:
+        """Write a DataFrame to a file under base_path.
+        
+        Args:
+            df: DataFrame to write
+            filename: Output filename relative to base_path
+            format: "csv", "parquet" or "arrow" (Arrow IPC / Feather).
+                    Default: taken from the file extension
+        """
+        ...
+    
     # ── Parse helpers ──
     
     @staticmethod
@@ -115,7 +409,8 @@ class SdtModel:
         Args:
             df: Input DataFrame
             column: Column name to parse
-            format: Datetime format string (e.g., "%Y-%m-%d %H:%M:%S")
+            format: Datetime format string (e.g., "%Y-%m-%d %H:%M:%S"), or
+                    "epoch_s" / "epoch_ms" / "epoch_us" for Unix epoch integers
             
         Returns:
             DataFrame with parsed datetime column
@@ -123,12 +418,19 @@ class SdtModel:
         ...
     
     @staticmethod
-    def parse_float(df: pl.DataFrame, column: str) -> pl.DataFrame:
+    def parse_float(
+        df: pl.DataFrame,
+        column: str,
+        strip_chars: Optional[str] = None,
+        thousands: Optional[str] = None,
+    ) -> pl.DataFrame:
         """Parse a string column to Float64.
         
         Args:
             df: Input DataFrame
             column: Column name to parse
+            strip_chars: Characters to remove anywhere in the value, e.g. "$ kg"
+            thousands: Thousands separator to remove, e.g. "," (single character)
             
         Returns:
             DataFrame with parsed float column
@@ -136,28 +438,540 @@ class SdtModel:
         ...
     
     @staticmethod
-    def parse_int(df: pl.DataFrame, column: str) -> pl.DataFrame:
+    def parse_int(
+        df: pl.DataFrame,
+        column: str,
+        strip_chars: Optional[str] = None,
+        thousands: Optional[str] = None,
+    ) -> pl.DataFrame:
         """Parse a string column to Int64.
         
         Args:
-            df: Input DataFrame
-            column: Column name to parse
+            df: Input DataFrame
+            column: Column name to parse
+            strip_chars: Characters to remove anywhere in the value, e.g. "$ kg"
+            thousands: Thousands separator to remove, e.g. "," (single character)
+            
+        Returns:
+            DataFrame with parsed int column
+        """
+        ...
+    
+    @staticmethod
+    def winsorize(
+        df: pl.DataFrame,
+        column: str,
+        lower_q: float = 0.01,
+        upper_q: float = 0.99,
+        group_by: Optional[list[str]] = None,
+    ) -> pl.DataFrame:
+        """Clamp a numeric column to its per-group quantile bounds.
+        
+        Useful to tame sensor spikes before aggregate_traced_data.
+        
+        Args:
+            df: Input DataFrame
+            column: Numeric column to clamp (cast to Float64)
+            lower_q: Lower quantile bound (default: 0.01)
+            upper_q: Upper quantile bound (default: 0.99)
+            group_by: Columns to compute quantiles within (default: whole frame)
+            
+        Returns:
+            DataFrame with the column clamped to the quantile bounds
+        """
+        ...
+    
+    # ── Tracing methods ──
+    
+    @overload
+    def trace_segments(
+        self,
+        origin_df: pl.DataFrame,
+        progress_callback: Optional[Callable[[int, int], Any]] = None,
+        origin_attrs: Optional[list[str]] = None,
+        distance_metric: Optional[Literal["hops", "neglog_factor"]] = None,
+        stop_ids: Optional[list[str]] = None,
+        drop_zero_factor: Optional[bool] = None,
+        min_factor: Optional[float] = None,
+        detail: Optional[bool] = None,
+        *,
+        as_result: Literal[True],
+    ) -> TraceResult: ...
+    @overload
+    def trace_segments(
+        self,
+        origin_df: pl.DataFrame,
+        progress_callback: Optional[Callable[[int, int], Any]] = None,
+        origin_attrs: Optional[list[str]] = None,
+        distance_metric: Optional[Literal["hops", "neglog_factor"]] = None,
+        stop_ids: Optional[list[str]] = None,
+        drop_zero_factor: Optional[bool] = None,
+        min_factor: Optional[float] = None,
+        detail: Optional[bool] = None,
+        as_result: Literal[False] = False,
+    ) -> pl.DataFrame:
+        """Trace segments from a DataFrame containing segment_id column.
+        
+        Results are cached per origin list and trace options and invalidated when
+        transfers are loaded or appended. Rows follow the order of origin_df, so the
+        same origins in another order are traced again rather than served from
+        cache. The segments traced from each origin come in an order that only
+        depends on the segment ids, so output is stable across runs. Options left out fall back to the defaults set with
+        configure_trace.
+        
+        Args:
+            origin_df: DataFrame with segment_id column
+            progress_callback: Called as callback(done, total) about every 1% of
+                               origins traced and on completion
+            origin_attrs: Segment columns of the origin to add to every row,
+                          prefixed "origin_" (e.g. ["species"] -> origin_species).
+                          Requires loaded segments. "segment_id", repeated
+                          names and names whose origin_ column the trace
+                          already has raise ValidationError before tracing.
+            distance_metric: Add a trace_distance column with the shortest-path
+                             distance from the origin: "hops" counts transfers,
+                             "neglog_factor" sums -ln(share_count_forward) downstream
+                             or -ln(share_count_backward) upstream, so larger means
+                             more diluted. Paths obey stop_ids and min_factor.
+                             Identity rows are 0 (default: None)
+            stop_ids: Segments not traced past (e.g. harvest segments). They
+                      are included in the result, but segments beyond them and
+                      factors of paths through them are not (default: None)
+            drop_zero_factor: Omit non-identity rows whose four share factors
+                              are all zero. They contribute nothing to weighted
+                              aggregations, but show reachability (default:
+                              None, the configure_trace setting, else False)
+            min_factor: Do not follow transfers moving less than this share:
+                        share_count_forward is checked downstream and
+                        share_count_backward upstream. Segments reached only
+                        through such transfers are left out (default: None,
+                        the configure_trace setting)
+            detail: Add n_paths (number of transfer paths from origin to
+                    traced segment), min_path_factor and max_path_factor (the
+                    smallest and largest single-path share_count product in the
+                    trace direction). Identity rows count one path with factor
+                    1.0 (default: None, the configure_trace setting, else
+                    False)
+            as_result: Return a TraceResult wrapping the frame (default: False,
+                       so existing code that treats the result as a DataFrame
+                       keeps working)
+            
+        Returns:
+            DataFrame with traceability index, or a TraceResult with as_result=True
+        """
+        ...
+    
+    def configure_trace(
+        self,
+        *,
+        origin_attrs: Optional[list[str]] = None,
+        distance_metric: Optional[Literal["hops", "neglog_factor"]] = None,
+        stop_ids: Optional[list[str]] = None,
+        drop_zero_factor: bool = False,
+        min_factor: Optional[float] = None,
+        detail: bool = False,
+    ) -> None:
+        """Set default trace_segments options for this model.
+        
+        Later trace_segments calls use these unless they pass the option
+        themselves. Each call replaces all defaults; configure_trace() clears them.
+        
+        Every trace_segments option but progress_callback and as_result can be
+        set. There is no depth, path-count or direction limit to configure: tracing
+        always follows every path both ways and emits the identity row. Filter the
+        "direction" column to keep one direction or drop identity rows, and use
+        path_count to find pairs that would be slow to trace.
+        
+        Args:
+            origin_attrs: Default origin_attrs (see trace_segments)
+            distance_metric: Default distance_metric (see trace_segments)
+            stop_ids: Default stop_ids (see trace_segments); a call passing
+                      stop_ids=[] traces past them
+            drop_zero_factor: Default drop_zero_factor (see trace_segments)
+            min_factor: Default min_factor (see trace_segments); a call passing
+                        min_factor=0 follows every transfer
+            detail: Default detail (see trace_segments)
+        """
+        ...
+    
+    def clear_trace_cache(self) -> None:
+        """Drop all cached trace_segments results."""
+        ...
+    
+    def trace_iter(self, ids: list[str]) -> TraceIter:
+        """Trace origins one at a time, yielding one traceability frame per origin.
+        
+        Yields the same rows as trace_segments, including the configure_trace
+        defaults, without building one large frame, so long runs can report
+        progress and write results incrementally.
+        
+        Args:
+            ids: Origin segment ids
+            
+        Returns:
+            Iterator of per-origin traceability DataFrames
+        """
+        ...
+    
+    def trace_within(self, origin_ids: list[str], scope_ids: list[str]) -> pl.DataFrame:
+        """Trace origins without leaving a set of segments.
+        
+        Only transfers with both ends in the scope are followed, e.g. to get
+        the lineage within one site. Origins outside the scope yield just
+        their identity row. The configure_trace defaults apply; results are
+        not cached.
+        
+        Args:
+            origin_ids: Origin segment ids
+            scope_ids: Segment ids the trace may visit
+            
+        Returns:
+            DataFrame with traceability index
+        """
+        ...
+    
+    def path_count(self, source_segment_id: str, target_segment_id: str) -> int:
+        """Number of simple paths between two segments in the transfer graph.
+        
+        Tracing enumerates every path between an origin and each traced segment,
+        so a large count means that pair will be slow to trace. The count itself
+        is cheap (linear in the graph size) and saturates at 2**64 - 1.
+        
+        Args:
+            source_segment_id: Upstream segment id
+            target_segment_id: Downstream segment id
+            
+        Returns:
+            Path count (0 if either id is unknown, 1 if they are the same segment)
+        """
+        ...
+    
+    def lowest_common_ancestor(self, id_a: str, id_b: str) -> Optional[str]:
+        """Nearest segment that both segments descend from.
+        
+        Nearest means the fewest transfers back from id_a plus back from id_b;
+        ties go to the smallest segment id. Either segment is the answer if it
+        is an ancestor of the other.
+        
+        Args:
+            id_a: First segment id
+            id_b: Second segment id
+            
+        Returns:
+            Segment id of the common ancestor, or None if they share none
+        """
+        ...
+    
+    def transfer_context(self, source_id: str, dest_id: str) -> pl.DataFrame:
+        """Everything known about one transfer, for debugging it.
+        
+        Joins the transfer row(s) with both endpoint segments' attributes
+        (prefixed "source_" / "dest_") and, when containers are loaded, their
+        containers' attributes (prefixed "source_container_" / "dest_container_").
+        
+        Args:
+            source_id: Source segment id of the transfer
+            dest_id: Destination segment id of the transfer
+            
+        Returns:
+            DataFrame with the transfer's stock, factors and endpoint attributes;
+            empty if there is no such transfer
+        """
+        ...
+    
+    def explain_trace(self, origin_id: str, target_id: str) -> str:
+        """Describe the lineage between two segments as a multi-line narrative.
+        
+        The target may be downstream or upstream of the origin. The text starts
+        with the total shares over all paths, then lists each path and the shares
+        moved on each of its transfers, e.g.
+        "S3 received 60.0% (by count) and 55.0% (by biomass) of its fish from S1
+        via 2 paths."
+        
+        Args:
+            origin_id: Segment to explain from
+            target_id: Traced segment to explain
+            
+        Returns:
+            Multi-line string intended for non-technical readers
+        """
+        ...
+    
+    def edge_factors(self, source_id: str, dest_id: str) -> pl.DataFrame:
+        """The share factors the graph holds for one transfer edge.
+        
+        These are the values multiplied along paths when tracing, after factor
+        derivation and any merges, so they can be used to check hand calculations.
+        
+        Args:
+            source_id: Source segment id
+            dest_id: Destination segment id
+            
+        Returns:
+            One-row DataFrame with share_count_forward, share_biomass_forward,
+            share_count_backward and share_biomass_backward, or an empty frame
+            if there is no such edge
+        """
+        ...
+    
+    def biomass_flow_matrix(self, by: Literal["biomass", "count"] = "biomass") -> pl.DataFrame:
+        """Total stock moved between each pair of containers, as a wide matrix.
+        
+        Transfers are mapped to the containers of their source and dest segments
+        and the chosen stock is summed per container pair, e.g. as input for a
+        Sankey diagram. Transfers whose segments have no container are left out.
+        Null stock values count as 0; if all are null the call raises.
+        
+        Args:
+            by: Stock to sum: "biomass" (transfer_biomass_kg) or "count"
+                (transfer_count) (default: "biomass")
+            
+        Returns:
+            DataFrame with a source_container_id column and one column per dest
+            container, both sorted by id; pairs without transfers are 0
+            
+        Raises:
+            ValidationError: When the transfers were loaded without stock columns
+                             (create_missing_stock=False), have no non-null stock,
+                             or a dest container's id is "source_container_id"
+        """
+        ...
+    
+    def flow_into(self, container_id: str, start: datetime, end: datetime) -> pl.DataFrame:
+        """Total stock transferred into a container within a time window.
+        
+        Sums transfers whose dest segment is in the container and whose transfer
+        time is in start <= t < end. The transfer time is the source segment's
+        end_time, falling back to the dest segment's start_time, as in the
+        visualization.
+        
+        Args:
+            container_id: Receiving container
+            start: Window start, naive datetime (inclusive)
+            end: Window end, naive datetime (exclusive)
+            
+        Returns:
+            One-row DataFrame with transfer_count, transfer_biomass_kg and
+            n_transfers
+            
+        Raises:
+            ValidationError: When the transfers were loaded without stock columns
+                             (create_missing_stock=False)
+        """
+        ...
+    
+    def flow_out_of(self, container_id: str, start: datetime, end: datetime) -> pl.DataFrame:
+        """Total stock transferred out of a container within a time window.
+        
+        Same as flow_into, for transfers whose source segment is in the container.
+        
+        Args:
+            container_id: Sending container
+            start: Window start, naive datetime (inclusive)
+            end: Window end, naive datetime (exclusive)
+            
+        Returns:
+            One-row DataFrame with transfer_count, transfer_biomass_kg and
+            n_transfers
+            
+        Raises:
+            ValidationError: When the transfers were loaded without stock columns
+                             (create_missing_stock=False)
+        """
+        ...
+    
+    def transfers_with_time(self) -> pl.DataFrame:
+        """The loaded transfers with the time each one happened.
+        
+        transfer_time is the source segment's end_time, falling back to the
+        dest segment's start_time: the time the chart draws the transfer at and
+        flow_into / flow_out_of filter on. It is null when neither segment is
+        loaded.
+        
+        Returns:
+            Transfers DataFrame plus a transfer_time Datetime column
+        """
+        ...
+    
+    def trace_to_json(self, ids: list[str]) -> str:
+        """Trace origins and serialize the result as nested JSON.
+        
+        Produces one object per origin, in the order given:
+        {"origin_segment_id": ..., "traced": [{"segment_id", "direction",
+        "share_count_forward", "share_biomass_forward", "share_count_backward",
+        "share_biomass_backward"}, ...]}
+        
+        Origins are traced as in trace_segments with the configure_trace
+        defaults, so e.g. default stop_ids and min_factor apply.
+        
+        Args:
+            ids: Origin segment ids
+            
+        Returns:
+            JSON string (a list of origin objects)
+        """
+        ...
+    
+    def reachability_matrix(
+        self,
+        ids: list[str],
+        direction: Literal["forward", "backward", "both"] = "forward",
+    ) -> pl.DataFrame:
+        """Which segments each origin reaches, as a wide boolean frame.
+        
+        Only reachability is computed, so this is much cheaper than deriving
+        the same overview from trace_segments.
+        
+        Args:
+            ids: Origin segment ids; each becomes a column (repeats are
+                 dropped)
+            direction: "forward" (downstream, default), "backward" (upstream)
+                       or "both"
+            
+        Returns:
+            DataFrame with a traced_segment_id column (every segment reached by
+            any origin, origins included, sorted) and one Boolean column per
+            origin id, True where that origin reaches the row's segment
+            
+        Raises:
+            RuntimeError: If an id is "traced_segment_id", which would collide
+                with the row id column
+        """
+        ...
+    
+    def root_segments(self) -> pl.DataFrame:
+        """Segments with no incoming transfers (entry populations of the network).
+        
+        Returns:
+            DataFrame with a single segment_id column
+        """
+        ...
+    
+    def leaf_segments(self) -> pl.DataFrame:
+        """Segments with no outgoing transfers (exit populations of the network).
+        
+        Returns:
+            DataFrame with a single segment_id column
+        """
+        ...
+    
+    # ── Validation methods ──
+    
+    @staticmethod
+    def validate_trace_frame(df: pl.DataFrame) -> None:
+        """Check that a traceability frame can feed weighted aggregations.
+        
+        For traces built outside this library: requires a String "direction"
+        column holding only "forward", "backward" or "identity", and the four
+        share factor columns as Float64.
+        
+        Args:
+            df: Traceability frame to check
+            
+        Raises:
+            RuntimeError: Listing every missing column, wrong dtype and
+                          invalid direction value
+        """
+        ...
+    
+    @staticmethod
+    def expected_schema() -> dict[str, Any]:
+        """Describe the input files the loaders expect.
+        
+        Built from the same schema constants the loaders use, e.g. for onboarding
+        docs or checking uploads before loading them.
+        
+        Returns:
+            Dict keyed by file kind ("transfers", "segments", "containers",
+            "segment_timeseries", "container_timeseries"). Each entry has
+            default_filename, loader, columns (each with name, dtype, required,
+            derived and description), requires_one_of (column groups of which at
+            least one must be complete) and extra_columns_kept
+        """
+        ...
+    
+    def check_factor_reciprocity(self, tolerance: float = 1e-6) -> pl.DataFrame:
+        """Check that transfer share factors are consistent with each other and the stock.
+        
+        Rows with stock values must have factors matching the stock shares
+        (share_count_forward * source_total == transfer_count == share_count_backward * dest_total).
+        Forward factors must sum to 1 per source segment, backward factors per dest segment.
+        Transfers loaded with create_missing_stock=False only get the sum checks.
+        
+        Args:
+            tolerance: Allowed absolute deviation (default: 1e-6)
+            
+        Returns:
+            Offending transfer rows with a "reciprocity_issue" column naming the failed checks
+        """
+        ...
+    
+    def segments_with_gaps(self, min_gap_us: int = 0) -> pl.DataFrame:
+        """Adjacent segments within a container that leave a gap in the timeline.
+        
+        Segments are sorted per container by start_time and each consecutive
+        pair is compared. Segments without an end_time are not compared.
+        
+        Args:
+            min_gap_us: Only report gaps longer than this many microseconds (default: 0)
+            
+        Returns:
+            DataFrame with container_id, prev_segment_id, next_segment_id,
+            prev_end_time, next_start_time and gap_us
+        """
+        ...
+    
+    def validate_acyclic(self) -> Optional[pl.DataFrame]:
+        """Check that the loaded transfers form a DAG without building the tracer.
+        
+        Only the id columns are read, so this is a cheap gate to run before tracing.
+        
+        Returns:
+            None if there is no cycle, otherwise the transfer rows that lie on
+            a cycle (including self-loops)
+        """
+        ...
+    
+    # ── Derived columns ──
+    
+    def segment_lifespan(self, as_of: Optional[datetime] = None) -> pl.DataFrame:
+        """Segments with their lifespan (end_time - start_time) added.
+        
+        Adds "lifespan_us" (Int64 microseconds) and "lifespan_days" (Float64).
+        
+        Args:
+            as_of: Naive datetime used as end for segments without end_time
+                   (default: None, giving a null lifespan)
             
         Returns:
-            DataFrame with parsed int column
+            DataFrame with segments and lifespan columns
         """
         ...
     
-    # ── Tracing methods ──
-    
-    def trace_segments(self, origin_df: pl.DataFrame) -> pl.DataFrame:
-        """Trace segments from a DataFrame containing segment_id column.
+    def add_segment_column(
+        self,
+        name: str,
+        values: pl.DataFrame,
+        replace: bool = False,
+    ) -> pl.DataFrame:
+        """Attach a derived attribute (e.g. a risk score) to the loaded segments.
+        
+        The column is left-joined into the model's segments, so later calls such
+        as visualize_trace and transfer_context see it. Segments without a value
+        get null.
         
         Args:
-            origin_df: DataFrame with segment_id column
+            name: Column in values to add
+            values: DataFrame with segment_id and name, at most one row per segment
+            replace: Overwrite an existing column of the same name and dtype
+                     (default: False)
             
         Returns:
-            DataFrame with traceability index
+            The updated segments DataFrame
+            
+        Raises:
+            ValidationError: When values has segment_ids that are not loaded;
+                             rows holds those rows
         """
         ...
     
@@ -166,6 +980,8 @@ class SdtModel:
     def get_segments_active_at(self, timestamp: datetime) -> pl.DataFrame:
         """Get segments active at a specific timestamp.
         
+        Endpoints are handled per the model's interval_closed setting.
+        
         Args:
             timestamp: Naive datetime (no timezone info)
             
@@ -174,6 +990,27 @@ class SdtModel:
         """
         ...
     
+    def segments_active_histogram(
+        self,
+        freq: str,
+        start: Optional[datetime] = None,
+        end: Optional[datetime] = None,
+    ) -> pl.DataFrame:
+        """Number of active segments at regular ticks over a time range.
+        
+        A segment counts at a tick when the tick is within its interval per the
+        model's interval_closed setting, as in get_segments_active_at.
+        
+        Args:
+            freq: Step between ticks as a Polars duration, e.g. "1d", "6h", "1mo"
+            start: First tick, naive datetime (default: earliest segment start)
+            end: Last possible tick, inclusive (default: latest segment start or end)
+            
+        Returns:
+            DataFrame with date_time and active_count columns
+        """
+        ...
+    
     def get_segments_incoming(self) -> pl.DataFrame:
         """Get segments that have incoming transfers.
         
@@ -213,6 +1050,8 @@ class SdtModel:
         container_data: pl.DataFrame,
         include_unmatched: bool =False, 
         allow_multiple: bool = False,
+        time_col: Optional[str] = None,
+        end_inclusive: Optional[bool] = None,
     ) -> pl.DataFrame:
         """Map container-level timeseries to segments.
         
@@ -221,9 +1060,20 @@ class SdtModel:
         Args:
             container_data: DataFrame with container_id and date_time columns
             allow_multiple: Whether to allow multiple segment matches per input row
+            time_col: Datetime column to match against segment periods (default: "date_time")
+            end_inclusive: Override the model's interval_closed end bound for this
+                           call. True matches readings taken exactly at a segment's
+                           end_time (date_time <= end_time); a reading at a transfer
+                           then also matches the segment starting there
+                           (default: None, use interval_closed)
             
         Returns:
             DataFrame with container data mapped to segments
+            
+        Raises:
+            ValidationError: When allow_multiple=False and rows match several
+                             segments; rows holds those input rows with a
+                             match_count column
         """
         ...
     
@@ -234,13 +1084,71 @@ class SdtModel:
         traced_data: pl.DataFrame,
         aggregations: list[Aggregation],
         group_by: Optional[list[str]] = None,
+        identity_in_each_direction: bool = False,
+        progress_callback: Optional[Callable[[int, int], Any]] = None,
+        origin_normalize: bool = False,
+        nan_to_null: bool = True,
     ) -> pl.DataFrame:
         """Aggregate traced data using built-in Rust aggregations.
         
+        Weighted aggregations choose each row's weight from that row's direction,
+        so "direction" can be added to group_by for per-direction rollups. The
+        identity row (the origin, weight 1.0) then forms its own group unless
+        identity_in_each_direction is set.
+        
+        traced_data may come from anywhere, not only trace_segments: any frame
+        with "direction" and the share factor columns works. Only the factors
+        the aggregations weight by are required (the two count factors for
+        aggregate_by="count", the two biomass factors for "biomass"); they are
+        checked as in validate_trace_frame.
+        
         Args:
             traced_data: DataFrame with traced data
             aggregations: List of Aggregation objects
-            group_by: Column names to group by (default: ["origin_segment_id", "date_time"])
+            group_by: Column names to group by; an empty list aggregates the whole
+                      frame into one row. None (default) uses ["origin_segment_id",
+                      "date_time"], or ["origin_segment_id"] when the frame has no
+                      date_time column
+            identity_in_each_direction: When grouping by direction, count the identity
+                                        row in both the forward and backward groups
+            progress_callback: Called as callback(done, total) about every 1% of
+                               groups aggregated and on completion
+            origin_normalize: Divide the share factors of a traced segment reached
+                              from several origins by that number of origins, so
+                              pooled origins do not double-count it (default: False)
+            nan_to_null: Return NaN results (e.g. averages over empty or zero-weight
+                         groups) as null/None. Pass False to keep NaN (default: True)
+            
+        Returns:
+            Aggregated DataFrame
+        """
+        ...
+    
+    def trace_and_aggregate(
+        self,
+        origins: pl.DataFrame,
+        data: pl.DataFrame,
+        aggregations: list[Aggregation],
+        group_by: Optional[list[str]] = None,
+        identity_in_each_direction: bool = False,
+        origin_normalize: bool = False,
+        nan_to_null: bool = True,
+    ) -> pl.DataFrame:
+        """Trace, join segment data and aggregate in a single call.
+        
+        Equivalent to trace_segments -> add_data_to_trace -> aggregate_traced_data,
+        but the traced and joined intermediate frames are never returned to Python,
+        which lowers peak memory for large origin sets. The trace uses the defaults
+        set with configure_trace.
+        
+        Args:
+            origins: DataFrame with a "segment_id" column of origins to trace
+            data: Segment-level data with a "segment_id" column
+            aggregations: List of Aggregation objects
+            group_by: Column names to group by; default as in aggregate_traced_data
+            identity_in_each_direction: As in aggregate_traced_data
+            origin_normalize: As in aggregate_traced_data
+            nan_to_null: Return NaN results as null/None (default: True)
             
         Returns:
             Aggregated DataFrame
@@ -275,6 +1183,28 @@ class SdtModel:
     
     # ── Properties ──
     
+    @property
+    def interval_closed(self) -> Literal["left", "right", "both", "neither"]:
+        """Which segment interval endpoints count as active (see __init__)."""
+        ...
+    
+    @interval_closed.setter
+    def interval_closed(self, value: Literal["left", "right", "both", "neither"]) -> None: ...
+    
+    def derived_columns(self) -> list[str]:
+        """Transfer columns added by the loader rather than read from the file.
+        
+        These are the share factors calculated from stock and, for factor-only
+        files, the null stock columns. A factor column present in the file with
+        only some rows filled from stock counts as file-provided, as does a
+        column any file had after append_transfers.
+        
+        Returns:
+            Column names, e.g. to drop before re-exporting a canonical file:
+            model.transfers_df.drop(model.derived_columns())
+        """
+        ...
+    
     @property
     def transfers_df(self) -> Optional[pl.DataFrame]:
         """Get loaded transfers DataFrame."""
@@ -290,6 +1220,38 @@ class SdtModel:
         """Get loaded segments DataFrame."""
         ...
     
+    # ── Graph export ──
+    
+    def to_edge_frame(self, weight: str = "share_count_forward") -> pl.DataFrame:
+        """Transfers as an edge list for networkx.from_pandas_edgelist.
+        
+        Args:
+            weight: Transfer column copied into the "weight" column
+                    (default: "share_count_forward")
+            
+        Returns:
+            DataFrame with source, target, weight and the remaining transfer
+            columns as edge attributes
+            
+        Example:
+            G = nx.from_pandas_edgelist(model.to_edge_frame().to_pandas(),
+                                        edge_attr=True, create_using=nx.DiGraph)
+        """
+        ...
+    
+    def to_node_frame(self) -> pl.DataFrame:
+        """All segments in the transfer graph and segments table as a node list.
+        
+        Returns:
+            DataFrame with one row per segment_id and the segment columns as
+            node attributes (null for ids missing from the segments table)
+            
+        Example:
+            nodes = model.to_node_frame().to_pandas().set_index("segment_id")
+            G.add_nodes_from(nodes.to_dict("index").items())
+        """
+        ...
+    
     # ── Visualization ──
     
     def visualize_trace(
@@ -301,21 +1263,89 @@ class SdtModel:
         gap_px: int = 32,
         lane_height_px: int = 24,
         initial_zoom: float = 1.0,
+        show_gap_duration: bool = False,
+        gap_merge_tolerance_us: int = 0,
+        stripe_lanes: bool = True,
+        lane_sublabel_col: Optional[str] = None,
+        orientation: Literal["horizontal", "vertical"] = "horizontal",
+        missing_value_text: Optional[str] = None,
     ) -> str:
         """Visualize the trace as an interactive timeline chart.
         
         Returns a self-contained HTML string with SVG and JS.
         Use with IPython.display.HTML(model.visualize_trace(...)) in Jupyter.
+        Transfers whose segments are missing from the segments data cannot be
+        placed on the time axis; they are skipped and reported with a UserWarning
+        and a note in the chart header.
         
         Args:
             container_label_col: Column from containers df for y-axis labels (default: "container_id")
             segment_label_col: Column from segments df to display on rectangles (default: "segment_id")
             segment_tooltip_cols: Columns from segments df to show on hover (default: [])
             transfer_tooltip_cols: Columns from transfers df to show on transfer hover
-                                   (default: ["transfer_count", "transfer_biomass_kg"])
+                                   (default: ["transfer_count", "transfer_biomass_kg"], or the
+                                   forward share factors when the file has no stock values)
             gap_px: Pixel width of gap inserted at each transfer time (default: 32)
             lane_height_px: Pixel height per container lane (default: 24)
             initial_zoom: Initial zoom level (default: 1.0)
+            show_gap_duration: Label each transfer gap with the real time until
+                               the next event (default: False)
+            gap_merge_tolerance_us: Merge transfer times within this many
+                                    microseconds into a single gap, to declutter
+                                    charts with logging jitter (default: 0)
+            stripe_lanes: Alternate lane background shading (default: True)
+            lane_sublabel_col: Column from containers df shown in smaller text
+                               beneath each lane label (default: None)
+            orientation: "horizontal" (time left to right, one row per container)
+                         or "vertical" (time top to bottom, one column per
+                         container, for portrait displays) (default: "horizontal")
+            missing_value_text: Text shown for null and NaN values in tooltips,
+                                e.g. "—" (default: None, which leaves those
+                                fields out)
+            
+        Returns:
+            HTML string with embedded SVG and JavaScript
+        """
+        ...
+    
+    def visualize_trace_with(self, config: VisualizationConfig) -> str:
+        """Visualize the trace using a reusable VisualizationConfig.
+        
+        Args:
+            config: Visualization options (see VisualizationConfig)
+            
+        Returns:
+            HTML string with embedded SVG and JavaScript
+        """
+        ...
+    
+    def trace_layout(self, config: Optional[VisualizationConfig] = None) -> pl.DataFrame:
+        """Pixel position of each segment rectangle in the trace chart.
+        
+        Runs the chart's gap insertion and lane placement in Rust, so layouts can
+        be checked in tests or reused for server-side SVG without a browser.
+        Coordinates match visualize_trace_with(config) at its initial zoom, in
+        SVG pixels including the margins.
+        
+        Args:
+            config: Visualization options (default: VisualizationConfig())
+            
+        Returns:
+            DataFrame with segment_id, container_id, lane (0-based), x, y,
+            width and height. Segments whose container is not loaded are left
+            out, as in the chart.
+        """
+        ...
+    
+    def container_timeline(self, container_id: str, **viz_opts: Any) -> str:
+        """Render one container's segments as a single-lane strip.
+        
+        Transfers into or out of the container are drawn as short dashed stub
+        arrows. Much lighter than the full chart for quick tank reviews.
+        
+        Args:
+            container_id: Container to show
+            **viz_opts: VisualizationConfig arguments, e.g. gap_px=16
             
         Returns:
             HTML string with embedded SVG and JavaScript
@@ -323,9 +1353,114 @@ class SdtModel:
         ...
 
 
+class VisualizationConfig:
+    """Reusable configuration for SdtModel.visualize_trace_with.
+    
+    All constructor arguments are also readable and writable attributes.
+    """
+    
+    container_label_col: Optional[str]
+    segment_label_col: Optional[str]
+    segment_tooltip_cols: list[str]
+    transfer_tooltip_cols: Optional[list[str]]
+    gap_px: int
+    lane_height_px: int
+    initial_zoom: float
+    show_gap_duration: bool
+    gap_merge_tolerance_us: int
+    stripe_lanes: bool
+    lane_sublabel_col: Optional[str]
+    orientation: str
+    missing_value_text: Optional[str]
+    
+    def __init__(
+        self,
+        container_label_col: Optional[str] = None,
+        segment_label_col: Optional[str] = None,
+        segment_tooltip_cols: Optional[list[str]] = None,
+        transfer_tooltip_cols: Optional[list[str]] = None,
+        gap_px: int = 32,
+        lane_height_px: int = 24,
+        initial_zoom: float = 1.0,
+        show_gap_duration: bool = False,
+        gap_merge_tolerance_us: int = 0,
+        stripe_lanes: bool = True,
+        lane_sublabel_col: Optional[str] = None,
+        orientation: Literal["horizontal", "vertical"] = "horizontal",
+        missing_value_text: Optional[str] = None,
+    ) -> None:
+        """Create a visualization config. Arguments match SdtModel.visualize_trace."""
+        ...
+
+
+class ValidationError(RuntimeError):
+    """Validation failure carrying the data that caused it."""
+    
+    column: Optional[str]
+    """Offending column, or None when the failure concerns whole rows."""
+    rows: pl.DataFrame
+    """The offending rows."""
+
+
+class TraceIter(Iterator[pl.DataFrame]):
+    """Iterator over per-origin traceability frames (see SdtModel.trace_iter)."""
+    
+    def __iter__(self) -> TraceIter: ...
+    def __next__(self) -> pl.DataFrame: ...
+    def __len__(self) -> int:
+        """Number of origins not yet traced."""
+        ...
+
+
+class TraceResult:
+    """Traceability frame with shortcuts for the usual filters.
+    
+    Returned by trace_segments(as_result=True). Filters return a new
+    TraceResult, so they chain: result.forward().for_origin("S1").to_frame()
+    """
+    
+    def __init__(self, frame: pl.DataFrame) -> None:
+        """Wrap a traceability frame, e.g. one from trace_segments."""
+        ...
+    
+    def forward(self) -> TraceResult:
+        """Rows traced downstream of their origin."""
+        ...
+    
+    def backward(self) -> TraceResult:
+        """Rows traced upstream of their origin."""
+        ...
+    
+    def for_origin(self, origin_id: str) -> TraceResult:
+        """Rows traced from origin_id."""
+        ...
+    
+    def to_frame(self) -> pl.DataFrame:
+        """The underlying traceability frame."""
+        ...
+    
+    def __len__(self) -> int:
+        """Number of rows."""
+        ...
+
+
 class Aggregation:
     """Declarative aggregation specification for traced data."""
     
+    def with_dtype(self, dtype: str) -> Aggregation:
+        """Return a copy that builds its output column(s) with a fixed dtype.
+        
+        Without this the dtype is inferred from the group values, which can
+        flip when an early group is all-null or group ordering changes.
+        
+        Args:
+            dtype: One of "f64", "i64" or "str"
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
     @staticmethod
     def custom(callable: Callable[[pl.DataFrame], dict[str, Any]]) -> Aggregation:
         """Create custom aggregation using a Python callable.
@@ -338,6 +1473,42 @@ class Aggregation:
         """
         ...
     
+    @staticmethod
+    def custom_batched(callable: Callable[[pl.DataFrame], pl.DataFrame]) -> Aggregation:
+        """Create a custom aggregation called once for all groups.
+        
+        Avoids one Python call per group when the logic can be vectorized. The
+        callable receives every group stacked into one DataFrame with a
+        group_index column, and returns a DataFrame with group_index and one
+        column per output, e.g.
+        lambda df: df.group_by("group_index").agg(pl.col("weight").sum()).
+        Groups without a returned row get nulls. Not supported inside namespaced.
+        
+        Args:
+            callable: Function from the stacked groups to one row per group_index
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
+    @staticmethod
+    def expr(name: str, expression: str) -> Aggregation:
+        """Aggregation from a restricted Polars expression, evaluated natively per group.
+        
+        Supports col('x'), numeric literals, + - * /, parentheses and the reducers
+        sum, mean, min, max, median, count, first, last, std, var, n_unique.
+        No Python is called per group, so this is faster than `custom`.
+        
+        Args:
+            name: Output column name
+            expression: Expression string, e.g. "col('x').sum() / col('y').sum()"
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
     @staticmethod
     def min(column: str, alias: Optional[str] = None) -> Aggregation:
         """Minimum value aggregation.
@@ -364,6 +1535,76 @@ class Aggregation:
         """
         ...
     
+    @staticmethod
+    def argmin(value_column: str, label_column: str) -> Aggregation:
+        """Label of the row with the smallest value.
+        
+        Ties resolve to the first row. Null values are skipped.
+        
+        Args:
+            value_column: Column to find the minimum of
+            label_column: Column to read at the minimum row
+            
+        Returns:
+            Aggregation object (output column: "{value_column}_argmin")
+        """
+        ...
+    
+    @staticmethod
+    def argmax(value_column: str, label_column: str) -> Aggregation:
+        """Label of the row with the largest value.
+        
+        Ties resolve to the first row. Null values are skipped.
+        
+        Args:
+            value_column: Column to find the maximum of
+            label_column: Column to read at the maximum row
+            
+        Returns:
+            Aggregation object (output column: "{value_column}_argmax")
+        """
+        ...
+    
+    @staticmethod
+    def nth(column: str, index: int, alias: Optional[str] = None) -> Aggregation:
+        """Value of a column at a given row of each group, in group order.
+        
+        Output column: "{column}_nth_{index}" (or alias). The column's dtype
+        is kept.
+        
+        Args:
+            column: Column to take the value from
+            index: Row position; negative counts from the end (-1 is the last
+                   row, -2 the one before). Out-of-range positions give null
+            alias: Output column name
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
+    @staticmethod
+    def count_where(
+        column: str,
+        op: str,
+        value: str,
+        alias: Optional[str] = None,
+    ) -> Aggregation:
+        """Count rows in the group where `column <op> value`.
+        
+        Numeric columns compare numerically, others as strings. Nulls never match.
+        
+        Args:
+            column: Column to test
+            op: One of "eq", "ne", "gt", "lt", "ge", "le"
+            value: Value to compare against
+            alias: Output column name (default: "{column}_count_where")
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
     @staticmethod
     def sum(columns: list[str]) -> Aggregation:
         """Sum aggregation for multiple columns.
@@ -388,23 +1629,134 @@ class Aggregation:
         """
         ...
     
+    @staticmethod
+    def coverage(column: str) -> Aggregation:
+        """Fraction of rows in each group that have a value in a column.
+        
+        Output column: "{column}_coverage", computed as
+        (rows - null rows) / rows. NaN (null with nan_to_null) for an empty
+        group. Useful next to weighted metrics to show how complete the
+        measurements behind them are.
+        
+        Args:
+            column: Column to check for values
+            
+        Returns:
+            Aggregation object
+        """
+        ...
+    
+    @staticmethod
+    def namespaced(prefix: str, aggregations: list[Aggregation]) -> Aggregation:
+        """Group aggregations under a namespace prefix.
+        
+        Output columns of the grouped aggregations are named "{prefix}.{name}",
+        keeping related results together. Namespaces can be nested.
+        
+        Args:
+            prefix: Namespace prepended to each output column name
+            aggregations: Aggregations to group
+            
+        Returns:
+            Aggregation object
+            
+        Example:
+            Aggregation.namespaced("mortality", [Aggregation.sum(["dead"]),
+                                                 Aggregation.ratio("dead", "stocked")])
+            # -> "mortality.dead_sum", "mortality.dead_per_stocked"
+        """
+        ...
+    
+    @staticmethod
+    def histogram(
+        column: str,
+        bin_edges: list[float],
+        alias: Optional[str] = None,
+    ) -> Aggregation:
+        """Counts of column values per bin, as a compact string.
+        
+        Bins are half-open [edge, next_edge). Values below the first edge go to
+        the underflow bucket, values at or above the last edge to the overflow
+        bucket, and null/NaN values to the null bucket.
+        
+        Args:
+            column: Numeric column to bin
+            bin_edges: Strictly increasing bin edges, e.g. [0, 1, 2]
+            alias: Output column name (default: "{column}_histogram")
+            
+        Returns:
+            Aggregation object emitting e.g. "<0:0|0-1:3|1-2:5|2+:1|null:0"
+        """
+        ...
+    
+    @staticmethod
+    def ratio(
+        numerator: str,
+        denominator: str,
+        alias: Optional[str] = None,
+    ) -> Aggregation:
+        """Ratio of two summed columns, sum(numerator) / sum(denominator).
+        
+        Computed natively, so it avoids the overhead of a custom callable.
+        
+        Args:
+            numerator: Column summed for the numerator
+            denominator: Column summed for the denominator
+            alias: Output column name (default: "{numerator}_per_{denominator}")
+            
+        Returns:
+            Aggregation object. The result is NaN when the denominator sums to zero.
+        """
+        ...
+    
+    @staticmethod
+    def sum_product(
+        col_a: str,
+        col_b: str,
+        alias: Optional[str] = None,
+    ) -> Aggregation:
+        """Sum of the row-wise product of two columns, sum(col_a * col_b).
+        
+        E.g. concentration x volume. Unlike weighted_sum, both operands are
+        data columns rather than trace share factors. Computed natively.
+        
+        Args:
+            col_a: First column
+            col_b: Second column
+            alias: Output column name (default: "{col_a}_times_{col_b}")
+            
+        Returns:
+            Aggregation object. Rows where either value is null are skipped;
+            an empty group gives 0.
+        """
+        ...
+    
     @staticmethod
     def weighted_sum(
         columns: list[str],
-        aggregate_by: str,  # "count" or "biomass"
+        aggregate_by: Optional[str] = None,  # "count" or "biomass"
         include_calculation: bool = False,
+        normalize: bool = False,
+        exclude_identity: bool = False,
+        weight_column: Optional[str] = None,
     ) -> Aggregation:
         """Direction-aware weighted sum aggregation.
         
         Automatically selects the correct factor columns based on trace direction:
         - For forward traces: uses backward factors (how much came from origin)
         - For backward traces: uses forward factors (how much went to descendants)
-        - For identity: uses 1.0
+        - For identity: uses 1.0 (0.0 with exclude_identity)
         
         Args:
             columns: List of value columns to aggregate
             aggregate_by: Either "count" or "biomass" - determines which factor columns to use
             include_calculation: Whether to include calculation details
+            normalize: Divide the weighted total by the sum of weights and emit
+                       "{col}_weighted_share" instead of "{col}" (NaN for zero weight)
+            exclude_identity: Skip identity rows so the origin does not contribute
+                              to its own weighted statistic (default: False)
+            weight_column: Column in the traced frame to use directly as the weight,
+                           instead of aggregate_by (pass exactly one of the two)
             
         Returns:
             Aggregation object
@@ -417,18 +1769,28 @@ class Aggregation:
 
     @staticmethod
     def weighted_avg(
-        column: str, 
-        aggregate_by: str) -> Aggregation:
+        columns: str | list[str],
+        aggregate_by: Optional[str] = None,
+        exclude_identity: bool = False,
+        weight_column: Optional[str] = None,
+        include_weights: bool = False) -> Aggregation:
         """Direction-aware weighted average aggregation.
         
         Automatically selects the correct factor columns based on trace direction:
         - For forward traces: uses forward factors (proper weights for averaging)
         - For backward traces: uses backward factors (proper weights for averaging)
-        - For identity: uses 1.0
+        - For identity: uses 1.0 (0.0 with exclude_identity)
         
         Args:
-            column: Value column to aggregate
+            columns: Value column, or list of columns, to aggregate. Emits
+                     "{col}_weighted_avg" per column; weights are gathered once.
             aggregate_by: Either "count" or "biomass" - determines which factor columns to use
+            exclude_identity: Skip identity rows so the origin does not contribute
+                              to its own weighted statistic (default: False)
+            weight_column: Column in the traced frame to use directly as the weight,
+                           instead of aggregate_by (pass exactly one of the two)
+            include_weights: Also emit "{col}_weighted_value_sum" (numerator) and
+                             "{col}_weight_sum" (denominator) per column (default: False)
             
         Returns:
             Aggregation object
@@ -444,6 +1806,7 @@ class Aggregation:
         columns: list[str],
         separator: str = ", ",
         unique: bool = False,
+        skip_nulls: bool = True,
     ) -> Aggregation:
         """Concatenate column values.
         
@@ -451,6 +1814,7 @@ class Aggregation:
             columns: List of columns to concatenate
             separator: String separator (default: ", ")
             unique: Whether to deduplicate values (default: False)
+            skip_nulls: Omit null values; dedup happens after removal (default: True)
             
         Returns:
             Aggregation object
@@ -463,6 +1827,7 @@ class Aggregation:
         field_separator: str = ":",
         row_separator: str = ", ",
         alias: Optional[str] = None,
+        skip_nulls: bool = True,
     ) -> Aggregation:
         """Create contribution breakdown showing all contributing rows.
         
@@ -471,6 +1836,7 @@ class Aggregation:
             field_separator: Separator between fields within a row (default: ":")
             row_separator: Separator between rows (default: ", ")
             alias: Output column name (default: "contribution_breakdown")
+            skip_nulls: Omit rows with a null in any of the columns (default: True)
             
         Returns:
             Aggregation object
@@ -486,6 +1852,7 @@ class transfer:
     DEST_SEGMENT_ID: str
     TRANSFER_COUNT: str
     TRANSFER_BIOMASS_KG: str
+    TRANSFER_TIME: str
 
 class factors:
     """Trace factor column name constants."""
@@ -506,6 +1873,8 @@ class segment:
     CONTAINER_ID: str
     START_TIME: str
     END_TIME: str
+    LIFESPAN_US: str
+    LIFESPAN_DAYS: str
 
 class container:
     """Container column name constants."""
@@ -516,6 +1885,11 @@ class traceability:
     ORIGIN_SEGMENT_ID: str
     TRACED_SEGMENT_ID: str
     TRACE_DIRECTION: str
+    TRACE_DISTANCE: str
+    N_PATHS: str
+    MIN_PATH_FACTOR: str
+    MAX_PATH_FACTOR: str
+    SCHEMA_VERSION: int
 
 class timeseries:
     """Time series column name constants."""
@@ -46,6 +46,7 @@ class SdtSchema:
         DEST_SEGMENT_ID = _rust.transfer.DEST_SEGMENT_ID
         TRANSFER_COUNT = _rust.transfer.TRANSFER_COUNT
         TRANSFER_BIOMASS_KG = _rust.transfer.TRANSFER_BIOMASS_KG
+        TRANSFER_TIME = _rust.transfer.TRANSFER_TIME
         FACTORS = TraceFactors
 
     class Segment:
@@ -54,12 +55,19 @@ class SdtSchema:
         CONTAINER_ID = _rust.segment.CONTAINER_ID
         START_TIME = _rust.segment.START_TIME
         END_TIME = _rust.segment.END_TIME
+        LIFESPAN_US = _rust.segment.LIFESPAN_US
+        LIFESPAN_DAYS = _rust.segment.LIFESPAN_DAYS
 
     class TraceabilityIndex:
         """Traceability index column names."""
         ORIGIN_SEGMENT_ID = _rust.traceability.ORIGIN_SEGMENT_ID
         TRACED_SEGMENT_ID = _rust.traceability.TRACED_SEGMENT_ID
         TRACE_DIRECTION = _rust.traceability.TRACE_DIRECTION
+        TRACE_DISTANCE = _rust.traceability.TRACE_DISTANCE
+        N_PATHS = _rust.traceability.N_PATHS
+        MIN_PATH_FACTOR = _rust.traceability.MIN_PATH_FACTOR
+        MAX_PATH_FACTOR = _rust.traceability.MAX_PATH_FACTOR
+        SCHEMA_VERSION = _rust.traceability.SCHEMA_VERSION
         FACTORS = TraceFactors
 
     class TimeSeries:
@@ -1,16 +1,124 @@
 use crate::error::SdtError;
-use crate::schema::{factors, traceability};
+use crate::expr_parser::parse_expression;
+use crate::schema::{aggregate_by, direction, factors, traceability};
 use polars::prelude::*;
+use std::str::FromStr;
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use pyo3_polars::PyDataFrame;
 
 /// Aggregation dimension for direction-aware weighted operations.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum AggregateBy {
     Count,
     Biomass,
+    /// A column in the traced frame used directly as the weight, bypassing the
+    /// forward/backward factor selection
+    Column(String),
+}
+
+impl AggregateBy {
+    /// Accepted `aggregate_by` string for `AggregateBy::Count`
+    pub const COUNT: &'static str = aggregate_by::COUNT;
+    /// Accepted `aggregate_by` string for `AggregateBy::Biomass`
+    pub const BIOMASS: &'static str = aggregate_by::BIOMASS;
+
+    /// The (forward, backward) share factor columns weighted by, or `None` for a
+    /// weight column. Only these need to be in the traced frame.
+    pub fn factor_columns(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Count => Some((factors::SHARE_COUNT_FORWARD, factors::SHARE_COUNT_BACKWARD)),
+            Self::Biomass => Some((factors::SHARE_BIOMASS_FORWARD, factors::SHARE_BIOMASS_BACKWARD)),
+            Self::Column(_) => None,
+        }
+    }
+
+    /// Resolve the Python `aggregate_by` / `weight_column` arguments; exactly one must be set.
+    fn from_args(aggregate_by: Option<String>, weight_column: Option<String>) -> PyResult<Self> {
+        match (aggregate_by, weight_column) {
+            (None, Some(column)) => Ok(Self::Column(column)),
+            (Some(name), None) => name
+                .parse()
+                .map_err(|e: SdtError| PyValueError::new_err(e.to_string())),
+            _ => Err(PyValueError::new_err(
+                "Pass exactly one of aggregate_by or weight_column",
+            )),
+        }
+    }
+}
+
+impl FromStr for AggregateBy {
+    type Err = SdtError;
+
+    /// Parse an `aggregate_by` name (`schema::aggregate_by`). Weight columns are
+    /// not parsed from strings; use `AggregateBy::Column` directly.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            Self::COUNT => Ok(Self::Count),
+            Self::BIOMASS => Ok(Self::Biomass),
+            other => Err(SdtError::Validation(format!(
+                "Invalid aggregate_by: '{}'. Must be '{}' or '{}'",
+                other,
+                Self::COUNT,
+                Self::BIOMASS
+            ))),
+        }
+    }
+}
+
+/// Comparison used by `Aggregation.count_where`.
+#[derive(Debug, Clone, Copy)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+impl CompareOp {
+    fn parse(op: &str) -> Result<Self, SdtError> {
+        match op {
+            "eq" => Ok(Self::Eq),
+            "ne" => Ok(Self::Ne),
+            "gt" => Ok(Self::Gt),
+            "lt" => Ok(Self::Lt),
+            "ge" => Ok(Self::Ge),
+            "le" => Ok(Self::Le),
+            other => Err(SdtError::Validation(format!(
+                "Unknown comparison '{other}'. Use 'eq', 'ne', 'gt', 'lt', 'ge' or 'le'"
+            ))),
+        }
+    }
+
+    fn matches<T: PartialOrd + ?Sized>(self, lhs: &T, rhs: &T) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Gt => lhs > rhs,
+            Self::Lt => lhs < rhs,
+            Self::Ge => lhs >= rhs,
+            Self::Le => lhs <= rhs,
+        }
+    }
+}
+
+/// Column argument that accepts a single name or a list of names from Python.
+#[derive(FromPyObject)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(column) => vec![column],
+            OneOrMany::Many(columns) => columns,
+        }
+    }
 }
 
 /// Declarative aggregation specification.
@@ -20,6 +128,8 @@ pub enum AggregateBy {
 #[pyclass(name = "Aggregation")]
 pub struct Aggregation {
     pub(crate) kind: AggKind,
+    /// Declared dtype for the output column(s); inferred from the values when `None`
+    pub(crate) output_dtype: Option<DataType>,
 }
 
 impl Clone for AggKind {
@@ -29,6 +139,14 @@ impl Clone for AggKind {
                 let cloned = Python::with_gil(|py| callable.clone_ref(py));
                 Self::Custom { callable: cloned }
             }
+            Self::CustomBatched { callable } => {
+                let cloned = Python::with_gil(|py| callable.clone_ref(py));
+                Self::CustomBatched { callable: cloned }
+            }
+            Self::Expr { name, expr } => Self::Expr {
+                name: name.clone(),
+                expr: expr.clone(),
+            },
             Self::Min { column, alias } => Self::Min {
                 column: column.clone(),
                 alias: alias.clone(),
@@ -37,47 +155,130 @@ impl Clone for AggKind {
                 column: column.clone(),
                 alias: alias.clone(),
             },
+            Self::ArgMin {
+                value_column,
+                label_column,
+            } => Self::ArgMin {
+                value_column: value_column.clone(),
+                label_column: label_column.clone(),
+            },
+            Self::ArgMax {
+                value_column,
+                label_column,
+            } => Self::ArgMax {
+                value_column: value_column.clone(),
+                label_column: label_column.clone(),
+            },
+            Self::Nth {
+                column,
+                index,
+                alias,
+            } => Self::Nth {
+                column: column.clone(),
+                index: *index,
+                alias: alias.clone(),
+            },
+            Self::CountWhere {
+                column,
+                op,
+                value,
+                alias,
+            } => Self::CountWhere {
+                column: column.clone(),
+                op: *op,
+                value: value.clone(),
+                alias: alias.clone(),
+            },
             Self::Sum { columns } => Self::Sum {
                 columns: columns.clone(),
             },
             Self::Avg { columns } => Self::Avg {
                 columns: columns.clone(),
             },
+            Self::Coverage { column } => Self::Coverage {
+                column: column.clone(),
+            },
+            Self::Ratio {
+                numerator,
+                denominator,
+                alias,
+            } => Self::Ratio {
+                numerator: numerator.clone(),
+                denominator: denominator.clone(),
+                alias: alias.clone(),
+            },
+            Self::SumProduct {
+                col_a,
+                col_b,
+                alias,
+            } => Self::SumProduct {
+                col_a: col_a.clone(),
+                col_b: col_b.clone(),
+                alias: alias.clone(),
+            },
+            Self::Histogram {
+                column,
+                bin_edges,
+                alias,
+            } => Self::Histogram {
+                column: column.clone(),
+                bin_edges: bin_edges.clone(),
+                alias: alias.clone(),
+            },
+            Self::Namespaced {
+                prefix,
+                aggregations,
+            } => Self::Namespaced {
+                prefix: prefix.clone(),
+                aggregations: aggregations.clone(),
+            },
             Self::WeightedSum {
                 columns,
                 aggregate_by,
                 include_calculation,
+                normalize,
+                exclude_identity,
             } => Self::WeightedSum {
                 columns: columns.clone(),
-                aggregate_by: *aggregate_by,
+                aggregate_by: aggregate_by.clone(),
                 include_calculation: *include_calculation,
+                normalize: *normalize,
+                exclude_identity: *exclude_identity,
             },
             Self::WeightedAvg {
-                column,
+                columns,
                 aggregate_by,
+                exclude_identity,
+                include_weights,
             } => Self::WeightedAvg {
-                column: column.clone(),
-                aggregate_by: *aggregate_by,
+                columns: columns.clone(),
+                aggregate_by: aggregate_by.clone(),
+                exclude_identity: *exclude_identity,
+                include_weights: *include_weights,
             },
             Self::Concat {
                 columns,
                 separator,
                 unique,
+                skip_nulls,
             } => Self::Concat {
                 columns: columns.clone(),
                 separator: separator.clone(),
                 unique: *unique,
+                skip_nulls: *skip_nulls,
             },
             Self::ContributionBreakdown {
                 columns,
                 field_separator,
                 row_separator,
                 alias,
+                skip_nulls,
             } => Self::ContributionBreakdown {
                 columns: columns.clone(),
                 field_separator: field_separator.clone(),
                 row_separator: row_separator.clone(),
                 alias: alias.clone(),
+                skip_nulls: *skip_nulls,
             },
         }
     }
@@ -88,6 +289,14 @@ pub enum AggKind {
     Custom {
         callable: PyObject,
     },
+    /// Called once with all groups stacked, see `call_batched`
+    CustomBatched {
+        callable: PyObject,
+    },
+    Expr {
+        name: String,
+        expr: Expr,
+    },
     Min {
         column: String,
         alias: Option<String>,
@@ -96,147 +305,665 @@ pub enum AggKind {
         column: String,
         alias: Option<String>,
     },
+    ArgMin {
+        value_column: String,
+        label_column: String,
+    },
+    ArgMax {
+        value_column: String,
+        label_column: String,
+    },
+    Nth {
+        column: String,
+        index: i64, // negative counts from the end
+        alias: Option<String>,
+    },
+    CountWhere {
+        column: String,
+        op: CompareOp,
+        value: String,
+        alias: Option<String>,
+    },
     Sum {
         columns: Vec<String>,
     },
     Avg {
         columns: Vec<String>,
     },
+    Coverage {
+        column: String,
+    },
+    Ratio {
+        numerator: String,
+        denominator: String,
+        alias: Option<String>,
+    },
+    SumProduct {
+        col_a: String,
+        col_b: String,
+        alias: Option<String>,
+    },
+    Histogram {
+        column: String,
+        bin_edges: Vec<f64>, // strictly increasing
+        alias: Option<String>,
+    },
+    Namespaced {
+        prefix: String,
+        aggregations: Vec<Aggregation>, // outputs are named "{prefix}.{name}"
+    },
     WeightedSum {
         columns: Vec<String>,
         aggregate_by: AggregateBy,
         include_calculation: bool,
+        normalize: bool,
+        exclude_identity: bool, // identity rows get weight 0 instead of 1
     },
     WeightedAvg {
-        column: String,
+        columns: Vec<String>,
         aggregate_by: AggregateBy,
+        exclude_identity: bool,
+        include_weights: bool, // also emit the numerator and denominator sums
     },
     Concat {
         columns: Vec<String>,
         separator: String,
         unique: bool,
+        skip_nulls: bool,
     },
     ContributionBreakdown {
         columns: Vec<String>,
         field_separator: String, // between fields within a row, e.g. ":"
         row_separator: String,   // between rows, e.g. ", "
         alias: Option<String>,
+        skip_nulls: bool, // drop rows with a null in any of the columns
     },
 }
 
+impl Aggregation {
+    /// Construct an aggregation directly from Rust.
+    pub fn new(kind: AggKind) -> Self {
+        Self {
+            kind,
+            output_dtype: None,
+        }
+    }
+
+    /// Build the output column(s) with `dtype` instead of inferring it from the values.
+    pub fn with_output_dtype(mut self, dtype: DataType) -> Self {
+        self.output_dtype = Some(dtype);
+        self
+    }
+
+    /// Whether this aggregation weights rows by `direction` and the share factors.
+    pub fn uses_trace_factors(&self) -> bool {
+        match &self.kind {
+            AggKind::WeightedSum { .. } | AggKind::WeightedAvg { .. } => true,
+            AggKind::Namespaced { aggregations, .. } => {
+                aggregations.iter().any(Aggregation::uses_trace_factors)
+            }
+            _ => false,
+        }
+    }
+
+    /// Share factor columns this aggregation reads, e.g. only the two count factors
+    /// for a count-weighted sum.
+    pub fn factor_columns(&self) -> Vec<&'static str> {
+        match &self.kind {
+            AggKind::WeightedSum { aggregate_by, .. } | AggKind::WeightedAvg { aggregate_by, .. } => {
+                aggregate_by
+                    .factor_columns()
+                    .map_or_else(Vec::new, |(fwd, bwd)| vec![fwd, bwd])
+            }
+            AggKind::Namespaced { aggregations, .. } => aggregations
+                .iter()
+                .flat_map(Aggregation::factor_columns)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Check that `df` can feed weighted aggregations: a string `direction` column holding
+/// only forward/backward/identity, and the four share factor columns as Float64.
+///
+/// Any frame passing this works with the aggregation engine, whether it came from
+/// `DagTracer` or an external traceability index.
+pub fn validate_trace_frame(df: &DataFrame) -> Result<(), SdtError> {
+    validate_trace_frame_for(df, &factors::ALL)
+}
+
+/// `validate_trace_frame` requiring only the `factor_cols` share factor columns, e.g.
+/// the count factors for a count-only trace.
+pub fn validate_trace_frame_for(df: &DataFrame, factor_cols: &[&str]) -> Result<(), SdtError> {
+    let mut problems = Vec::new();
+    let mut missing = Vec::new();
+
+    match df.column(traceability::TRACE_DIRECTION) {
+        Err(_) => missing.push(traceability::TRACE_DIRECTION.to_string()),
+        Ok(c) if c.dtype() != &DataType::String => problems.push(format!(
+            "'{}' must be String, found {}",
+            traceability::TRACE_DIRECTION,
+            c.dtype()
+        )),
+        Ok(c) => {
+            let mut invalid: Vec<&str> = c
+                .str()?
+                .into_iter()
+                .flatten()
+                .filter(|v| ![direction::FORWARD, direction::BACKWARD, direction::IDENTITY].contains(v))
+                .collect();
+            invalid.sort_unstable();
+            invalid.dedup();
+            if !invalid.is_empty() {
+                problems.push(format!(
+                    "'{}' values must be '{}', '{}' or '{}', found: {}",
+                    traceability::TRACE_DIRECTION,
+                    direction::FORWARD,
+                    direction::BACKWARD,
+                    direction::IDENTITY,
+                    invalid.join(", ")
+                ));
+            }
+        }
+    }
+    for &name in factor_cols {
+        match df.column(name) {
+            Err(_) => missing.push(name.to_string()),
+            Ok(c) if c.dtype() != &DataType::Float64 => {
+                problems.push(format!("'{name}' must be Float64, found {}", c.dtype()))
+            }
+            Ok(_) => {}
+        }
+    }
+
+    if !missing.is_empty() {
+        problems.insert(0, format!("missing columns: {}", missing.join(", ")));
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(SdtError::Validation(format!(
+            "Not a valid trace frame: {}",
+            problems.join("; ")
+        )))
+    }
+}
+
 #[pymethods]
 impl Aggregation {
+    /// Return a copy that builds its output column(s) as `dtype` ("f64", "i64" or "str").
+    ///
+    /// Without this the dtype is inferred from the group values, which can flip when an
+    /// early group is all-null or group ordering changes.
+    fn with_dtype(&self, dtype: &str) -> PyResult<Self> {
+        let dtype = match dtype {
+            "f64" | "float" => DataType::Float64,
+            "i64" | "int" => DataType::Int64,
+            "str" | "string" => DataType::String,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown output dtype '{other}'. Use 'f64', 'i64' or 'str'"
+                )))
+            }
+        };
+        Ok(self.clone().with_output_dtype(dtype))
+    }
+
     #[staticmethod]
     fn custom(callable: PyObject) -> Self {
-        Self {
-            kind: AggKind::Custom { callable },
-        }
+        Self::new(AggKind::Custom { callable })
+    }
+
+    /// Custom aggregation called once for all groups instead of once per group.
+    ///
+    /// The callable receives every group stacked into one DataFrame with a
+    /// `group_index` column, and returns a DataFrame with `group_index` and one column
+    /// per output, e.g. `lambda df: df.group_by("group_index").agg(pl.col("x").sum())`.
+    #[staticmethod]
+    fn custom_batched(callable: PyObject) -> Self {
+        Self::new(AggKind::CustomBatched { callable })
+    }
+
+    /// Aggregation evaluated natively from a restricted Polars expression string,
+    /// e.g. `"col('mortality').sum() / col('count').sum()"`.
+    /// The expression is parsed here so syntax errors surface before aggregating.
+    #[staticmethod]
+    fn expr(name: String, expression: &str) -> PyResult<Self> {
+        let expr = parse_expression(expression)?;
+        Ok(Self::new(AggKind::Expr { name, expr }))
     }
 
     #[staticmethod]
     #[pyo3(signature = (column, alias=None))]
     fn min(column: String, alias: Option<String>) -> Self {
-        Self {
-            kind: AggKind::Min { column, alias },
-        }
+        Self::new(AggKind::Min { column, alias })
     }
 
     #[staticmethod]
     #[pyo3(signature = (column, alias=None))]
     fn max(column: String, alias: Option<String>) -> Self {
-        Self {
-            kind: AggKind::Max { column, alias },
-        }
+        Self::new(AggKind::Max { column, alias })
+    }
+
+    /// Value of `label_column` at the row with the smallest `value_column`.
+    /// Ties resolve to the first row.
+    #[staticmethod]
+    fn argmin(value_column: String, label_column: String) -> Self {
+        Self::new(AggKind::ArgMin {
+            value_column,
+            label_column,
+        })
+    }
+
+    /// Value of `label_column` at the row with the largest `value_column`.
+    /// Ties resolve to the first row.
+    #[staticmethod]
+    fn argmax(value_column: String, label_column: String) -> Self {
+        Self::new(AggKind::ArgMax {
+            value_column,
+            label_column,
+        })
+    }
+
+    /// Value of `column` at row `index` of the group, keeping the column's dtype.
+    /// Negative indices count from the end (-1 is the last row); out of range gives null.
+    #[staticmethod]
+    #[pyo3(signature = (column, index, alias=None))]
+    fn nth(column: String, index: i64, alias: Option<String>) -> Self {
+        Self::new(AggKind::Nth {
+            column,
+            index,
+            alias,
+        })
+    }
+
+    /// Number of rows in the group where `column <op> value`.
+    /// Numeric columns compare numerically, others as strings; nulls never match.
+    #[staticmethod]
+    #[pyo3(signature = (column, op, value, alias=None))]
+    fn count_where(column: String, op: &str, value: String, alias: Option<String>) -> PyResult<Self> {
+        let op = CompareOp::parse(op)?;
+        Ok(Self::new(AggKind::CountWhere {
+            column,
+            op,
+            value,
+            alias,
+        }))
     }
 
     #[staticmethod]
     fn sum(columns: Vec<String>) -> Self {
-        Self {
-            kind: AggKind::Sum { columns },
-        }
+        Self::new(AggKind::Sum { columns })
     }
 
     #[staticmethod]
     fn avg(columns: Vec<String>) -> Self {
-        Self {
-            kind: AggKind::Avg { columns },
+        Self::new(AggKind::Avg { columns })
+    }
+
+    /// Fraction of the group's rows with a non-null `column`, emitted as
+    /// `{column}_coverage`. NaN for an empty group.
+    #[staticmethod]
+    fn coverage(column: String) -> Self {
+        Self::new(AggKind::Coverage { column })
+    }
+
+    /// Group aggregations under `prefix`: their output columns are named
+    /// `{prefix}.{name}`. Namespaces can be nested.
+    #[staticmethod]
+    fn namespaced(prefix: String, aggregations: Vec<Aggregation>) -> Self {
+        Self::new(AggKind::Namespaced {
+            prefix,
+            aggregations,
+        })
+    }
+
+    /// Counts of `column` values per bin, emitted as `{column}_histogram`, a string like
+    /// `"<0:0|0-1:3|1-2:5|2+:1|null:0"`.
+    ///
+    /// Bins are half-open `[edge, next_edge)`; values below the first edge go to the
+    /// underflow bucket, values at or above the last edge to the overflow bucket.
+    #[staticmethod]
+    #[pyo3(signature = (column, bin_edges, alias=None))]
+    fn histogram(column: String, bin_edges: Vec<f64>, alias: Option<String>) -> PyResult<Self> {
+        if bin_edges.is_empty()
+            || bin_edges.iter().any(|e| !e.is_finite())
+            || bin_edges.windows(2).any(|w| w[0] >= w[1])
+        {
+            return Err(PyValueError::new_err(
+                "bin_edges must be a non-empty list of strictly increasing finite numbers",
+            ));
         }
+        Ok(Self::new(AggKind::Histogram {
+            column,
+            bin_edges,
+            alias,
+        }))
+    }
+
+    /// `sum(numerator) / sum(denominator)` per group, emitted as
+    /// `{numerator}_per_{denominator}` (NaN when the denominator sums to zero).
+    #[staticmethod]
+    #[pyo3(signature = (numerator, denominator, alias=None))]
+    fn ratio(numerator: String, denominator: String, alias: Option<String>) -> Self {
+        Self::new(AggKind::Ratio {
+            numerator,
+            denominator,
+            alias,
+        })
     }
 
+    /// `sum(col_a * col_b)` per group, emitted as `{col_a}_times_{col_b}`. Unlike
+    /// `weighted_sum` both operands are data columns; rows where either is null are
+    /// skipped, and an empty group sums to 0.
     #[staticmethod]
-    #[pyo3(signature = (columns, aggregate_by, include_calculation=false))]
+    #[pyo3(signature = (col_a, col_b, alias=None))]
+    fn sum_product(col_a: String, col_b: String, alias: Option<String>) -> Self {
+        Self::new(AggKind::SumProduct {
+            col_a,
+            col_b,
+            alias,
+        })
+    }
+
+    /// With `normalize=true` the weighted total is divided by the sum of weights and
+    /// emitted as `{col}_weighted_share` (NaN when the weights sum to zero).
+    /// `weight_column` names a column in the traced frame to use directly as the weight
+    /// instead of the `aggregate_by` share factors.
+    #[staticmethod]
+    #[pyo3(signature = (columns, aggregate_by=None, include_calculation=false, normalize=false, exclude_identity=false, weight_column=None))]
     fn weighted_sum(
         columns: Vec<String>,
-        aggregate_by: String,
+        aggregate_by: Option<String>,
         include_calculation: bool,
+        normalize: bool,
+        exclude_identity: bool,
+        weight_column: Option<String>,
     ) -> PyResult<Self> {
-        let agg_by = match aggregate_by.as_str() {
-            "count" => AggregateBy::Count,
-            "biomass" => AggregateBy::Biomass,
-            _ => {
-                return Err(PyValueError::new_err(format!(
-                    "Invalid aggregate_by: '{}'. Must be 'count' or 'biomass'",
-                    aggregate_by
-                )))
-            }
-        };
-        Ok(Self {
-            kind: AggKind::WeightedSum {
-                columns,
-                aggregate_by: agg_by,
-                include_calculation,
-            },
-        })
+        let agg_by = AggregateBy::from_args(aggregate_by, weight_column)?;
+        Ok(Self::new(AggKind::WeightedSum {
+            columns,
+            aggregate_by: agg_by,
+            include_calculation,
+            normalize,
+            exclude_identity,
+        }))
     }
 
+    /// Weighted average of one or more value columns; the weights are gathered once
+    /// per group and shared by all columns.
+    /// With `include_weights=true` each column also emits `{col}_weighted_value_sum`
+    /// (numerator) and `{col}_weight_sum` (denominator).
     #[staticmethod]
-    fn weighted_avg(column: String, aggregate_by: String) -> PyResult<Self> {
-        let agg_by = match aggregate_by.as_str() {
-            "count" => AggregateBy::Count,
-            "biomass" => AggregateBy::Biomass,
-            _ => {
-                return Err(PyValueError::new_err(format!(
-                    "Invalid aggregate_by: '{}'. Must be 'count' or 'biomass'",
-                    aggregate_by
-                )))
-            }
-        };
-        Ok(Self {
-            kind: AggKind::WeightedAvg {
-                column,
-                aggregate_by: agg_by,
-            },
-        })
+    #[pyo3(signature = (columns, aggregate_by=None, exclude_identity=false, weight_column=None, include_weights=false))]
+    fn weighted_avg(
+        columns: OneOrMany,
+        aggregate_by: Option<String>,
+        exclude_identity: bool,
+        weight_column: Option<String>,
+        include_weights: bool,
+    ) -> PyResult<Self> {
+        let agg_by = AggregateBy::from_args(aggregate_by, weight_column)?;
+        Ok(Self::new(AggKind::WeightedAvg {
+            columns: columns.into(),
+            aggregate_by: agg_by,
+            exclude_identity,
+            include_weights,
+        }))
     }
 
     #[staticmethod]
-    #[pyo3(signature = (columns, separator=", ", unique=false))]
-    fn concat(columns: Vec<String>, separator: &str, unique: bool) -> Self {
-        Self {
-            kind: AggKind::Concat {
-                columns,
-                separator: separator.to_string(),
-                unique,
-            },
-        }
+    #[pyo3(signature = (columns, separator=", ", unique=false, skip_nulls=true))]
+    fn concat(columns: Vec<String>, separator: &str, unique: bool, skip_nulls: bool) -> Self {
+        Self::new(AggKind::Concat {
+            columns,
+            separator: separator.to_string(),
+            unique,
+            skip_nulls,
+        })
     }
 
     #[staticmethod]
-    #[pyo3(signature = (columns, field_separator=":", row_separator=", ", alias=None))]
+    #[pyo3(signature = (columns, field_separator=":", row_separator=", ", alias=None, skip_nulls=true))]
     fn contribution_breakdown(
         columns: Vec<String>,
         field_separator: &str,
         row_separator: &str,
         alias: Option<String>,
+        skip_nulls: bool,
     ) -> Self {
-        Self {
-            kind: AggKind::ContributionBreakdown {
-                columns,
-                field_separator: field_separator.to_string(),
-                row_separator: row_separator.to_string(),
-                alias,
-            },
+        Self::new(AggKind::ContributionBreakdown {
+            columns,
+            field_separator: field_separator.to_string(),
+            row_separator: row_separator.to_string(),
+            alias,
+            skip_nulls,
+        })
+    }
+}
+
+/// Group `df` by `group_cols` and apply the aggregations to each group.
+///
+/// Output has one row per group: the group key columns followed by the
+/// aggregation result columns. With no `group_cols` the whole frame is aggregated
+/// into one row. An empty input is returned unchanged.
+pub fn aggregate_groups(
+    df: &DataFrame,
+    aggregations: &[Aggregation],
+    group_cols: &[String],
+) -> Result<DataFrame, SdtError> {
+    aggregate_groups_with_progress(df, aggregations, group_cols, |_, _| {})
+}
+
+/// Same as `aggregate_groups`, calling `progress(done, total)` after each group.
+pub fn aggregate_groups_with_progress(
+    df: &DataFrame,
+    aggregations: &[Aggregation],
+    group_cols: &[String],
+    mut progress: impl FnMut(usize, usize),
+) -> Result<DataFrame, SdtError> {
+    // Partition into group DataFrames. Without group columns the whole frame is
+    // one group, giving a single grand-total row of aggregation columns.
+    let partitions = if group_cols.is_empty() && df.height() > 0 {
+        vec![df.clone()]
+    } else {
+        df.partition_by(group_cols, true)?
+    };
+
+    // Determine output column names from first group (or return empty)
+    if partitions.is_empty() {
+        return Ok(df.clone());
+    }
+
+    // custom_batched aggregations run once over all groups up front
+    let batched = aggregations
+        .iter()
+        .map(|agg| match &agg.kind {
+            AggKind::CustomBatched { callable } => call_batched(callable, &partitions).map(Some),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<_>, SdtError>>()?;
+
+    // Output names from the first group, each tagged with its aggregation's declared dtype
+    let mut agg_names: Vec<String> = Vec::new();
+    let mut agg_dtypes: Vec<Option<&DataType>> = Vec::new();
+    for (agg, batch) in aggregations.iter().zip(&batched) {
+        let outputs = match batch {
+            Some(outputs) => outputs
+                .iter()
+                .map(|(name, _)| (name.clone(), agg.output_dtype.as_ref()))
+                .collect(),
+            None => declared_outputs(&partitions[0], agg, None)?,
+        };
+        for (name, dtype) in outputs {
+            agg_names.push(name);
+            agg_dtypes.push(dtype);
+        }
+    }
+
+    // Build column vectors: group key columns + aggregation result columns
+    // Group keys: take first row of each partition
+    let mut key_columns: Vec<Vec<AnyValue>> = vec![vec![]; group_cols.len()];
+    let mut agg_columns: Vec<Vec<AnyValue>> = vec![vec![]; agg_names.len()];
+
+    for (done, partition) in partitions.iter().enumerate() {
+        // Extract group key values from first row
+        for (i, gc) in group_cols.iter().enumerate() {
+            let val = partition.column(gc)?.get(0)?;
+            key_columns[i].push(val.into_static());
+        }
+
+        // Apply aggregations
+        let mut i = 0;
+        for (agg, batch) in aggregations.iter().zip(&batched) {
+            let values: Vec<AnyValue<'static>> = match batch {
+                Some(outputs) => outputs.iter().map(|(_, values)| values[done].clone()).collect(),
+                None => apply_builtin_aggregations(partition, std::slice::from_ref(agg))?
+                    .into_iter()
+                    .map(|(_name, val)| val)
+                    .collect(),
+            };
+            for val in values {
+                agg_columns[i].push(val);
+                i += 1;
+            }
+        }
+        progress(done + 1, partitions.len());
+    }
+
+    // Build the output DataFrame
+    let mut columns: Vec<Column> = Vec::new();
+
+    for (i, gc) in group_cols.iter().enumerate() {
+        let series = Series::from_any_values(gc.into(), &key_columns[i], true)?;
+        columns.push(series.into());
+    }
+
+    for (i, name) in agg_names.iter().enumerate() {
+        let series = match agg_dtypes[i] {
+            Some(dtype) => typed_series(name, &agg_columns[i], dtype)?,
+            None => Series::from_any_values(name.into(), &agg_columns[i], true)?,
+        };
+        columns.push(series.into());
+    }
+
+    Ok(DataFrame::new(columns)?)
+}
+
+/// Build an aggregation output column as `dtype`. Values are converted one by one so
+/// a value that does not fit (e.g. a string under "f64") is an error, not a silent null.
+fn typed_series(name: &str, values: &[AnyValue], dtype: &DataType) -> Result<Series, SdtError> {
+    let converted: Vec<AnyValue> = values
+        .iter()
+        .map(|v| match v {
+            AnyValue::Null => Some(AnyValue::Null),
+            v => v.strict_cast(dtype),
+        }
+        .ok_or_else(|| {
+            SdtError::Validation(format!(
+                "Aggregation output '{name}' has value {v} that cannot be converted to {dtype}"
+            ))
+        }))
+        .collect::<Result<_, _>>()?;
+    Ok(Series::from_any_values_and_dtype(name.into(), &converted, dtype, true)?)
+}
+
+/// Column with each group's position that `custom_batched` callables receive and return.
+pub const GROUP_INDEX: &str = "group_index";
+
+/// Call a `custom_batched` callable once with all `partitions` stacked and tagged with
+/// their `GROUP_INDEX`. Returns each output column of the result with one value per
+/// partition; groups the callable returned no row for are null.
+fn call_batched(
+    callable: &PyObject,
+    partitions: &[DataFrame],
+) -> Result<Vec<(String, Vec<AnyValue<'static>>)>, SdtError> {
+    let tagged: Vec<LazyFrame> = partitions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| p.clone().lazy().with_column(lit(i as u32).alias(GROUP_INDEX)))
+        .collect();
+    let stacked = concat(tagged, UnionArgs::default())?.collect()?;
+    let result = Python::with_gil(|py| -> PyResult<DataFrame> {
+        let out = callable.call1(py, (PyDataFrame(stacked),))?;
+        let PyDataFrame(df) = out.extract(py).map_err(|_| {
+            PyValueError::new_err("custom_batched aggregation must return a DataFrame")
+        })?;
+        Ok(df)
+    })?;
+
+    let index = result
+        .column(GROUP_INDEX)
+        .map_err(|_| {
+            SdtError::Validation(format!(
+                "custom_batched aggregation result has no '{GROUP_INDEX}' column"
+            ))
+        })?
+        .cast(&DataType::UInt32)?;
+    let rows: Vec<usize> = index
+        .u32()?
+        .into_iter()
+        .map(|i| match i {
+            Some(i) if (i as usize) < partitions.len() => Ok(i as usize),
+            other => Err(SdtError::Validation(format!(
+                "custom_batched aggregation returned invalid {GROUP_INDEX} {other:?}"
+            ))),
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut outputs = Vec::new();
+    for column in result.get_columns() {
+        if column.name() == GROUP_INDEX {
+            continue;
+        }
+        let mut values = vec![AnyValue::Null; partitions.len()];
+        for (row, &group) in rows.iter().enumerate() {
+            values[group] = column.get(row)?.into_static();
+        }
+        outputs.push((column.name().to_string(), values));
+    }
+    Ok(outputs)
+}
+
+/// The (forward, backward) factor columns of `group` that `aggregate_by` weights by,
+/// fetching only that pair; `None` for a weight column.
+fn factor_pair<'a>(
+    group: &'a DataFrame,
+    aggregate_by: &AggregateBy,
+) -> Result<Option<(&'a Float64Chunked, &'a Float64Chunked)>, SdtError> {
+    let Some((fwd, bwd)) = aggregate_by.factor_columns() else {
+        return Ok(None);
+    };
+    let get = |name: &str| group.column(name).and_then(|c| c.as_materialized_series().f64());
+    Ok(Some((get(fwd)?, get(bwd)?)))
+}
+
+/// Output names of `agg` on `group` with the dtype each was declared with. Aggregations
+/// inside a namespace inherit the namespace's dtype unless they declare their own.
+fn declared_outputs<'a>(
+    group: &DataFrame,
+    agg: &'a Aggregation,
+    inherited: Option<&'a DataType>,
+) -> Result<Vec<(String, Option<&'a DataType>)>, SdtError> {
+    let dtype = agg.output_dtype.as_ref().or(inherited);
+    match &agg.kind {
+        AggKind::Namespaced {
+            prefix,
+            aggregations,
+        } => {
+            let mut outputs = Vec::new();
+            for inner in aggregations {
+                for (name, inner_dtype) in declared_outputs(group, inner, dtype)? {
+                    outputs.push((format!("{prefix}.{name}"), inner_dtype));
+                }
+            }
+            Ok(outputs)
         }
+        _ => Ok(apply_builtin_aggregations(group, std::slice::from_ref(agg))?
+            .into_iter()
+            .map(|(name, _)| (name, dtype))
+            .collect()),
     }
 }
 
@@ -273,6 +1000,28 @@ pub fn apply_builtin_aggregations(
                 })
                 .map_err(SdtError::from)?;
             }
+            AggKind::CustomBatched { .. } => {
+                return Err(SdtError::Validation(
+                    "custom_batched aggregations are only supported at the top level, \
+                     not inside a namespace"
+                        .into(),
+                ));
+            }
+            AggKind::Expr { name, expr } => {
+                let out = group
+                    .clone()
+                    .lazy()
+                    .select([expr.clone().alias(name.as_str())])
+                    .collect()?;
+                if out.height() != 1 {
+                    return Err(SdtError::Validation(format!(
+                        "Expression aggregation '{name}' must reduce each group to one value, got {} rows",
+                        out.height()
+                    )));
+                }
+                let val = out.column(name)?.get(0)?.into_static();
+                results.push((name.clone(), val));
+            }
             AggKind::Min { column, alias } => {
                 let s = group.column(column)?.as_materialized_series();
                 let name = alias.clone().unwrap_or_else(|| format!("{column}_min"));
@@ -287,6 +1036,70 @@ pub fn apply_builtin_aggregations(
                 let f = val.value().try_extract::<f64>().unwrap_or(f64::NAN);
                 results.push((name, AnyValue::Float64(f)));
             }
+            AggKind::ArgMin {
+                value_column,
+                label_column,
+            } => {
+                let val = label_at_extreme(group, value_column, label_column, |v, best| v < best)?;
+                results.push((format!("{value_column}_argmin"), val));
+            }
+            AggKind::ArgMax {
+                value_column,
+                label_column,
+            } => {
+                let val = label_at_extreme(group, value_column, label_column, |v, best| v > best)?;
+                results.push((format!("{value_column}_argmax"), val));
+            }
+            AggKind::Nth {
+                column,
+                index,
+                alias,
+            } => {
+                let c = group.column(column)?;
+                let height = c.len() as i64;
+                let pos = if *index < 0 { height + index } else { *index };
+                let val = if (0..height).contains(&pos) {
+                    c.get(pos as usize)?.into_static()
+                } else {
+                    AnyValue::Null
+                };
+                let name = alias.clone().unwrap_or_else(|| format!("{column}_nth_{index}"));
+                results.push((name, val));
+            }
+            AggKind::CountWhere {
+                column,
+                op,
+                value,
+                alias,
+            } => {
+                let s = group.column(column)?.as_materialized_series();
+                let count = if s.dtype().is_primitive_numeric() {
+                    let target = value.parse::<f64>().map_err(|_| {
+                        SdtError::Validation(format!(
+                            "count_where value '{value}' is not a number, but '{column}' is numeric"
+                        ))
+                    })?;
+                    let values = s.cast(&DataType::Float64)?;
+                    values
+                        .f64()?
+                        .into_iter()
+                        .flatten()
+                        .filter(|v| op.matches(v, &target))
+                        .count()
+                } else {
+                    let values = s.cast(&DataType::String)?;
+                    values
+                        .str()?
+                        .into_iter()
+                        .flatten()
+                        .filter(|v| op.matches(*v, value.as_str()))
+                        .count()
+                };
+                let name = alias
+                    .clone()
+                    .unwrap_or_else(|| format!("{column}_count_where"));
+                results.push((name, AnyValue::Int64(count as i64)));
+            }
             AggKind::Sum { columns } => {
                 for col in columns {
                     let s = group.column(col)?.as_materialized_series();
@@ -303,10 +1116,88 @@ pub fn apply_builtin_aggregations(
                     results.push((format!("{col}_avg"), AnyValue::Float64(f)));
                 }
             }
+            AggKind::Coverage { column } => {
+                let c = group.column(column)?;
+                let f = if c.is_empty() {
+                    f64::NAN
+                } else {
+                    (c.len() - c.null_count()) as f64 / c.len() as f64
+                };
+                results.push((format!("{column}_coverage"), AnyValue::Float64(f)));
+            }
+            AggKind::Ratio {
+                numerator,
+                denominator,
+                alias,
+            } => {
+                let sum = |column: &str| -> Result<f64, SdtError> {
+                    let s = group.column(column)?.cast(&DataType::Float64)?;
+                    Ok(s.f64()?.sum().unwrap_or(0.0))
+                };
+                let (num, den) = (sum(numerator)?, sum(denominator)?);
+                let val = if den != 0.0 { num / den } else { f64::NAN };
+                let name = alias
+                    .clone()
+                    .unwrap_or_else(|| format!("{numerator}_per_{denominator}"));
+                results.push((name, AnyValue::Float64(val)));
+            }
+            AggKind::SumProduct {
+                col_a,
+                col_b,
+                alias,
+            } => {
+                let a = group.column(col_a)?.cast(&DataType::Float64)?;
+                let b = group.column(col_b)?.cast(&DataType::Float64)?;
+                let val = (a.f64()? * b.f64()?).sum().unwrap_or(0.0);
+                let name = alias.clone().unwrap_or_else(|| format!("{col_a}_times_{col_b}"));
+                results.push((name, AnyValue::Float64(val)));
+            }
+            AggKind::Namespaced {
+                prefix,
+                aggregations,
+            } => {
+                for (name, val) in apply_builtin_aggregations(group, aggregations)? {
+                    results.push((format!("{prefix}.{name}"), val));
+                }
+            }
+            AggKind::Histogram {
+                column,
+                bin_edges,
+                alias,
+            } => {
+                let s = group.column(column)?.cast(&DataType::Float64)?;
+                // [underflow, bins..., overflow]
+                let mut counts = vec![0usize; bin_edges.len() + 1];
+                let mut nulls = 0;
+                for v in s.f64()?.into_iter() {
+                    match v {
+                        Some(v) if !v.is_nan() => {
+                            counts[bin_edges.partition_point(|&e| e <= v)] += 1
+                        }
+                        _ => nulls += 1,
+                    }
+                }
+
+                let last = bin_edges.len() - 1;
+                let mut parts = Vec::with_capacity(counts.len() + 1);
+                parts.push(format!("<{}:{}", bin_edges[0], counts[0]));
+                for (i, w) in bin_edges.windows(2).enumerate() {
+                    parts.push(format!("{}-{}:{}", w[0], w[1], counts[i + 1]));
+                }
+                parts.push(format!("{}+:{}", bin_edges[last], counts[last + 1]));
+                parts.push(format!("null:{nulls}"));
+
+                let name = alias
+                    .clone()
+                    .unwrap_or_else(|| format!("{column}_histogram"));
+                results.push((name, AnyValue::StringOwned(parts.join("|").into())));
+            }
             AggKind::WeightedSum {
                 columns,
                 aggregate_by,
                 include_calculation: _,
+                normalize,
+                exclude_identity,
             } => {
                 // Direction-aware weighted sum
                 let direction_col = group
@@ -314,28 +1205,18 @@ pub fn apply_builtin_aggregations(
                     .as_materialized_series()
                     .str()?;
 
-                // Pre-fetch all factor columns
-                let count_fwd = group
-                    .column(factors::SHARE_COUNT_FORWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let count_bwd = group
-                    .column(factors::SHARE_COUNT_BACKWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let biomass_fwd = group
-                    .column(factors::SHARE_BIOMASS_FORWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let biomass_bwd = group
-                    .column(factors::SHARE_BIOMASS_BACKWARD)?
-                    .as_materialized_series()
-                    .f64()?;
+                let factor_pair = factor_pair(group, aggregate_by)?;
+                let weight_col = match aggregate_by {
+                    AggregateBy::Column(name) => Some(group.column(name)?.cast(&DataType::Float64)?),
+                    _ => None,
+                };
+                let weight_col = weight_col.as_ref().map(|c| c.f64()).transpose()?;
 
                 for col in columns {
                     let v = group.column(col)?.as_materialized_series().f64()?;
 
                     let mut total: f64 = 0.0;
+                    let mut total_weight: f64 = 0.0;
                     for i in 0..group.height() {
                         let dir = direction_col.get(i).ok_or_else(|| {
                             SdtError::General("Null direction in traced data".into())
@@ -345,14 +1226,14 @@ pub fn apply_builtin_aggregations(
                         // For WeightedSum (scale-then-sum):
                         // - forward direction uses backward factors
                         // - backward direction uses forward factors
-                        let weight = match (dir, aggregate_by) {
-                            ("forward", AggregateBy::Count) => count_bwd.get(i).unwrap_or(0.0),
-                            ("forward", AggregateBy::Biomass) => biomass_bwd.get(i).unwrap_or(0.0),
-                            ("backward", AggregateBy::Count) => count_fwd.get(i).unwrap_or(0.0),
-                            ("backward", AggregateBy::Biomass) => {
-                                biomass_fwd.get(i).unwrap_or(0.0)
+                        let weight = match (dir, factor_pair) {
+                            (direction::IDENTITY, _) if *exclude_identity => 0.0,
+                            (direction::FORWARD | direction::BACKWARD | direction::IDENTITY, None) => {
+                                weight_col.and_then(|w| w.get(i)).unwrap_or(0.0)
                             }
-                            ("identity", _) => 1.0,
+                            (direction::FORWARD, Some((_, bwd))) => bwd.get(i).unwrap_or(0.0),
+                            (direction::BACKWARD, Some((fwd, _))) => fwd.get(i).unwrap_or(0.0),
+                            (direction::IDENTITY, _) => 1.0,
                             _ => {
                                 return Err(SdtError::General(format!(
                                     "Unknown direction: {}",
@@ -362,14 +1243,26 @@ pub fn apply_builtin_aggregations(
                         };
 
                         total += value * weight;
+                        total_weight += weight;
                     }
 
-                    results.push((col.clone(), AnyValue::Float64(total)));
+                    if *normalize {
+                        let share = if total_weight > 0.0 {
+                            total / total_weight
+                        } else {
+                            f64::NAN
+                        };
+                        results.push((format!("{col}_weighted_share"), AnyValue::Float64(share)));
+                    } else {
+                        results.push((col.clone(), AnyValue::Float64(total)));
+                    }
                 }
             }
             AggKind::WeightedAvg {
-                column,
+                columns,
                 aggregate_by,
+                exclude_identity,
+                include_weights,
             } => {
                 // Direction-aware weighted average
                 let direction_col = group
@@ -377,69 +1270,77 @@ pub fn apply_builtin_aggregations(
                     .as_materialized_series()
                     .str()?;
 
-                // Pre-fetch all factor columns
-                let count_fwd = group
-                    .column(factors::SHARE_COUNT_FORWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let count_bwd = group
-                    .column(factors::SHARE_COUNT_BACKWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let biomass_fwd = group
-                    .column(factors::SHARE_BIOMASS_FORWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-                let biomass_bwd = group
-                    .column(factors::SHARE_BIOMASS_BACKWARD)?
-                    .as_materialized_series()
-                    .f64()?;
-
-                let v = group.column(column)?.as_materialized_series().f64()?;
-
-                let mut sum_vw: f64 = 0.0;
-                let mut sum_w: f64 = 0.0;
+                let factor_pair = factor_pair(group, aggregate_by)?;
+                let weight_col = match aggregate_by {
+                    AggregateBy::Column(name) => Some(group.column(name)?.cast(&DataType::Float64)?),
+                    _ => None,
+                };
+                let weight_col = weight_col.as_ref().map(|c| c.f64()).transpose()?;
 
+                let mut weights = Vec::with_capacity(group.height());
                 for i in 0..group.height() {
                     let dir = direction_col.get(i).ok_or_else(|| {
                         SdtError::General("Null direction in traced data".into())
                     })?;
-                    let value = v.get(i).unwrap_or(0.0);
 
                     // For WeightedAvg (true weighted average):
                     // - forward direction uses forward factors
                     // - backward direction uses backward factors
-                    let weight = match (dir, aggregate_by) {
-                        ("forward", AggregateBy::Count) => count_fwd.get(i).unwrap_or(0.0),
-                        ("forward", AggregateBy::Biomass) => biomass_fwd.get(i).unwrap_or(0.0),
-                        ("backward", AggregateBy::Count) => count_bwd.get(i).unwrap_or(0.0),
-                        ("backward", AggregateBy::Biomass) => biomass_bwd.get(i).unwrap_or(0.0),
-                        ("identity", _) => 1.0,
+                    let weight = match (dir, factor_pair) {
+                        (direction::IDENTITY, _) if *exclude_identity => 0.0,
+                        (direction::FORWARD | direction::BACKWARD | direction::IDENTITY, None) => {
+                            weight_col.and_then(|w| w.get(i)).unwrap_or(0.0)
+                        }
+                        (direction::FORWARD, Some((fwd, _))) => fwd.get(i).unwrap_or(0.0),
+                        (direction::BACKWARD, Some((_, bwd))) => bwd.get(i).unwrap_or(0.0),
+                        (direction::IDENTITY, _) => 1.0,
                         _ => {
                             return Err(SdtError::General(format!("Unknown direction: {}", dir)))
                         }
                     };
 
-                    sum_vw += value * weight;
-                    sum_w += weight;
+                    weights.push(weight);
                 }
+                let sum_w: f64 = weights.iter().sum();
 
-                let val = if sum_w > 0.0 {
-                    sum_vw / sum_w
-                } else {
-                    f64::NAN
-                };
+                for column in columns {
+                    let v = group.column(column)?.as_materialized_series().f64()?;
+                    let sum_vw: f64 = weights
+                        .iter()
+                        .enumerate()
+                        .map(|(i, w)| v.get(i).unwrap_or(0.0) * w)
+                        .sum();
+
+                    let val = if sum_w > 0.0 {
+                        sum_vw / sum_w
+                    } else {
+                        f64::NAN
+                    };
 
-                results.push((format!("{column}_weighted_avg"), AnyValue::Float64(val)));
+                    results.push((format!("{column}_weighted_avg"), AnyValue::Float64(val)));
+                    if *include_weights {
+                        results.push((
+                            format!("{column}_weighted_value_sum"),
+                            AnyValue::Float64(sum_vw),
+                        ));
+                        results.push((format!("{column}_weight_sum"), AnyValue::Float64(sum_w)));
+                    }
+                }
             }
             AggKind::Concat {
                 columns,
                 separator,
                 unique,
+                skip_nulls,
             } => {
                 for col in columns {
                     let s = group.column(col)?.as_materialized_series();
-                    let vals: Vec<String> = s.iter().map(|v| format!("{v}")).collect();
+                    // Nulls are dropped before dedup so they never claim a slot
+                    let vals: Vec<String> = s
+                        .iter()
+                        .filter(|v| !(*skip_nulls && v.is_null()))
+                        .map(|v| format!("{v}"))
+                        .collect();
                     let result = if *unique {
                         let mut seen = std::collections::HashSet::new();
                         vals.into_iter()
@@ -457,6 +1358,7 @@ pub fn apply_builtin_aggregations(
                 field_separator,
                 row_separator,
                 alias,
+                skip_nulls,
             } => {
                 let height = group.height();
                 let series: Vec<&Series> = columns
@@ -465,22 +1367,25 @@ pub fn apply_builtin_aggregations(
                     .collect::<Result<_, _>>()
                     .map_err(SdtError::from)?;
 
-                let parts: Vec<String> = (0..height)
-                    .map(|i| {
-                        series
-                            .iter()
-                            .map(|s| {
-                                let val = s.get(i).unwrap();
-                                match &val {
-                                    AnyValue::String(s) => s.to_string(),
-                                    AnyValue::StringOwned(s) => s.to_string(),
-                                    other => format!("{other}"),
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                            .join(field_separator)
-                    })
-                    .collect();
+                let mut parts: Vec<String> = Vec::with_capacity(height);
+                for i in 0..height {
+                    let row: Vec<AnyValue> = series
+                        .iter()
+                        .map(|s| s.get(i))
+                        .collect::<Result<_, _>>()?;
+                    if *skip_nulls && row.iter().any(AnyValue::is_null) {
+                        continue;
+                    }
+                    let fields: Vec<String> = row
+                        .iter()
+                        .map(|val| match val {
+                            AnyValue::String(s) => s.to_string(),
+                            AnyValue::StringOwned(s) => s.to_string(),
+                            other => format!("{other}"),
+                        })
+                        .collect();
+                    parts.push(fields.join(field_separator));
+                }
 
                 let name = alias
                     .clone()
@@ -494,4 +1399,190 @@ pub fn apply_builtin_aggregations(
     }
 
     Ok(results)
-}
\ No newline at end of file
+}
+
+/// Read `label_column` at the first row whose `value_column` beats all earlier rows
+/// according to `better`. Null values are skipped; an all-null group yields Null.
+fn label_at_extreme(
+    group: &DataFrame,
+    value_column: &str,
+    label_column: &str,
+    better: impl Fn(f64, f64) -> bool,
+) -> Result<AnyValue<'static>, SdtError> {
+    let values = group
+        .column(value_column)?
+        .as_materialized_series()
+        .cast(&DataType::Float64)?;
+    let values = values.f64()?;
+
+    let mut best: Option<(usize, f64)> = None;
+    for (i, v) in values.into_iter().enumerate() {
+        if let Some(v) = v {
+            if best.is_none_or(|(_, b)| better(v, b)) {
+                best = Some((i, v));
+            }
+        }
+    }
+
+    match best {
+        Some((idx, _)) => Ok(group.column(label_column)?.get(idx)?.into_static()),
+        None => Ok(AnyValue::Null),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_series_converts_compatible_values() {
+        let values = [AnyValue::Int64(2), AnyValue::Null, AnyValue::Float64(0.5)];
+        let s = typed_series("x", &values, &DataType::Float64).unwrap();
+        assert_eq!(s.dtype(), &DataType::Float64);
+        let got: Vec<Option<f64>> = s.f64().unwrap().into_iter().collect();
+        assert_eq!(got, vec![Some(2.0), None, Some(0.5)]);
+    }
+
+    #[test]
+    fn typed_series_rejects_unconvertible_values() {
+        let values = [AnyValue::Float64(1.0), AnyValue::String("abc")];
+        let err = typed_series("x", &values, &DataType::Float64).unwrap_err();
+        assert!(err.to_string().contains("'x'"), "{err}");
+    }
+
+    /// The single output of `kind` on `group`.
+    fn reduce(group: &DataFrame, kind: AggKind) -> AnyValue<'static> {
+        let mut results = apply_builtin_aggregations(group, &[Aggregation::new(kind)]).unwrap();
+        assert_eq!(results.len(), 1);
+        results.pop().unwrap().1
+    }
+
+    #[test]
+    fn histogram_bins_are_half_open_with_null_bucket() {
+        let group = df!("x" => [Some(-1.0), Some(0.0), Some(0.5), Some(1.0), Some(2.0), Some(5.0), None, Some(f64::NAN)])
+            .unwrap();
+        let kind = AggKind::Histogram {
+            column: "x".into(),
+            bin_edges: vec![0.0, 1.0, 2.0],
+            alias: None,
+        };
+        assert_eq!(
+            reduce(&group, kind),
+            AnyValue::StringOwned("<0:1|0-1:2|1-2:1|2+:2|null:2".into())
+        );
+    }
+
+    #[test]
+    fn argmin_and_argmax_resolve_ties_to_the_first_row() {
+        let group = df!(
+            "value" => [Some(3.0), Some(1.0), Some(1.0), None, Some(5.0), Some(5.0)],
+            "label" => ["a", "b", "c", "d", "e", "f"]
+        )
+        .unwrap();
+        let arg = |min: bool| {
+            let (value_column, label_column) = ("value".to_string(), "label".to_string());
+            reduce(
+                &group,
+                if min {
+                    AggKind::ArgMin {
+                        value_column,
+                        label_column,
+                    }
+                } else {
+                    AggKind::ArgMax {
+                        value_column,
+                        label_column,
+                    }
+                },
+            )
+        };
+        assert_eq!(arg(true), AnyValue::StringOwned("b".into()));
+        assert_eq!(arg(false), AnyValue::StringOwned("e".into()));
+    }
+
+    #[test]
+    fn count_where_compares_numbers_numerically_and_strings_lexically() {
+        let group = df!(
+            "n" => [Some(1i64), Some(2), Some(10), None],
+            "s" => [Some("1"), Some("2"), Some("10"), None]
+        )
+        .unwrap();
+        let count = |column: &str, op: CompareOp| {
+            reduce(
+                &group,
+                AggKind::CountWhere {
+                    column: column.into(),
+                    op,
+                    value: "2".into(),
+                    alias: None,
+                },
+            )
+        };
+        assert_eq!(count("n", CompareOp::Gt), AnyValue::Int64(1));
+        // "10" sorts before "2" as a string
+        assert_eq!(count("s", CompareOp::Gt), AnyValue::Int64(0));
+        assert_eq!(count("s", CompareOp::Lt), AnyValue::Int64(2));
+
+        let not_a_number = AggKind::CountWhere {
+            column: "n".into(),
+            op: CompareOp::Eq,
+            value: "abc".into(),
+            alias: None,
+        };
+        assert!(apply_builtin_aggregations(&group, &[Aggregation::new(not_a_number)]).is_err());
+    }
+
+    #[test]
+    fn ratio_of_zero_denominator_is_nan() {
+        let group = df!("num" => [1.0, 2.0], "den" => [2.0, 4.0], "zero" => [1.0, -1.0]).unwrap();
+        let ratio = |denominator: &str| {
+            let kind = AggKind::Ratio {
+                numerator: "num".into(),
+                denominator: denominator.into(),
+                alias: None,
+            };
+            reduce(&group, kind).extract::<f64>().unwrap()
+        };
+        assert_eq!(ratio("den"), 0.5);
+        assert!(ratio("zero").is_nan());
+    }
+
+    #[test]
+    fn nth_counts_negative_indices_from_the_end() {
+        let group = df!("x" => ["a", "b", "c"]).unwrap();
+        let nth = |index: i64| {
+            reduce(
+                &group,
+                AggKind::Nth {
+                    column: "x".into(),
+                    index,
+                    alias: None,
+                },
+            )
+        };
+        assert_eq!(nth(0), AnyValue::StringOwned("a".into()));
+        assert_eq!(nth(-1), AnyValue::StringOwned("c".into()));
+        assert_eq!(nth(3), AnyValue::Null);
+        assert_eq!(nth(-4), AnyValue::Null);
+    }
+
+    #[test]
+    fn sum_product_skips_rows_with_a_null() {
+        let group = df!(
+            "a" => [Some(1.0), None, Some(3.0)],
+            "b" => [Some(2.0), Some(5.0), None],
+            "none" => [None::<f64>, None, None]
+        )
+        .unwrap();
+        let sum_product = |col_b: &str| {
+            let kind = AggKind::SumProduct {
+                col_a: "a".into(),
+                col_b: col_b.into(),
+                alias: None,
+            };
+            reduce(&group, kind)
+        };
+        assert_eq!(sum_product("b"), AnyValue::Float64(2.0));
+        assert_eq!(sum_product("none"), AnyValue::Float64(0.0));
+    }
+}
@@ -1,11 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::algo::{dijkstra, is_cyclic_directed, tarjan_scc, toposort};
+use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
+use petgraph::visit::{EdgeFiltered, EdgeRef, Reversed};
 use petgraph::Direction;
 use polars::prelude::*;
 
 use crate::error::SdtError;
-use crate::schema::{factors, traceability, transfer};
+use crate::schema::{direction, factors, traceability, transfer};
 
 /// Edge payload: the four share/trace factors.
 #[derive(Debug, Clone)]
@@ -13,6 +15,68 @@ struct EdgeFactors {
     values: [f64; 4], // indexed same as factors::ALL
 }
 
+/// How far a trace follows the graph beyond plain reachability.
+#[derive(Default)]
+struct TraceLimits {
+    /// Nodes traced but not traversed past
+    stops: HashSet<NodeIndex>,
+    /// Edges whose share_count factor in the trace direction is below this are not followed
+    min_factor: Option<f64>,
+}
+
+impl TraceLimits {
+    /// Whether an edge is followed when tracing in `direction`: downstream
+    /// (`Outgoing`) checks `share_count_forward`, upstream `share_count_backward`.
+    fn follows(&self, edge: &EdgeFactors, direction: Direction) -> bool {
+        let factor = edge.values[count_factor_index(direction)];
+        self.min_factor.is_none_or(|min| factor >= min)
+    }
+}
+
+/// Index into `factors::ALL` of the share_count factor for tracing in `direction`.
+fn count_factor_index(direction: Direction) -> usize {
+    match direction {
+        Direction::Outgoing => 0,
+        Direction::Incoming => 2,
+    }
+}
+
+/// Factors summed over all simple paths between two segments, plus per-path statistics.
+struct PathTotals {
+    factors: [f64; 4],
+    n_paths: u64,
+    /// Smallest / largest single-path share_count product in the trace direction
+    min_path_factor: f64,
+    max_path_factor: f64,
+}
+
+/// Output columns of `trace_with_limits`, filled one row at a time.
+#[derive(Default)]
+struct TraceColumns {
+    origins: Vec<String>,
+    traced: Vec<String>,
+    directions: Vec<String>,
+    factors: [Vec<f64>; 4],
+    n_paths: Vec<u64>,
+    min_path_factor: Vec<Option<f64>>,
+    max_path_factor: Vec<Option<f64>>,
+}
+
+impl TraceColumns {
+    fn push(&mut self, origin_id: &str, traced_id: &str, dir: &str, totals: &PathTotals) {
+        self.origins.push(origin_id.to_string());
+        self.traced.push(traced_id.to_string());
+        self.directions.push(dir.to_string());
+        for (fv, f) in self.factors.iter_mut().zip(totals.factors) {
+            fv.push(f);
+        }
+        let any_path = totals.n_paths > 0;
+        self.n_paths.push(totals.n_paths);
+        self.min_path_factor.push(any_path.then_some(totals.min_path_factor));
+        self.max_path_factor.push(any_path.then_some(totals.max_path_factor));
+    }
+}
+
 /// Core directed-acyclic-graph tracer.
 ///
 /// Builds a petgraph DiGraph from a transfers DataFrame and exposes
@@ -23,30 +87,153 @@ pub struct DagTracer {
     node_map: HashMap<String, NodeIndex>,
 }
 
+/// Edge cost used for `trace_distance`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    /// Number of transfers on the shortest path
+    Hops,
+    /// Sum of `-ln(share_count_*)` along the path with the largest factor product, i.e.
+    /// `-ln` of the largest share of the origin reaching the traced segment on one path.
+    /// Edges with a zero factor are not followed.
+    NegLogFactor,
+}
+
+impl DistanceMetric {
+    pub fn parse(value: &str) -> Result<Self, SdtError> {
+        match value {
+            "hops" => Ok(Self::Hops),
+            "neglog_factor" => Ok(Self::NegLogFactor),
+            other => Err(SdtError::Validation(format!(
+                "Invalid distance_metric '{other}'. Must be 'hops' or 'neglog_factor'"
+            ))),
+        }
+    }
+
+    fn edge_cost(self, factor: f64) -> f64 {
+        match self {
+            Self::Hops => 1.0,
+            Self::NegLogFactor if factor > 0.0 => (-factor.ln()).max(0.0),
+            Self::NegLogFactor => f64::INFINITY,
+        }
+    }
+}
+
+/// Row indices of the transfers that lie on a cycle (empty for a DAG).
+///
+/// Only the id columns are read, so this is cheaper than building a `DagTracer`.
+/// An edge is on a cycle when both ends are in the same strongly connected
+/// component of more than one node, or when it is a self-loop.
+pub fn cyclic_transfer_rows(df: &DataFrame) -> Result<Vec<IdxSize>, SdtError> {
+    let source = df.column(transfer::SOURCE_SEGMENT_ID)?.str()?;
+    let dest = df.column(transfer::DEST_SEGMENT_ID)?.str()?;
+
+    let mut graph: DiGraph<(), ()> = DiGraph::new();
+    let mut nodes: HashMap<&str, NodeIndex> = HashMap::new();
+    let mut edges: Vec<Option<(NodeIndex, NodeIndex)>> = Vec::with_capacity(df.height());
+    for (src, dst) in source.iter().zip(dest.iter()) {
+        let (Some(src), Some(dst)) = (src, dst) else {
+            edges.push(None);
+            continue;
+        };
+        let a = *nodes.entry(src).or_insert_with(|| graph.add_node(()));
+        let b = *nodes.entry(dst).or_insert_with(|| graph.add_node(()));
+        graph.add_edge(a, b, ());
+        edges.push(Some((a, b)));
+    }
+
+    let mut component = vec![usize::MAX; graph.node_count()];
+    for (i, scc) in tarjan_scc(&graph).into_iter().enumerate() {
+        if scc.len() > 1 {
+            for n in scc {
+                component[n.index()] = i;
+            }
+        }
+    }
+
+    Ok(edges
+        .iter()
+        .enumerate()
+        .filter_map(|(row, edge)| {
+            let (a, b) = (*edge)?;
+            let on_cycle = a == b || (component[a.index()] != usize::MAX
+                && component[a.index()] == component[b.index()]);
+            on_cycle.then_some(row as IdxSize)
+        })
+        .collect())
+}
+
 impl DagTracer {
     /// Build the graph from a transfers DataFrame.
     ///
     /// Required columns: source_segment, dest_segment, and the four factor columns.
     pub fn from_transfers(df: &DataFrame) -> Result<Self, SdtError> {
+        let mut tracer = Self {
+            graph: DiGraph::new(),
+            node_map: HashMap::new(),
+        };
+        tracer.insert_transfers(df)?;
+        Ok(tracer)
+    }
+
+    /// Add transfer rows to an already built graph.
+    ///
+    /// New segment ids become new nodes; known ids reuse their node. If the rows would
+    /// introduce a cycle (or a row is invalid) the whole batch is rolled back and the
+    /// tracer is left as it was.
+    pub fn add_transfers(&mut self, df: &DataFrame) -> Result<(), SdtError> {
+        let node_count = self.graph.node_count();
+        let edge_count = self.graph.edge_count();
+
+        if let Err(e) = self.insert_transfers(df) {
+            self.truncate(node_count, edge_count);
+            return Err(e);
+        }
+        if is_cyclic_directed(&self.graph) {
+            self.truncate(node_count, edge_count);
+            return Err(SdtError::Validation(
+                "Appended transfers would introduce a cycle in the transfer graph".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Tracer induced by `ids`: only those segments and the transfers between them.
+    ///
+    /// Ids not in the graph are ignored. Tracing the result never leaves the set.
+    pub fn subgraph(&self, ids: &[String]) -> Self {
+        let mut sub = Self {
+            graph: DiGraph::new(),
+            node_map: HashMap::new(),
+        };
+        for id in ids {
+            if self.node_map.contains_key(id) {
+                sub.get_or_insert_node(id);
+            }
+        }
+        for edge in self.graph.edge_references() {
+            let src = &self.graph[edge.source()];
+            let dst = &self.graph[edge.target()];
+            if let (Some(&s), Some(&d)) = (sub.node_map.get(src), sub.node_map.get(dst)) {
+                sub.graph.add_edge(s, d, edge.weight().clone());
+            }
+        }
+        sub
+    }
+
+    /// Whether the transfer graph contains a cycle.
+    pub fn has_cycle(&self) -> bool {
+        is_cyclic_directed(&self.graph)
+    }
+
+    fn insert_transfers(&mut self, df: &DataFrame) -> Result<(), SdtError> {
         let source = df.column(transfer::SOURCE_SEGMENT_ID)?.str()?;
         let dest = df.column(transfer::DEST_SEGMENT_ID)?.str()?;
 
         let factor_series: Vec<&ChunkedArray<Float64Type>> = factors::ALL
             .iter()
-            .map(|name| df.column(name).and_then(|s| Ok(s.f64()?)))
+            .map(|name| df.column(name).and_then(|s| s.f64()))
             .collect::<Result<Vec<_>, _>>()?;
 
-        let mut graph = DiGraph::new();
-        let mut node_map: HashMap<String, NodeIndex> = HashMap::new();
-
-        let get_or_insert = |map: &mut HashMap<String, NodeIndex>,
-                                  g: &mut DiGraph<String, EdgeFactors>,
-                                  id: &str|
-         -> NodeIndex {
-            *map.entry(id.to_string())
-                .or_insert_with(|| g.add_node(id.to_string()))
-        };
-
         for i in 0..df.height() {
             let src = source.get(i).ok_or_else(|| {
                 SdtError::General(format!("Null source_segment at row {i}"))
@@ -60,112 +247,438 @@ impl DagTracer {
                 values[j] = fs.get(i).unwrap_or(0.0);
             }
 
-            let src_idx = get_or_insert(&mut node_map, &mut graph, src);
-            let dst_idx = get_or_insert(&mut node_map, &mut graph, dst);
-            graph.add_edge(src_idx, dst_idx, EdgeFactors { values });
+            let src_idx = self.get_or_insert_node(src);
+            let dst_idx = self.get_or_insert_node(dst);
+            self.graph.add_edge(src_idx, dst_idx, EdgeFactors { values });
         }
 
-        Ok(Self { graph, node_map })
+        Ok(())
+    }
+
+    /// Node of segment `id`, added on first sight. Ids repeat across many transfer
+    /// rows, so each is allocated once when interned rather than once per row.
+    fn get_or_insert_node(&mut self, id: &str) -> NodeIndex {
+        if let Some(&idx) = self.node_map.get(id) {
+            return idx;
+        }
+        let idx = self.graph.add_node(id.to_string());
+        self.node_map.insert(id.to_string(), idx);
+        idx
+    }
+
+    /// Drop nodes and edges added after the graph had `node_count` nodes and `edge_count` edges.
+    /// Removing the last index each time keeps all earlier indices stable.
+    fn truncate(&mut self, node_count: usize, edge_count: usize) {
+        while self.graph.edge_count() > edge_count {
+            let last = EdgeIndex::new(self.graph.edge_count() - 1);
+            self.graph.remove_edge(last);
+        }
+        while self.graph.node_count() > node_count {
+            let last = NodeIndex::new(self.graph.node_count() - 1);
+            if let Some(id) = self.graph.remove_node(last) {
+                self.node_map.remove(&id);
+            }
+        }
     }
 
     /// Trace all reachable segments from a set of origin segment ids.
     ///
-    /// Returns a DataFrame with columns:
-    ///   origin_segment, traced_segment, direction, + 4 factor columns
+    /// Returns a DataFrame with columns `traceability::ALL` followed by `factors::ALL`:
+    ///   origin_segment_id, traced_segment_id, direction, + 4 factor columns
+    /// These are the names `add_data_to_trace` and the aggregations join on; see
+    /// `traceability::SCHEMA_VERSION`.
     pub fn trace(&self, origin_ids: &[String]) -> Result<DataFrame, SdtError> {
-        let mut origins = Vec::new();
-        let mut traced = Vec::new();
-        let mut directions = Vec::new();
-        let mut factor_vecs: [Vec<f64>; 4] = [vec![], vec![], vec![], vec![]];
-
-        for origin_id in origin_ids {
-            self.trace_single(
-                origin_id,
-                &mut origins,
-                &mut traced,
-                &mut directions,
-                &mut factor_vecs,
-            );
-        }
-
-        let df = DataFrame::new(vec![
-            Column::new(traceability::ORIGIN_SEGMENT_ID.into(), &origins),
-            Column::new(traceability::TRACED_SEGMENT_ID.into(), &traced),
-            Column::new(traceability::TRACE_DIRECTION.into(), &directions),
-            Column::new(factors::ALL[0].into(), &factor_vecs[0]),
-            Column::new(factors::ALL[1].into(), &factor_vecs[1]),
-            Column::new(factors::ALL[2].into(), &factor_vecs[2]),
-            Column::new(factors::ALL[3].into(), &factor_vecs[3]),
-        ])?;
+        self.trace_with_progress(origin_ids, |_, _| {})
+    }
 
-        Ok(df)
+    /// Same as `trace`, calling `progress(done, total)` after each origin.
+    pub fn trace_with_progress(
+        &self,
+        origin_ids: &[String],
+        progress: impl FnMut(usize, usize),
+    ) -> Result<DataFrame, SdtError> {
+        self.trace_with_limits(origin_ids, &[], None, false, progress)
+    }
+
+    /// Same as `trace_with_progress`, but tracing does not go past the `stop_ids`
+    /// segments: they are traced themselves, but act as sinks downstream and as
+    /// sources upstream, and paths through them are not counted. Stops do not limit
+    /// tracing from an origin that is itself a stop.
+    ///
+    /// With `min_factor`, transfers moving less than that share are not followed:
+    /// `share_count_forward` is checked downstream and `share_count_backward`
+    /// upstream, so negligible contributions are pruned from the trace.
+    ///
+    /// With `detail`, `n_paths`, `min_path_factor` and `max_path_factor` are appended:
+    /// the number of simple paths behind each row and the smallest and largest
+    /// single-path share_count product in the trace direction. The identity row
+    /// counts as one path with factor 1.
+    pub fn trace_with_limits(
+        &self,
+        origin_ids: &[String],
+        stop_ids: &[String],
+        min_factor: Option<f64>,
+        detail: bool,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<DataFrame, SdtError> {
+        if origin_ids.is_empty() && !detail {
+            return Ok(DataFrame::empty_with_schema(&Self::trace_schema()));
+        }
+        let limits = self.trace_limits(stop_ids, min_factor);
+
+        let mut out = TraceColumns::default();
+        for (i, origin_id) in origin_ids.iter().enumerate() {
+            self.trace_single(origin_id, &limits, &mut out);
+            progress(i + 1, origin_ids.len());
+        }
+
+        let mut columns = vec![
+            Column::new(traceability::ALL[0].into(), &out.origins),
+            Column::new(traceability::ALL[1].into(), &out.traced),
+            Column::new(traceability::ALL[2].into(), &out.directions),
+            Column::new(factors::ALL[0].into(), &out.factors[0]),
+            Column::new(factors::ALL[1].into(), &out.factors[1]),
+            Column::new(factors::ALL[2].into(), &out.factors[2]),
+            Column::new(factors::ALL[3].into(), &out.factors[3]),
+        ];
+        if detail {
+            columns.extend([
+                Column::new(traceability::N_PATHS.into(), &out.n_paths),
+                Column::new(traceability::MIN_PATH_FACTOR.into(), &out.min_path_factor),
+                Column::new(traceability::MAX_PATH_FACTOR.into(), &out.max_path_factor),
+            ]);
+        }
+
+        Ok(DataFrame::new(columns)?)
+    }
+
+    /// Schema of every `trace` result, including the empty one.
+    pub fn trace_schema() -> Schema {
+        traceability::ALL
+            .iter()
+            .map(|name| Field::new((*name).into(), DataType::String))
+            .chain(
+                factors::ALL
+                    .iter()
+                    .map(|name| Field::new((*name).into(), DataType::Float64)),
+            )
+            .collect()
     }
 
     fn trace_single(
         &self,
         origin_id: &str,
-        origins: &mut Vec<String>,
-        traced: &mut Vec<String>,
-        directions: &mut Vec<String>,
-        factor_vecs: &mut [Vec<f64>; 4],
+        limits: &TraceLimits,
+        out: &mut TraceColumns,
     ) {
         // Identity row
-        origins.push(origin_id.to_string());
-        traced.push(origin_id.to_string());
-        directions.push("identity".to_string());
-        for fv in factor_vecs.iter_mut() {
-            fv.push(1.0);
-        }
+        let identity = PathTotals {
+            factors: [1.0; 4],
+            n_paths: 1,
+            min_path_factor: 1.0,
+            max_path_factor: 1.0,
+        };
+        out.push(origin_id, origin_id, direction::IDENTITY, &identity);
 
         let Some(&origin_idx) = self.node_map.get(origin_id) else {
             return; // not in graph — only identity row
         };
 
         // Forward: origin → descendants
-        let descendants = self.reachable(origin_idx, Direction::Outgoing);
+        let descendants = self.reachable(origin_idx, Direction::Outgoing, limits);
         for target_idx in &descendants {
-            let agg = self.aggregate_path_factors(origin_idx, *target_idx);
-            origins.push(origin_id.to_string());
-            traced.push(self.graph[*target_idx].clone());
-            directions.push("forward".to_string());
-            for (j, fv) in factor_vecs.iter_mut().enumerate() {
-                fv.push(agg[j]);
-            }
+            let agg =
+                self.aggregate_path_factors(origin_idx, *target_idx, Direction::Outgoing, limits);
+            out.push(origin_id, &self.graph[*target_idx], direction::FORWARD, &agg);
         }
 
         // Backward: ancestors → origin
-        let ancestors = self.reachable(origin_idx, Direction::Incoming);
+        let ancestors = self.reachable(origin_idx, Direction::Incoming, limits);
         for source_idx in &ancestors {
-            let agg = self.aggregate_path_factors(*source_idx, origin_idx);
-            origins.push(origin_id.to_string());
-            traced.push(self.graph[*source_idx].clone());
-            directions.push("backward".to_string());
-            for (j, fv) in factor_vecs.iter_mut().enumerate() {
-                fv.push(agg[j]);
+            let agg =
+                self.aggregate_path_factors(*source_idx, origin_idx, Direction::Incoming, limits);
+            out.push(origin_id, &self.graph[*source_idx], direction::BACKWARD, &agg);
+        }
+    }
+
+    /// Number of simple paths from `source` to `target` (segment ids).
+    ///
+    /// Counts in topological order (each segment's count is the sum over its
+    /// successors), so it is O(V+E) and a cheap way to spot origin/target pairs where
+    /// path enumeration would blow up. Counts saturate at `usize::MAX`; a cyclic graph
+    /// falls back to an exhaustive DFS over simple paths.
+    /// Unknown ids give 0; a segment has exactly one (empty) path to itself.
+    pub fn path_count(&self, source: &str, target: &str) -> usize {
+        let (Some(&source_idx), Some(&target_idx)) =
+            (self.node_map.get(source), self.node_map.get(target))
+        else {
+            return 0;
+        };
+        let Ok(order) = toposort(&self.graph, None) else {
+            let mut path = Vec::new();
+            return self.count_paths(source_idx, target_idx, &mut path);
+        };
+        let mut counts = vec![0usize; self.graph.node_count()];
+        counts[target_idx.index()] = 1;
+        for &node in order.iter().rev().filter(|&&n| n != target_idx) {
+            counts[node.index()] = self
+                .graph
+                .neighbors_directed(node, Direction::Outgoing)
+                .fold(0usize, |acc, n| acc.saturating_add(counts[n.index()]));
+        }
+        counts[source_idx.index()]
+    }
+
+    fn count_paths(&self, current: NodeIndex, target: NodeIndex, path: &mut Vec<NodeIndex>) -> usize {
+        if current == target {
+            return 1;
+        }
+        path.push(current);
+        let mut count = 0;
+        for neighbor in self.graph.neighbors_directed(current, Direction::Outgoing) {
+            if !path.contains(&neighbor) {
+                count = self.count_paths(neighbor, target, path).saturating_add(count);
+            }
+        }
+        path.pop();
+        count
+    }
+
+    /// All simple paths from `source` to `target` as lists of segment ids, both ends
+    /// included. Unknown ids give no paths; a segment has one path (itself) to itself.
+    pub fn paths(&self, source: &str, target: &str) -> Vec<Vec<String>> {
+        let (Some(&source_idx), Some(&target_idx)) =
+            (self.node_map.get(source), self.node_map.get(target))
+        else {
+            return Vec::new();
+        };
+        let mut paths = Vec::new();
+        let mut path = Vec::new();
+        self.collect_paths(source_idx, target_idx, &mut path, &mut paths);
+        paths
+    }
+
+    fn collect_paths(
+        &self,
+        current: NodeIndex,
+        target: NodeIndex,
+        path: &mut Vec<NodeIndex>,
+        paths: &mut Vec<Vec<String>>,
+    ) {
+        path.push(current);
+        if current == target {
+            paths.push(path.iter().map(|&n| self.graph[n].clone()).collect());
+        } else {
+            for neighbor in self.graph.neighbors_directed(current, Direction::Outgoing) {
+                if !path.contains(&neighbor) {
+                    self.collect_paths(neighbor, target, path, paths);
+                }
             }
         }
+        path.pop();
+    }
+
+    /// Add a `trace_distance` column to a trace result: the shortest-path distance from
+    /// the origin to each traced segment under `metric`, following transfers downstream
+    /// for forward rows and upstream for backward rows. Identity rows get 0.0; segments
+    /// only reachable through zero-factor edges get infinity.
+    ///
+    /// `stop_ids` and `min_factor` limit the paths as in `trace_with_limits`, so pass
+    /// the ones the trace was made with: distances never go past a stop (unless it is
+    /// the origin) or along a pruned transfer.
+    pub fn with_trace_distance(
+        &self,
+        trace: &DataFrame,
+        metric: DistanceMetric,
+        stop_ids: &[String],
+        min_factor: Option<f64>,
+    ) -> Result<DataFrame, SdtError> {
+        let limits = self.trace_limits(stop_ids, min_factor);
+        let origins = trace.column(traceability::ORIGIN_SEGMENT_ID)?.str()?;
+        let traced = trace.column(traceability::TRACED_SEGMENT_ID)?.str()?;
+        let directions = trace.column(traceability::TRACE_DIRECTION)?.str()?;
+
+        // One Dijkstra run per origin and direction
+        let mut runs: HashMap<(NodeIndex, bool), HashMap<NodeIndex, f64>> = HashMap::new();
+        let mut distances: Vec<Option<f64>> = Vec::with_capacity(trace.height());
+        for ((origin, target), dir) in origins.iter().zip(traced.iter()).zip(directions.iter()) {
+            let distance = match dir {
+                Some(direction::IDENTITY) => Some(0.0),
+                Some(dir @ (direction::FORWARD | direction::BACKWARD)) => {
+                    let nodes = origin
+                        .and_then(|o| self.node_map.get(o))
+                        .zip(target.and_then(|t| self.node_map.get(t)));
+                    nodes.map(|(&o, &t)| {
+                        let downstream = dir == direction::FORWARD;
+                        runs.entry((o, downstream))
+                            .or_insert_with(|| self.distances_from(o, downstream, metric, &limits))
+                            .get(&t)
+                            .copied()
+                            .unwrap_or(f64::INFINITY)
+                    })
+                }
+                _ => None,
+            };
+            distances.push(distance);
+        }
+
+        let mut df = trace.clone();
+        df.with_column(Column::new(traceability::TRACE_DISTANCE.into(), distances))?;
+        Ok(df)
+    }
+
+    /// Shortest distances from `start` to every reachable node, downstream using the
+    /// forward count factor or upstream using the backward count factor. Only edges
+    /// `limits` follows are used, and stops other than `start` are not expanded.
+    fn distances_from(
+        &self,
+        start: NodeIndex,
+        downstream: bool,
+        metric: DistanceMetric,
+        limits: &TraceLimits,
+    ) -> HashMap<NodeIndex, f64> {
+        let direction = if downstream { Direction::Outgoing } else { Direction::Incoming };
+        let followed = EdgeFiltered::from_fn(&self.graph, |e| {
+            let from = if downstream { e.source() } else { e.target() };
+            limits.follows(e.weight(), direction) && (from == start || !limits.stops.contains(&from))
+        });
+        if downstream {
+            dijkstra(&followed, start, None, |e| metric.edge_cost(e.weight().values[0]))
+        } else {
+            dijkstra(Reversed(&followed), start, None, |e| {
+                metric.edge_cost(e.weight().values[2])
+            })
+        }
+    }
+
+    /// `TraceLimits` for `stop_ids` (ids not in the graph are ignored) and `min_factor`.
+    fn trace_limits(&self, stop_ids: &[String], min_factor: Option<f64>) -> TraceLimits {
+        TraceLimits {
+            stops: stop_ids
+                .iter()
+                .filter_map(|id| self.node_map.get(id).copied())
+                .collect(),
+            min_factor,
+        }
+    }
+
+    /// The four factors (ordered as `factors::ALL`) stored on the `source` → `dest` edge,
+    /// as used when multiplying along paths. None if there is no such edge.
+    pub fn edge_factors(&self, source: &str, dest: &str) -> Option<[f64; 4]> {
+        let edge = self
+            .graph
+            .find_edge(*self.node_map.get(source)?, *self.node_map.get(dest)?)?;
+        Some(self.graph[edge].values)
+    }
+
+    /// The nearest segment both `a` and `b` descend from (either may be the ancestor
+    /// itself). Nearest means the fewest transfers back from `a` plus back from `b`;
+    /// ties go to the smallest segment id. None if they share no ancestor.
+    pub fn lowest_common_ancestor(&self, a: &str, b: &str) -> Option<String> {
+        let from_a = self.upstream_depths(*self.node_map.get(a)?);
+        let from_b = self.upstream_depths(*self.node_map.get(b)?);
+        from_a
+            .iter()
+            .filter_map(|(node, depth_a)| from_b.get(node).map(|depth_b| (depth_a + depth_b, *node)))
+            .min_by(|(d1, n1), (d2, n2)| d1.cmp(d2).then_with(|| self.graph[*n1].cmp(&self.graph[*n2])))
+            .map(|(_, node)| self.graph[node].clone())
+    }
+
+    /// Hop distance from `start` to each of its ancestors (and 0 to itself), by BFS.
+    fn upstream_depths(&self, start: NodeIndex) -> HashMap<NodeIndex, usize> {
+        let mut depths = HashMap::from([(start, 0)]);
+        let mut queue = std::collections::VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            let depth = depths[&node];
+            for parent in self.graph.neighbors_directed(node, Direction::Incoming) {
+                depths.entry(parent).or_insert_with(|| {
+                    queue.push_back(parent);
+                    depth + 1
+                });
+            }
+        }
+        depths
+    }
+
+    /// Segment ids with no incoming transfers (the entry populations of the network).
+    pub fn roots(&self) -> Vec<String> {
+        self.nodes_without_edges(Direction::Incoming)
+    }
+
+    /// Segment ids with no outgoing transfers (the exit populations of the network).
+    pub fn leaves(&self) -> Vec<String> {
+        self.nodes_without_edges(Direction::Outgoing)
+    }
+
+    /// Segment ids reachable from `origin_id` over transfers in `direction`
+    /// (`Outgoing` downstream, `Incoming` upstream), without computing any factors.
+    /// The origin itself is not included; unknown ids reach nothing.
+    pub fn reachable_ids(&self, origin_id: &str, direction: Direction) -> Vec<&str> {
+        let Some(&origin_idx) = self.node_map.get(origin_id) else {
+            return Vec::new();
+        };
+        self.reachable(origin_idx, direction, &TraceLimits::default())
+            .into_iter()
+            .map(|n| self.graph[n].as_str())
+            .collect()
     }
 
-    /// Find all nodes reachable from `start` following edges in `direction`.
-    fn reachable(&self, start: NodeIndex, direction: Direction) -> Vec<NodeIndex> {
+    fn nodes_without_edges(&self, direction: Direction) -> Vec<String> {
+        self.graph
+            .node_indices()
+            .filter(|&n| self.graph.neighbors_directed(n, direction).next().is_none())
+            .map(|n| self.graph[n].clone())
+            .collect()
+    }
+
+    /// Neighbors of `node` in `direction` over edges `limits` follows, by descending
+    /// segment id so that popping them off a stack visits them in ascending order.
+    fn sorted_neighbors(
+        &self,
+        node: NodeIndex,
+        direction: Direction,
+        limits: &TraceLimits,
+    ) -> Vec<NodeIndex> {
+        let mut neighbors: Vec<NodeIndex> = self
+            .graph
+            .edges_directed(node, direction)
+            .filter(|e| limits.follows(e.weight(), direction))
+            .map(|e| match direction {
+                Direction::Outgoing => e.target(),
+                Direction::Incoming => e.source(),
+            })
+            .collect();
+        neighbors.sort_unstable_by(|a, b| self.graph[*b].cmp(&self.graph[*a]));
+        neighbors.dedup();
+        neighbors
+    }
+
+    /// Find all nodes reachable from `start` following edges in `direction` that
+    /// `limits` allows. Stop nodes are included but not expanded. The order only
+    /// depends on the segment ids: neighbors are visited in id order.
+    fn reachable(
+        &self,
+        start: NodeIndex,
+        direction: Direction,
+        limits: &TraceLimits,
+    ) -> Vec<NodeIndex> {
         let mut result = Vec::new();
 
         // For outgoing we DFS on the graph as-is.
         // For incoming we DFS on the reversed graph.
         // petgraph Dfs only goes forward, so we use neighbors_directed manually.
-        let mut stack = Vec::new();
+        let mut stack = self.sorted_neighbors(start, direction, limits);
         let mut visited = std::collections::HashSet::new();
 
-        for neighbor in self.graph.neighbors_directed(start, direction) {
-            stack.push(neighbor);
-        }
-
         while let Some(node) = stack.pop() {
             if !visited.insert(node) {
                 continue;
             }
             result.push(node);
-            for neighbor in self.graph.neighbors_directed(node, direction) {
+            if limits.stops.contains(&node) {
+                continue;
+            }
+            for neighbor in self.sorted_neighbors(node, direction, limits) {
                 if !visited.contains(&neighbor) {
                     stack.push(neighbor);
                 }
@@ -179,20 +692,36 @@ impl DagTracer {
     ///
     /// For each path, factors are multiplied along edges.
     /// Across paths, factors are summed (same logic as the Python version).
-    fn aggregate_path_factors(&self, source: NodeIndex, target: NodeIndex) -> [f64; 4] {
-        let mut totals = [0.0f64; 4];
+    /// Paths passing through a stop node or an edge `limits` does not follow when
+    /// tracing in `direction` are skipped.
+    fn aggregate_path_factors(
+        &self,
+        source: NodeIndex,
+        target: NodeIndex,
+        direction: Direction,
+        limits: &TraceLimits,
+    ) -> PathTotals {
+        let mut totals = PathTotals {
+            factors: [0.0; 4],
+            n_paths: 0,
+            min_path_factor: f64::INFINITY,
+            max_path_factor: f64::NEG_INFINITY,
+        };
         let mut path = Vec::new();
-        self.enumerate_paths(source, target, &mut path, &mut totals);
+        self.enumerate_paths(source, target, direction, limits, &mut path, &mut totals);
         totals
     }
 
-    /// Recursive DFS enumeration of all simple paths, accumulating factor products.
+    /// Recursive DFS enumeration of all simple paths, accumulating factor products
+    /// and the path count and range.
     fn enumerate_paths(
         &self,
         current: NodeIndex,
         target: NodeIndex,
+        direction: Direction,
+        limits: &TraceLimits,
         path: &mut Vec<NodeIndex>,
-        totals: &mut [f64; 4],
+        totals: &mut PathTotals,
     ) {
         path.push(current);
 
@@ -205,17 +734,22 @@ impl DagTracer {
                     .find_edge(window[0], window[1])
                     .expect("edge must exist on path");
                 let factors = &self.graph[edge_idx];
-                for i in 0..4 {
-                    product[i] *= factors.values[i];
+                for (p, f) in product.iter_mut().zip(factors.values.iter()) {
+                    *p *= f;
                 }
             }
-            for i in 0..4 {
-                totals[i] += product[i];
+            for (t, p) in totals.factors.iter_mut().zip(product.iter()) {
+                *t += p;
             }
-        } else {
-            for neighbor in self.graph.neighbors_directed(current, Direction::Outgoing) {
-                if !path.contains(&neighbor) {
-                    self.enumerate_paths(neighbor, target, path, totals);
+            let path_factor = product[count_factor_index(direction)];
+            totals.n_paths += 1;
+            totals.min_path_factor = totals.min_path_factor.min(path_factor);
+            totals.max_path_factor = totals.max_path_factor.max(path_factor);
+        } else if path.len() == 1 || !limits.stops.contains(&current) {
+            for edge in self.graph.edges_directed(current, Direction::Outgoing) {
+                let neighbor = edge.target();
+                if limits.follows(edge.weight(), direction) && !path.contains(&neighbor) {
+                    self.enumerate_paths(neighbor, target, direction, limits, path, totals);
                 }
             }
         }
@@ -223,3 +757,236 @@ impl DagTracer {
         path.pop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tracer over `edges` with every share factor set to 1.0.
+    fn tracer(edges: &[(&str, &str)]) -> DagTracer {
+        let edges: Vec<(&str, &str, f64)> = edges.iter().map(|&(s, d)| (s, d, 1.0)).collect();
+        weighted_tracer(&edges)
+    }
+
+    /// Tracer over `(source, dest, factor)` edges with all four share factors set to `factor`.
+    fn weighted_tracer(edges: &[(&str, &str, f64)]) -> DagTracer {
+        let mut columns = vec![
+            Column::new(
+                transfer::SOURCE_SEGMENT_ID.into(),
+                edges.iter().map(|e| e.0).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                transfer::DEST_SEGMENT_ID.into(),
+                edges.iter().map(|e| e.1).collect::<Vec<_>>(),
+            ),
+        ];
+        for name in factors::ALL {
+            columns.push(Column::new(
+                name.into(),
+                edges.iter().map(|e| e.2).collect::<Vec<_>>(),
+            ));
+        }
+        DagTracer::from_transfers(&DataFrame::new(columns).unwrap()).unwrap()
+    }
+
+    /// Traced segment ids of `origin` in `direction`, sorted.
+    fn traced(trace: &DataFrame, origin: &str, dir: &str) -> Vec<String> {
+        let column = |name: &str| trace.column(name).unwrap().str().unwrap().clone();
+        let (origins, traced, dirs) = (
+            column(traceability::ORIGIN_SEGMENT_ID),
+            column(traceability::TRACED_SEGMENT_ID),
+            column(traceability::TRACE_DIRECTION),
+        );
+        let mut ids: Vec<String> = (0..trace.height())
+            .filter(|&i| origins.get(i) == Some(origin) && dirs.get(i) == Some(dir))
+            .map(|i| traced.get(i).unwrap().to_string())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// `n` diamonds in a row: each `s{i}` splits to `a{i}` and `b{i}`, which join at `s{i+1}`.
+    fn diamond_chain(n: usize) -> DagTracer {
+        let edges: Vec<(String, String)> = (0..n)
+            .flat_map(|i| {
+                let (s, next) = (format!("s{i}"), format!("s{}", i + 1));
+                let (a, b) = (format!("a{i}"), format!("b{i}"));
+                [
+                    (s.clone(), a.clone()),
+                    (s, b.clone()),
+                    (a, next.clone()),
+                    (b, next),
+                ]
+            })
+            .collect();
+        let edges: Vec<(&str, &str)> = edges
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        tracer(&edges)
+    }
+
+    #[test]
+    fn path_count_multiplies_through_diamonds() {
+        let t = diamond_chain(3);
+        assert_eq!(t.path_count("s0", "s3"), 8);
+        assert_eq!(t.path_count("s1", "s3"), 4);
+        assert_eq!(t.path_count("s3", "s0"), 0);
+        assert_eq!(t.path_count("s2", "s2"), 1);
+        assert_eq!(t.path_count("s0", "unknown"), 0);
+        assert_eq!(t.path_count("s0", "s3"), t.paths("s0", "s3").len());
+    }
+
+    #[test]
+    fn path_count_enumerates_simple_paths_on_a_cycle() {
+        let t = tracer(&[("A", "B"), ("B", "A"), ("A", "C"), ("B", "C")]);
+        assert!(t.has_cycle());
+        assert_eq!(t.path_count("A", "C"), 2);
+        assert_eq!(t.path_count("A", "C"), t.paths("A", "C").len());
+    }
+
+    #[test]
+    fn path_count_saturates_instead_of_overflowing() {
+        // 2^70 paths do not fit in a u64
+        assert_eq!(diamond_chain(70).path_count("s0", "s70"), usize::MAX);
+    }
+
+    #[test]
+    fn trace_distance_does_not_go_past_stops() {
+        // The short way to T runs through the stop S
+        let t = tracer(&[("A", "S"), ("S", "T"), ("A", "B"), ("B", "C"), ("C", "T")]);
+        let distance = |origin: &str, traced: &str, stops: &[String]| {
+            let trace = t.trace_with_limits(&[origin.to_string()], stops, None, false, |_, _| {});
+            let trace = t
+                .with_trace_distance(&trace.unwrap(), DistanceMetric::Hops, stops, None)
+                .unwrap();
+            let row = trace
+                .column(traceability::TRACED_SEGMENT_ID)
+                .unwrap()
+                .str()
+                .unwrap()
+                .iter()
+                .position(|id| id == Some(traced))
+                .unwrap();
+            let distances = trace.column(traceability::TRACE_DISTANCE).unwrap();
+            distances.f64().unwrap().get(row).unwrap()
+        };
+        let stops = ["S".to_string()];
+        assert_eq!(distance("A", "T", &[]), 2.0);
+        assert_eq!(distance("A", "T", &stops), 3.0);
+        assert_eq!(distance("T", "A", &stops), 3.0);
+        // An origin that is a stop is still traced from
+        assert_eq!(distance("S", "T", &stops), 1.0);
+    }
+
+    #[test]
+    fn stop_in_a_chain_is_traced_but_not_passed() {
+        let t = tracer(&[("A", "B"), ("B", "C"), ("C", "D")]);
+        let ids = ["A".to_string(), "D".to_string()];
+        let trace = t.trace_with_limits(&ids, &["B".to_string()], None, false, |_, _| {});
+        let trace = trace.unwrap();
+        assert_eq!(traced(&trace, "A", direction::FORWARD), ["B"]);
+        assert_eq!(traced(&trace, "D", direction::BACKWARD), ["B", "C"]);
+    }
+
+    #[test]
+    fn origin_that_is_a_stop_is_traced_from() {
+        // Paths from the stop origin S count, paths through it from A do not
+        let t = tracer(&[("A", "S"), ("S", "X"), ("S", "Y"), ("X", "T"), ("Y", "T")]);
+        let ids = ["S".to_string(), "A".to_string()];
+        let stops = ["S".to_string()];
+        let trace = t
+            .trace_with_limits(&ids, &stops, None, true, |_, _| {})
+            .unwrap();
+        assert_eq!(traced(&trace, "S", direction::FORWARD), ["T", "X", "Y"]);
+        assert_eq!(traced(&trace, "S", direction::BACKWARD), ["A"]);
+        assert_eq!(traced(&trace, "A", direction::FORWARD), ["S"]);
+
+        let n_paths = trace.column(traceability::N_PATHS).unwrap().u64().unwrap();
+        let to_t = trace
+            .column(traceability::TRACED_SEGMENT_ID)
+            .unwrap()
+            .str()
+            .unwrap()
+            .iter()
+            .position(|id| id == Some("T"))
+            .unwrap();
+        assert_eq!(n_paths.get(to_t), Some(2));
+    }
+
+    #[test]
+    fn min_factor_prunes_low_share_transfers() {
+        let t = weighted_tracer(&[("A", "B", 0.9), ("A", "C", 0.1), ("C", "D", 1.0)]);
+        let ids = ["A".to_string(), "D".to_string()];
+        let trace = t
+            .trace_with_limits(&ids, &[], Some(0.5), false, |_, _| {})
+            .unwrap();
+        assert_eq!(traced(&trace, "A", direction::FORWARD), ["B"]);
+        assert_eq!(traced(&trace, "D", direction::BACKWARD), ["C"]);
+
+        // The limit is inclusive
+        let trace = t
+            .trace_with_limits(&ids, &[], Some(0.1), false, |_, _| {})
+            .unwrap();
+        assert_eq!(traced(&trace, "A", direction::FORWARD), ["B", "C", "D"]);
+    }
+
+    #[test]
+    fn detail_reports_path_count_and_range_on_a_diamond() {
+        let t = weighted_tracer(&[
+            ("S", "A", 0.25),
+            ("S", "B", 0.75),
+            ("A", "T", 1.0),
+            ("B", "T", 1.0),
+        ]);
+        let trace = t.trace_with_limits(&["S".to_string()], &[], None, true, |_, _| {});
+        let trace = trace.unwrap();
+        let row = |id: &str| {
+            let traced = trace.column(traceability::TRACED_SEGMENT_ID).unwrap();
+            let i = traced
+                .str()
+                .unwrap()
+                .iter()
+                .position(|t| t == Some(id))
+                .unwrap();
+            let f64_at = |name: &str| trace.column(name).unwrap().f64().unwrap().get(i).unwrap();
+            (
+                trace
+                    .column(traceability::N_PATHS)
+                    .unwrap()
+                    .u64()
+                    .unwrap()
+                    .get(i)
+                    .unwrap(),
+                f64_at(traceability::MIN_PATH_FACTOR),
+                f64_at(traceability::MAX_PATH_FACTOR),
+                f64_at(factors::SHARE_COUNT_FORWARD),
+            )
+        };
+        assert_eq!(row("T"), (2, 0.25, 0.75, 1.0));
+        assert_eq!(row("A"), (1, 0.25, 0.25, 0.25));
+        // The identity row counts as one path with factor 1
+        assert_eq!(row("S"), (1, 1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn lowest_common_ancestor_prefers_nearest_then_smallest_id() {
+        // Q is added first, so the tie between P and Q is not settled by node order
+        let t = tracer(&[
+            ("Q", "A"),
+            ("Q", "B"),
+            ("P", "A"),
+            ("P", "B"),
+            ("R", "P"),
+            ("A", "C"),
+            ("X", "Y"),
+        ]);
+        assert_eq!(t.lowest_common_ancestor("A", "B").as_deref(), Some("P"));
+        // Either node can be the ancestor itself
+        assert_eq!(t.lowest_common_ancestor("A", "C").as_deref(), Some("A"));
+        assert_eq!(t.lowest_common_ancestor("C", "R").as_deref(), Some("R"));
+        assert_eq!(t.lowest_common_ancestor("B", "C").as_deref(), Some("P"));
+        assert_eq!(t.lowest_common_ancestor("C", "Y"), None);
+        assert_eq!(t.lowest_common_ancestor("C", "unknown"), None);
+    }
+}
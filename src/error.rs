@@ -1,7 +1,17 @@
+use polars::prelude::DataFrame;
+use pyo3::create_exception;
 use pyo3::exceptions::PyRuntimeError;
-use pyo3::PyErr;
+use pyo3::prelude::*;
+use pyo3_polars::PyDataFrame;
 use thiserror::Error;
 
+create_exception!(
+    _core,
+    ValidationError,
+    PyRuntimeError,
+    "Validation failure with the offending `column` (or None) and `rows`."
+);
+
 #[derive(Error, Debug)]
 pub enum SdtError {
     #[error("Data not loaded: {0}")]
@@ -22,16 +32,40 @@ pub enum SdtError {
     #[error("Missing column: {0}")]
     MissingColumn(String),
 
+    #[error("Missing columns: {} (found: {})", missing.join(", "), found.join(", "))]
+    MissingColumns { missing: Vec<String>, found: Vec<String> },
+
     #[error("Validation: {0}")]
     Validation(String),
 
+    /// Validation failure with the rows that caused it, raised as `ValidationError`.
+    #[error("Validation: {message}")]
+    InvalidRows {
+        message: String,
+        column: Option<String>,
+        rows: DataFrame,
+    },
+
     #[error("InvalidData: {0}")]
     InvalidData(String),
 }
 
 impl From<SdtError> for PyErr {
     fn from(err: SdtError) -> PyErr {
-        PyRuntimeError::new_err(err.to_string())
+        match err {
+            SdtError::InvalidRows { ref column, ref rows, .. } => Python::with_gil(|py| {
+                let py_err = ValidationError::new_err(err.to_string());
+                let value = py_err.value(py);
+                if let Err(e) = value
+                    .setattr("column", column.clone())
+                    .and_then(|_| value.setattr("rows", PyDataFrame(rows.clone())))
+                {
+                    return e;
+                }
+                py_err
+            }),
+            _ => PyRuntimeError::new_err(err.to_string()),
+        }
     }
 }
 
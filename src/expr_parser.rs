@@ -0,0 +1,276 @@
+//! Parser for the restricted Polars expression strings accepted by `Aggregation.expr`.
+//!
+//! Supported syntax:
+//! - `col('name')` / `col("name")` column references
+//! - numeric literals, optionally wrapped as `lit(1.5)`
+//! - reducers chained as methods: `.sum()`, `.mean()`, `.min()`, `.max()`,
+//!   `.median()`, `.count()`, `.first()`, `.last()`, `.std()`, `.var()`, `.n_unique()`
+//! - `+ - * /`, unary minus and parentheses
+//!
+//! Example: `col('mortality').sum() / col('count').sum()`
+
+use polars::prelude::*;
+
+use crate::error::SdtError;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(char),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, SdtError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let num = text.parse::<f64>().map_err(|_| {
+                SdtError::Validation(format!("Invalid number '{text}' in expression"))
+            })?;
+            tokens.push(Token::Num(num));
+        } else if c == '\'' || c == '"' {
+            let start = i + 1;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(SdtError::Validation(
+                    "Unterminated string in expression".into(),
+                ));
+            }
+            tokens.push(Token::Str(chars[start..i].iter().collect()));
+            i += 1;
+        } else if "+-*/().".contains(c) {
+            tokens.push(Token::Op(c));
+            i += 1;
+        } else {
+            return Err(SdtError::Validation(format!(
+                "Unexpected character '{c}' in expression"
+            )));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn expect_op(&mut self, op: char) -> Result<(), SdtError> {
+        match self.next() {
+            Some(Token::Op(c)) if c == op => Ok(()),
+            other => Err(SdtError::Validation(format!(
+                "Expected '{op}' in expression, found {other:?}"
+            ))),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, SdtError> {
+        let mut lhs = self.parse_term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.parse_term()?;
+            lhs = if op == '+' { lhs + rhs } else { lhs - rhs };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, SdtError> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.parse_unary()?;
+            // Cast to float so integer columns divide as ratios
+            lhs = if op == '*' {
+                lhs * rhs
+            } else {
+                lhs.cast(DataType::Float64) / rhs.cast(DataType::Float64)
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, SdtError> {
+        if let Some(Token::Op('-')) = self.peek() {
+            self.pos += 1;
+            let inner = self.parse_unary()?;
+            return Ok(lit(0.0) - inner);
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, SdtError> {
+        let mut expr = self.parse_primary()?;
+        while let Some(Token::Op('.')) = self.peek() {
+            self.pos += 1;
+            let method = match self.next() {
+                Some(Token::Ident(name)) => name,
+                other => {
+                    return Err(SdtError::Validation(format!(
+                        "Expected method name after '.', found {other:?}"
+                    )))
+                }
+            };
+            self.expect_op('(')?;
+            self.expect_op(')')?;
+            expr = match method.as_str() {
+                "sum" => expr.sum(),
+                "mean" => expr.mean(),
+                "min" => expr.min(),
+                "max" => expr.max(),
+                "median" => expr.median(),
+                "count" => expr.count(),
+                "first" => expr.first(),
+                "last" => expr.last(),
+                "std" => expr.std(1),
+                "var" => expr.var(1),
+                "n_unique" => expr.n_unique(),
+                other => {
+                    return Err(SdtError::Validation(format!(
+                        "Unsupported method '{other}' in expression"
+                    )))
+                }
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, SdtError> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(lit(n)),
+            Some(Token::Op('(')) => {
+                let inner = self.parse_expr()?;
+                self.expect_op(')')?;
+                Ok(inner)
+            }
+            Some(Token::Ident(name)) if name == "col" => {
+                self.expect_op('(')?;
+                let column = match self.next() {
+                    Some(Token::Str(s)) => s,
+                    other => {
+                        return Err(SdtError::Validation(format!(
+                            "col() expects a quoted column name, found {other:?}"
+                        )))
+                    }
+                };
+                self.expect_op(')')?;
+                Ok(col(column))
+            }
+            Some(Token::Ident(name)) if name == "lit" => {
+                self.expect_op('(')?;
+                let value = match self.next() {
+                    Some(Token::Num(n)) => lit(n),
+                    Some(Token::Str(s)) => lit(s),
+                    other => {
+                        return Err(SdtError::Validation(format!(
+                            "lit() expects a number or string, found {other:?}"
+                        )))
+                    }
+                };
+                self.expect_op(')')?;
+                Ok(value)
+            }
+            other => Err(SdtError::Validation(format!(
+                "Unexpected token in expression: {other:?}"
+            ))),
+        }
+    }
+}
+
+/// Parse an expression string into a Polars `Expr`.
+pub fn parse_expression(input: &str) -> Result<Expr, SdtError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_expr()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(SdtError::Validation(format!(
+            "Unexpected trailing input in expression '{input}'"
+        )));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> f64 {
+        let df = df!("a" => [1i64, 2, 3], "b" => [2.0, 4.0, 6.0]).unwrap();
+        let out = df
+            .lazy()
+            .select([parse_expression(input).unwrap().alias("out")])
+            .collect()
+            .unwrap();
+        out.column("out")
+            .unwrap()
+            .cast(&DataType::Float64)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .get(0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_supported_grammar() {
+        assert_eq!(eval("col('a').sum()"), 6.0);
+        assert_eq!(eval("col(\"b\").mean()"), 4.0);
+        assert_eq!(eval("col('a').sum() / col('b').sum()"), 0.5);
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("-col('a').max() + lit(.5)"), -2.5);
+        assert_eq!(eval("col('a').n_unique()"), 3.0);
+        assert_eq!(eval("col('b').last() - col('b').first()"), 4.0);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for input in [
+            "",
+            "col(a).sum()",
+            "col('a').explode()",
+            "col('a').sum(",
+            "col('a) .sum()",
+            "col('a').sum() col('b')",
+            "col('a').sum() % 2",
+            "1.2.3",
+            "foo('a')",
+        ] {
+            assert!(parse_expression(input).is_err(), "accepted {input:?}");
+        }
+    }
+}
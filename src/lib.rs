@@ -1,11 +1,12 @@
 use pyo3::prelude::*;
 use pyo3::types::PyModule;
 
-mod aggregation;
-mod dag_tracer;
-mod error;
-mod model;
-mod schema;
+pub mod aggregation;
+pub mod dag_tracer;
+pub mod error;
+mod expr_parser;
+pub mod model;
+pub mod schema;
 
 use model::SdtModel;
 mod visualization;
@@ -18,6 +19,7 @@ fn add_schema_exports(m: &Bound<'_, PyModule>) -> PyResult<()> {
     transfer.add("DEST_SEGMENT_ID", schema::transfer::DEST_SEGMENT_ID)?;
     transfer.add("TRANSFER_COUNT", schema::transfer::TRANSFER_COUNT)?;
     transfer.add("TRANSFER_BIOMASS_KG", schema::transfer::TRANSFER_BIOMASS_KG)?;
+    transfer.add("TRANSFER_TIME", schema::transfer::TRANSFER_TIME)?;
     m.add_submodule(&transfer)?;
 
     // Factors
@@ -56,6 +58,8 @@ fn add_schema_exports(m: &Bound<'_, PyModule>) -> PyResult<()> {
     segment.add("CONTAINER_ID", schema::segment::CONTAINER_ID)?;
     segment.add("START_TIME", schema::segment::START_TIME)?;
     segment.add("END_TIME", schema::segment::END_TIME)?;
+    segment.add("LIFESPAN_US", schema::segment::LIFESPAN_US)?;
+    segment.add("LIFESPAN_DAYS", schema::segment::LIFESPAN_DAYS)?;
     m.add_submodule(&segment)?;
 
     // Container
@@ -74,6 +78,11 @@ fn add_schema_exports(m: &Bound<'_, PyModule>) -> PyResult<()> {
         schema::traceability::TRACED_SEGMENT_ID,
     )?;
     traceability.add("TRACE_DIRECTION", schema::traceability::TRACE_DIRECTION)?;
+    traceability.add("TRACE_DISTANCE", schema::traceability::TRACE_DISTANCE)?;
+    traceability.add("N_PATHS", schema::traceability::N_PATHS)?;
+    traceability.add("MIN_PATH_FACTOR", schema::traceability::MIN_PATH_FACTOR)?;
+    traceability.add("MAX_PATH_FACTOR", schema::traceability::MAX_PATH_FACTOR)?;
+    traceability.add("SCHEMA_VERSION", schema::traceability::SCHEMA_VERSION)?;
     m.add_submodule(&traceability)?;
 
     // TimeSeries
@@ -87,7 +96,11 @@ fn add_schema_exports(m: &Bound<'_, PyModule>) -> PyResult<()> {
 #[pymodule]
 fn _core(m: &Bound<'_, PyModule>) -> PyResult<()> {    
     m.add_class::<SdtModel>()?;
+    m.add_class::<model::TraceIter>()?;
+    m.add_class::<model::TraceResult>()?;
     m.add_class::<crate::aggregation::Aggregation>()?;
+    m.add_class::<visualization::VisualizationConfig>()?;
+    m.add("ValidationError", m.py().get_type::<error::ValidationError>())?;
     add_schema_exports(m)?;
     Ok(())
 }
@@ -1,23 +1,243 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::ffi::CString;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 
+use petgraph::Direction;
 use polars::datatypes::TimeUnit;
 use polars::prelude::StrptimeOptions;
 use polars::prelude::*;
 
-use pyo3::exceptions::PyValueError;
+use pyo3::exceptions::{PyUserWarning, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyDateTime;
+use pyo3::types::{PyDateTime, PyDict};
 use pyo3_polars::PyDataFrame;
 
 use chrono::NaiveDateTime;
+use encoding_rs::Encoding;
+use serde::Serialize;
 
-use crate::aggregation::Aggregation;
-use crate::dag_tracer::DagTracer;
+use crate::aggregation::{
+    aggregate_groups_with_progress, validate_trace_frame, validate_trace_frame_for, AggregateBy,
+    Aggregation,
+};
+use crate::dag_tracer::{cyclic_transfer_rows, DagTracer, DistanceMetric};
 use crate::error::SdtError;
 use crate::schema::*;
 use crate::visualization::{self, VisualizationConfig};
 
+const MICROS_PER_DAY: f64 = 86_400_000_000.0;
+const DEFAULT_TRACE_CACHE_SIZE: usize = 8;
+/// Files read by loaders called without `filename`, by `filenames` key
+const DEFAULT_FILENAMES: [(&str, &str); 3] = [
+    ("transfers", "transfers.csv"),
+    ("segments", "segments.csv"),
+    ("containers", "containers.csv"),
+];
+/// Progress callbacks fire about this many times per operation (plus the final step)
+const PROGRESS_UPDATES: usize = 100;
+
+/// Origin ids in call order, sorted stop ids, `min_factor` (as bits) and `detail` of a
+/// `trace_segments` call.
+type TraceKey = (Vec<String>, Vec<String>, Option<u64>, bool);
+
+/// Small LRU cache of `trace_segments` results keyed by the origins and trace limits.
+struct TraceCache {
+    capacity: usize,
+    // Most recently used entry at the back
+    entries: VecDeque<(TraceKey, DataFrame)>,
+}
+
+impl TraceCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: &TraceKey) -> Option<DataFrame> {
+        let pos = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(pos)?;
+        let df = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(df)
+    }
+
+    fn insert(&mut self, key: TraceKey, df: DataFrame) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, df));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// One origin in the nested `trace_to_json` output.
+#[derive(Serialize)]
+struct OriginTrace {
+    origin_segment_id: String,
+    traced: Vec<TracedSegment>,
+}
+
+/// One traced segment with its direction and the four share factors.
+#[derive(Serialize)]
+struct TracedSegment {
+    segment_id: String,
+    direction: String,
+    share_count_forward: f64,
+    share_biomass_forward: f64,
+    share_count_backward: f64,
+    share_biomass_backward: f64,
+}
+
+/// Expected layout of one input file, in the `expected_schema` output.
+#[derive(Serialize)]
+struct FileSpec {
+    /// Default filename, if the loader has one
+    default_filename: Option<&'static str>,
+    loader: &'static str,
+    columns: Vec<ColumnSpec>,
+    /// Column groups of which at least one must be complete
+    requires_one_of: Vec<Vec<&'static str>>,
+    /// Whether columns other than those listed are kept
+    extra_columns_kept: bool,
+}
+
+/// One column of a `FileSpec`.
+#[derive(Serialize)]
+struct ColumnSpec {
+    name: &'static str,
+    /// Type after loading: "string", "datetime" or "float"
+    dtype: &'static str,
+    required: bool,
+    /// Calculated by the loader when absent from the file
+    derived: bool,
+    description: &'static str,
+}
+
+impl ColumnSpec {
+    fn new(name: &'static str, dtype: &'static str, required: bool, description: &'static str) -> Self {
+        Self {
+            name,
+            dtype,
+            required,
+            derived: false,
+            description,
+        }
+    }
+
+    fn derived(mut self) -> Self {
+        self.derived = true;
+        self
+    }
+}
+
+/// Options shared by the file loaders.
+#[derive(Default)]
+struct ReadOptions {
+    /// Column rename map applied after reading
+    rename: Option<HashMap<String, String>>,
+    /// Keep Arrow column types instead of converting to strings
+    preserve_dtypes: bool,
+    /// Source encoding label, e.g. "latin1" or "cp1252" (default: UTF-8)
+    encoding: Option<String>,
+    /// Strip leading/trailing whitespace from all string cells, not just id columns
+    trim_values: bool,
+    /// The CSV has no header row; columns are named `column_1`, `column_2`, ...
+    headerless: bool,
+    /// Column names replacing the file's header (or the generated names), before `rename`
+    column_names: Option<Vec<String>>,
+}
+
+/// Most ticks `segments_active_histogram` will produce.
+const MAX_HISTOGRAM_TICKS: usize = 100_000;
+
+/// Id columns are always stored as trimmed strings so ids match across files.
+const ID_COLUMNS: [&str; 4] = [
+    segment::SEGMENT_ID,
+    segment::CONTAINER_ID,
+    transfer::SOURCE_SEGMENT_ID,
+    transfer::DEST_SEGMENT_ID,
+];
+
+/// The options of one `trace_segments` call. The model keeps one set as the defaults
+/// stored with `configure_trace`, which a call starts from and overrides.
+#[derive(Debug, Default, Clone)]
+struct TraceOptions {
+    origin_attrs: Option<Vec<String>>,
+    distance_metric: Option<DistanceMetric>,
+    stop_ids: Vec<String>,
+    drop_zero_factor: bool,
+    min_factor: Option<f64>,
+    detail: bool,
+}
+
+/// Which endpoints of a segment's `[start_time, end_time]` interval count as active.
+///
+/// Used by every method that matches a timestamp to segments, so a reading taken at a
+/// transfer instant is assigned the same way everywhere. `Left` (the default) is
+/// `start_time <= t < end_time`: a reading at a transfer belongs to the segment that
+/// starts there, not the one that ends there. A null `end_time` is open-ended.
+/// A zero-duration segment (`start_time == end_time`) matches exactly that instant
+/// whatever the setting, since a half-open interval would never match it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntervalClosed {
+    #[default]
+    Left,
+    Right,
+    Both,
+    Neither,
+}
+
+impl IntervalClosed {
+    fn parse(value: &str) -> Result<Self, SdtError> {
+        match value {
+            "left" => Ok(Self::Left),
+            "right" => Ok(Self::Right),
+            "both" => Ok(Self::Both),
+            "neither" => Ok(Self::Neither),
+            other => Err(SdtError::Validation(format!(
+                "Invalid interval_closed '{other}'. Must be 'left', 'right', 'both' or 'neither'"
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Both => "both",
+            Self::Neither => "neither",
+        }
+    }
+
+    /// Whether `t` lies in the segment interval. `end_inclusive` overrides the end bound.
+    fn contains(self, t: Expr, end_inclusive: Option<bool>) -> Expr {
+        let after_start = match self {
+            Self::Left | Self::Both => col(segment::START_TIME).lt_eq(t.clone()),
+            Self::Right | Self::Neither => col(segment::START_TIME).lt(t.clone()),
+        };
+        let instant = col(segment::START_TIME)
+            .eq(col(segment::END_TIME))
+            .and(t.clone().eq(col(segment::START_TIME)));
+        let before_end = if end_inclusive.unwrap_or(matches!(self, Self::Right | Self::Both)) {
+            t.lt_eq(col(segment::END_TIME))
+        } else {
+            t.lt(col(segment::END_TIME))
+        };
+        after_start
+            .and(col(segment::END_TIME).is_null().or(before_end))
+            .or(instant)
+    }
+}
+
 #[pyclass]
 pub struct SdtModel {
     base_path: PathBuf,
@@ -25,32 +245,110 @@ pub struct SdtModel {
     containers: Option<DataFrame>,
     segments: Option<DataFrame>,
     tracer: Option<DagTracer>,
+    trace_cache: TraceCache,
+    interval_closed: IntervalClosed,
+    /// Transfer columns added by the loader rather than read from the file(s)
+    derived_columns: Vec<String>,
+    trace_defaults: TraceOptions,
+    /// Overrides of `DEFAULT_FILENAMES`
+    filenames: HashMap<String, String>,
+    /// Keep the transfer id columns as Categorical while loaded
+    categorical_ids: bool,
 }
 
 #[pymethods]
 impl SdtModel {
+    /// `trace_cache_size` is the number of `trace_segments` results kept for reuse
+    /// (0 disables caching). `interval_closed` sets which segment interval endpoints
+    /// count as active (see `IntervalClosed`). `filenames` maps "transfers", "segments"
+    /// and/or "containers" to the file their loader reads when called without one.
+    ///
+    /// With `categorical_ids=true` the loaded transfers keep `source_segment_id` and
+    /// `dest_segment_id` as Categorical, which stores each id once instead of once per
+    /// row (see docs/DEVELOPMENT.md for measurements). They are cast back to String
+    /// for each operation that reads them and in every returned frame, so results are
+    /// the same either way; the saving is in the memory held between calls.
     #[new]
-    fn new(base_path: String) -> Self {
-        Self {
+    #[pyo3(signature = (
+        base_path,
+        trace_cache_size=DEFAULT_TRACE_CACHE_SIZE,
+        interval_closed="left",
+        filenames=None,
+        categorical_ids=false,
+    ))]
+    fn new(
+        base_path: String,
+        trace_cache_size: usize,
+        interval_closed: &str,
+        filenames: Option<HashMap<String, String>>,
+        categorical_ids: bool,
+    ) -> PyResult<Self> {
+        let filenames = filenames.unwrap_or_default();
+        Self::check_filenames(&filenames)?;
+        Ok(Self {
             base_path: PathBuf::from(base_path),
             transfers: None,
             containers: None,
             segments: None,
             tracer: None,
-        }
+            trace_cache: TraceCache::new(trace_cache_size),
+            interval_closed: IntervalClosed::parse(interval_closed)?,
+            derived_columns: Vec::new(),
+            trace_defaults: TraceOptions::default(),
+            filenames,
+            categorical_ids,
+        })
     }
 
     // ── Data loading ────────────────────────────────────────────────────────
 
     /// Load any CSV into a Polars DataFrame with all columns as strings.
     /// Optionally rename columns via a map.
-    #[pyo3(signature = (filename, rename=None))]
+    ///
+    /// Arrow IPC files (.arrow / .feather / .ipc) are read as well. With
+    /// `preserve_dtypes=True` their column types are kept instead of being
+    /// converted to strings (id columns are always strings). Datetime columns are
+    /// always kept.
+    ///
+    /// All loaders take an optional `encoding` (e.g. "latin1", "cp1252") for CSV
+    /// files that are not UTF-8.
+    ///
+    /// Whitespace around id values (segment, container and source/dest segment ids) is
+    /// always stripped, so `" S123"` matches `"S123"` across files. Pass
+    /// `trim_values=True` to strip it from every string column.
+    ///
+    /// For CSV files without a header row pass `has_header=False`; columns are then
+    /// named `column_1`, `column_2`, ... unless `column_names` is given. `column_names`
+    /// also replaces an existing header. It must list every column in the file.
+    #[pyo3(signature = (
+        filename,
+        rename=None,
+        preserve_dtypes=false,
+        encoding=None,
+        trim_values=false,
+        has_header=true,
+        column_names=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn load_csv(
         &self,
         filename: &str,
         rename: Option<HashMap<String, String>>,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+        has_header: bool,
+        column_names: Option<Vec<String>>,
     ) -> PyResult<PyDataFrame> {
-        let df = self.read_csv_as_strings(filename, rename)?;
+        let options = ReadOptions {
+            rename,
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            headerless: !has_header,
+            column_names,
+        };
+        let df = self.read_table(filename, &options)?;
         Ok(PyDataFrame(df))
     }
 
@@ -67,147 +365,243 @@ impl SdtModel {
     /// Share factors are calculated automatically but only if they are missing.
     /// Validation happens on row level - so if you want some rows may specify stock while others specify factors.
     /// All other  columns are preserved as strings.
-    #[pyo3(signature = (filename=None))]
-    fn load_transfers(&mut self, filename: Option<&str>) -> PyResult<PyDataFrame> {
-        let fname = filename.unwrap_or("transfers.csv");
-        let raw = self.read_csv_as_strings(fname, None)?;
-
-        Self::require_columns(&raw, &[transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID])?;
-
-        let schema = raw.schema();
-        let has_stock_cols = schema.contains(transfer::TRANSFER_COUNT)
-            && schema.contains(transfer::TRANSFER_BIOMASS_KG);
-        let has_factor_cols = schema.contains(factors::SHARE_COUNT_FORWARD)
-            && schema.contains(factors::SHARE_BIOMASS_FORWARD)
-            && schema.contains(factors::SHARE_COUNT_BACKWARD)
-            && schema.contains(factors::SHARE_BIOMASS_BACKWARD);
-
-        if !has_stock_cols && !has_factor_cols {
-            return Err(SdtError::InvalidData(
-                "Transfers CSV must contain either (transfer_count, transfer_biomass_kg) \
-             or all share factor columns"
-                    .to_string(),
-            )
-            .into());
-        }
-
-        let mut lazy = raw.lazy();
+    ///
+    /// When the file has no stock columns, null `transfer_count` / `transfer_biomass_kg`
+    /// columns are added so the frame always has the same shape. Pass
+    /// `create_missing_stock=False` to leave them out instead. Weighted aggregations
+    /// only use the factor columns, so they work the same either way; `flow_into`,
+    /// `flow_out_of` and `biomass_flow_matrix` sum the stock and raise without it.
+    ///
+    /// Calculated forward factors normalize the stock over `source_segment_id` and
+    /// backward factors over `dest_segment_id`. `forward_group_cols` /
+    /// `backward_group_cols` replace those partitions with a composite key, e.g.
+    /// `["source_segment_id", "batch"]` when one source fed independent events.
+    ///
+    /// With `clamp_negative_factors=true` negative factors (e.g. upstream rounding) are
+    /// set to 0.0 and the number clamped is reported with a `UserWarning`; NaN or
+    /// infinite factors are then rejected, so all factors are finite and non-negative.
+    ///
+    /// With `validate_only=true` the file is checked and processed as usual and the
+    /// result returned, but the model is left unchanged (see `load_segments` and
+    /// `load_containers` for the same option).
+    ///
+    /// With `factors_as_percent=true` the factor columns in the file are read as
+    /// percentages (0–100) and divided by 100. Calculated factors are unaffected.
+    #[pyo3(signature = (
+        filename=None,
+        create_missing_stock=true,
+        preserve_dtypes=false,
+        encoding=None,
+        trim_values=false,
+        forward_group_cols=None,
+        backward_group_cols=None,
+        clamp_negative_factors=false,
+        validate_only=false,
+        factors_as_percent=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn load_transfers(
+        &mut self,
+        py: Python<'_>,
+        filename: Option<&str>,
+        create_missing_stock: bool,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+        forward_group_cols: Option<Vec<String>>,
+        backward_group_cols: Option<Vec<String>>,
+        clamp_negative_factors: bool,
+        validate_only: bool,
+        factors_as_percent: bool,
+    ) -> PyResult<PyDataFrame> {
+        let fname = self.filename_or_default(filename, "transfers");
+        let options = ReadOptions {
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let raw = self.read_table(&fname, &options)?;
+        let file_cols = raw.get_column_names_owned();
+        let raw = if factors_as_percent {
+            Self::factors_from_percent(raw)?
+        } else {
+            raw
+        };
 
-        // Cast stock columns if present, otherwise create null columns
-        if has_stock_cols {
-            lazy = lazy.with_columns([
-                col(transfer::TRANSFER_COUNT).cast(DataType::Float64),
-                col(transfer::TRANSFER_BIOMASS_KG).cast(DataType::Float64),
-            ]);
+        let df = Self::prepare_transfers_grouped(
+            raw,
+            create_missing_stock,
+            forward_group_cols.as_deref(),
+            backward_group_cols.as_deref(),
+        )?;
+        let df = if clamp_negative_factors {
+            let (df, clamped) = Self::clamp_negative_factors(df)?;
+            if clamped > 0 {
+                let msg = CString::new(format!("Clamped {clamped} negative factor values to 0.0"))?;
+                PyErr::warn(py, &py.get_type::<PyUserWarning>(), &msg, 1)?;
+            }
+            df
         } else {
-            lazy = lazy.with_columns([
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(transfer::TRANSFER_COUNT),
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(transfer::TRANSFER_BIOMASS_KG),
-            ]);
+            df
+        };
+        if validate_only {
+            return Ok(PyDataFrame(df));
         }
+        self.derived_columns = Self::columns_not_in(&df, &file_cols);
+        self.transfers = Some(Self::stored_ids(df.clone(), self.categorical_ids)?);
+        self.tracer = None;
+        self.trace_cache.clear();
+        Ok(PyDataFrame(df))
+    }
 
-        // Cast or create factor columns
-        if has_factor_cols {
-            lazy = lazy.with_columns([
-                col(factors::SHARE_COUNT_FORWARD).cast(DataType::Float64),
-                col(factors::SHARE_BIOMASS_FORWARD).cast(DataType::Float64),
-                col(factors::SHARE_COUNT_BACKWARD).cast(DataType::Float64),
-                col(factors::SHARE_BIOMASS_BACKWARD).cast(DataType::Float64),
-            ]);
-        } else {
-            lazy = lazy.with_columns([
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(factors::SHARE_COUNT_FORWARD),
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(factors::SHARE_BIOMASS_FORWARD),
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(factors::SHARE_COUNT_BACKWARD),
-                lit(NULL)
-                    .cast(DataType::Float64)
-                    .alias(factors::SHARE_BIOMASS_BACKWARD),
-            ]);
+    /// Load several transfer files as one logical graph, e.g. freshwater and seawater
+    /// phases plus the file of transfers linking them.
+    ///
+    /// Files are read in the given order and concatenated (columns missing from a file
+    /// are null), then validated and given share factors once over the union, like
+    /// `load_transfers`. Replaces any loaded transfers.
+    #[pyo3(signature = (filenames, create_missing_stock=true, encoding=None, trim_values=false))]
+    fn load_transfers_merge(
+        &mut self,
+        filenames: Vec<String>,
+        create_missing_stock: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+    ) -> PyResult<PyDataFrame> {
+        if filenames.is_empty() {
+            return Err(SdtError::Validation("No transfer files given".into()).into());
         }
+        let options = ReadOptions {
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let frames = filenames
+            .iter()
+            .map(|f| self.read_table(f, &options).map(|df| df.lazy()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let raw = concat_lf_diagonal(frames, UnionArgs::default())
+            .and_then(|lf| lf.collect())
+            .map_err(SdtError::from)?;
+        let file_cols = raw.get_column_names_owned();
 
-        // Calculate factors from stock (for rows that need it)
-        let calc_forward_count = col(transfer::TRANSFER_COUNT)
-            / col(transfer::TRANSFER_COUNT)
-                .sum()
-                .over([col(transfer::SOURCE_SEGMENT_ID)]);
-        let calc_forward_biomass = col(transfer::TRANSFER_BIOMASS_KG)
-            / col(transfer::TRANSFER_BIOMASS_KG)
-                .sum()
-                .over([col(transfer::SOURCE_SEGMENT_ID)]);
-        let calc_backward_count = col(transfer::TRANSFER_COUNT)
-            / col(transfer::TRANSFER_COUNT)
-                .sum()
-                .over([col(transfer::DEST_SEGMENT_ID)]);
-        let calc_backward_biomass = col(transfer::TRANSFER_BIOMASS_KG)
-            / col(transfer::TRANSFER_BIOMASS_KG)
-                .sum()
-                .over([col(transfer::DEST_SEGMENT_ID)]);
+        let df = Self::prepare_transfers(raw, create_missing_stock)?;
+        self.derived_columns = Self::columns_not_in(&df, &file_cols);
+        self.transfers = Some(Self::stored_ids(df.clone(), self.categorical_ids)?);
+        self.tracer = None;
+        self.trace_cache.clear();
+        Ok(PyDataFrame(df))
+    }
 
-        // For each factor: use file value if present, otherwise calculate from stock
-        lazy = lazy.with_columns([
-            when(col(factors::SHARE_COUNT_FORWARD).is_not_null())
-                .then(col(factors::SHARE_COUNT_FORWARD))
-                .otherwise(calc_forward_count)
-                .alias(factors::SHARE_COUNT_FORWARD),
-            when(col(factors::SHARE_BIOMASS_FORWARD).is_not_null())
-                .then(col(factors::SHARE_BIOMASS_FORWARD))
-                .otherwise(calc_forward_biomass)
-                .alias(factors::SHARE_BIOMASS_FORWARD),
-            when(col(factors::SHARE_COUNT_BACKWARD).is_not_null())
-                .then(col(factors::SHARE_COUNT_BACKWARD))
-                .otherwise(calc_backward_count)
-                .alias(factors::SHARE_COUNT_BACKWARD),
-            when(col(factors::SHARE_BIOMASS_BACKWARD).is_not_null())
-                .then(col(factors::SHARE_BIOMASS_BACKWARD))
-                .otherwise(calc_backward_biomass)
-                .alias(factors::SHARE_BIOMASS_BACKWARD),
-        ]);
+    /// Append transfer rows from another CSV to the loaded transfers.
+    ///
+    /// The file is validated like `load_transfers`; missing share factors are calculated
+    /// from the stock within the appended file. When appended rows share a source or
+    /// dest segment with the loaded transfers, the factors are instead recalculated from
+    /// the stock of both; if either side read its factors from file that can't be done,
+    /// and those rows raise `ValidationError`. An already built tracer gets the new edges
+    /// instead of being rebuilt (and is dropped if factors were recalculated). Rows that
+    /// would introduce a cycle are rejected; on any error the model is left unchanged.
+    /// Returns the appended (processed) rows.
+    #[pyo3(signature = (filename, encoding=None, trim_values=false))]
+    fn append_transfers(
+        &mut self,
+        filename: &str,
+        encoding: Option<String>,
+        trim_values: bool,
+    ) -> PyResult<PyDataFrame> {
+        let options = ReadOptions {
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        Ok(PyDataFrame(self.append_transfer_file(filename, &options)?))
+    }
 
-        let df = lazy.collect().map_err(SdtError::from)?;
+    /// Compare another transfers file against the loaded transfers.
+    ///
+    /// The file is loaded and processed like `load_transfers`, then matched to the
+    /// loaded rows on (source_segment_id, dest_segment_id). Returns one row per
+    /// difference with a `change` column ("added", "removed" or "changed") and, for
+    /// changed rows, a `changed_columns` list of the stock/factor columns that differ
+    /// by more than `tolerance`. Stock columns are only compared when both the loaded
+    /// and the new transfers read them from file. Either side repeating a
+    /// (source, dest) pair raises `ValidationError` with those rows, since rows can't
+    /// be matched one to one. The loaded model is not modified.
+    #[pyo3(signature = (filename, tolerance=1e-9, encoding=None, trim_values=false))]
+    fn diff_transfers(
+        &self,
+        filename: &str,
+        tolerance: f64,
+        encoding: Option<String>,
+        trim_values: bool,
+    ) -> PyResult<PyDataFrame> {
+        let options = ReadOptions {
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        Ok(PyDataFrame(self.diff_transfer_file(filename, tolerance, &options)?))
+    }
 
-        // Validate that all rows have complete factor data
-        let factor_cols = [
-            factors::SHARE_COUNT_FORWARD,
-            factors::SHARE_BIOMASS_FORWARD,
-            factors::SHARE_COUNT_BACKWARD,
-            factors::SHARE_BIOMASS_BACKWARD,
-        ];
+    /// Collapse relabeled segments into one node before tracing.
+    ///
+    /// `mapping` maps old segment ids to the id they should be merged into. Transfer
+    /// source/dest ids and segment ids are rewritten, transfers that become self-loops
+    /// are dropped, and parallel transfers between the same pair are combined. When the
+    /// stock columns are complete they are summed and the share factors recalculated;
+    /// otherwise the factors themselves are summed, and the merge fails if a source's
+    /// forward (or a dest's backward) factors would then add up to more than 1.
+    /// Merged segment rows span the earliest start to the latest end (null if any
+    /// part has no end); other columns keep the first row's value.
+    /// Fails without changing the model if the merge would create a cycle.
+    /// Returns the merged transfers.
+    fn merge_segments(&mut self, mapping: HashMap<String, String>) -> PyResult<PyDataFrame> {
+        let merged = self.merge_segment_ids(&mapping)?;
+        Ok(PyDataFrame(merged))
+    }
 
-        for factor_col in &factor_cols {
-            let null_count = df.column(factor_col).map_err(SdtError::from)?.null_count();
-            if null_count > 0 {
-                return Err(SdtError::InvalidData(
-            format!("All rows must have valid factor values. Column '{}' has {} null values. \
-                     Provide either factor values or stock values (transfer_count, transfer_biomass_kg) for all rows.",
-                     factor_col, null_count)
-        ).into());
-            }
-        }
-        self.transfers = Some(df.clone());
-        self.tracer = None;
-        Ok(PyDataFrame(df))
+    /// Treat several containers as one, e.g. tanks reported as a single unit.
+    ///
+    /// `mapping` maps old container ids to the id they should be merged into. The
+    /// `container_id` of the loaded containers and segments is rewritten, so lanes and
+    /// container-data mapping see one container; merged container rows keep the first
+    /// row's other columns. Transfers and segment ids are unchanged, so tracing is not
+    /// affected. Fails without changing the model if segments of a merged container
+    /// would overlap in time under `interval_closed` (with "both", segments that touch
+    /// at a transfer instant overlap).
+    fn merge_containers(&mut self, mapping: HashMap<String, String>) -> PyResult<()> {
+        Ok(self.merge_container_ids(&mapping)?)
     }
 
     /// Load containers CSV.
     ///
     /// Required columns: container_id
     /// All user columns are preserved (as strings).
-    #[pyo3(signature = (filename=None))]
-    fn load_containers(&mut self, filename: Option<&str>) -> PyResult<PyDataFrame> {
-        let fname = filename.unwrap_or("containers.csv");
-        let raw = self.read_csv_as_strings(fname, None)?;
+    /// With `validate_only=true` the checked frame is returned without storing it.
+    #[pyo3(signature = (filename=None, preserve_dtypes=false, encoding=None, trim_values=false, validate_only=false))]
+    fn load_containers(
+        &mut self,
+        filename: Option<&str>,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+        validate_only: bool,
+    ) -> PyResult<PyDataFrame> {
+        let fname = self.filename_or_default(filename, "containers");
+        let options = ReadOptions {
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let raw = self.read_table(&fname, &options)?;
 
         Self::require_columns(&raw, &[container::CONTAINER_ID])?;
+        if validate_only {
+            return Ok(PyDataFrame(raw));
+        }
 
         self.containers = Some(raw.clone());
         Ok(PyDataFrame(raw))
@@ -216,12 +610,49 @@ impl SdtModel {
     /// Load segments CSV.
     ///
     /// Required columns: segment_id, container_id, start_time, end_time
-    /// start_time and end_time are parsed as datetime (%Y-%m-%d %H:%M:%S).
+    /// start_time and end_time are parsed as datetime (%Y-%m-%d %H:%M:%S), or with
+    /// `time_format`: a strptime format, or "epoch_s" / "epoch_ms" / "epoch_us" for
+    /// Unix epoch integers.
     /// All user columns are preserved (as strings).
-    #[pyo3(signature = (filename=None))]
-    fn load_segments(&mut self, filename: Option<&str>) -> PyResult<PyDataFrame> {
-        let fname = filename.unwrap_or("segments.csv");
-        let raw = self.read_csv_as_strings(fname, None)?;
+    /// With `infer_end_time=true` a null end_time (or a missing end_time column) is
+    /// filled with the start_time of the next segment in the same container; the last
+    /// segment of each container stays open-ended.
+    /// With `validate_only=true` the parsed frame is returned without storing it.
+    #[pyo3(signature = (
+        filename=None,
+        preserve_dtypes=false,
+        encoding=None,
+        trim_values=false,
+        validate_only=false,
+        time_format=None,
+        infer_end_time=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn load_segments(
+        &mut self,
+        filename: Option<&str>,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+        validate_only: bool,
+        time_format: Option<&str>,
+        infer_end_time: bool,
+    ) -> PyResult<PyDataFrame> {
+        let fname = self.filename_or_default(filename, "segments");
+        let options = ReadOptions {
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let mut raw = self.read_table(&fname, &options)?;
+        if infer_end_time && raw.column(segment::END_TIME).is_err() {
+            raw = raw
+                .lazy()
+                .with_column(lit(NULL).cast(DataType::String).alias(segment::END_TIME))
+                .collect()
+                .map_err(SdtError::from)?;
+        }
 
         Self::require_columns(
             &raw,
@@ -234,8 +665,17 @@ impl SdtModel {
         )?;
 
         // Parse datetime columns
-        let df = Self::parse_datetime_column(raw, segment::START_TIME, "%Y-%m-%d %H:%M:%S")?;
-        let df = Self::parse_datetime_column(df, segment::END_TIME, "%Y-%m-%d %H:%M:%S")?;
+        let format = time_format.unwrap_or("%Y-%m-%d %H:%M:%S");
+        let df = Self::parse_datetime_column(raw, segment::START_TIME, format)?;
+        let df = Self::parse_datetime_column(df, segment::END_TIME, format)?;
+        let df = if infer_end_time {
+            Self::infer_end_times(df)?
+        } else {
+            df
+        };
+        if validate_only {
+            return Ok(PyDataFrame(df));
+        }
 
         self.segments = Some(df.clone());
         Ok(PyDataFrame(df))
@@ -246,8 +686,21 @@ impl SdtModel {
     /// Required columns: segment_id, date_time, + any value columns.
     /// All columns loaded as strings — use parse helpers before passing
     /// to aggregation methods.
-    fn load_segment_timeseries(&self, filename: &str) -> PyResult<PyDataFrame> {
-        let df = self.read_csv_as_strings(filename, None)?;
+    #[pyo3(signature = (filename, preserve_dtypes=false, encoding=None, trim_values=false))]
+    fn load_segment_timeseries(
+        &self,
+        filename: &str,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+    ) -> PyResult<PyDataFrame> {
+        let options = ReadOptions {
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let df = self.read_table(filename, &options)?;
         Self::require_columns(&df, &[segment::SEGMENT_ID, timeseries::DATE_TIME])?;
         let df = Self::parse_datetime_column(df, timeseries::DATE_TIME, "%Y-%m-%d %H:%M:%S")?;
 
@@ -259,18 +712,75 @@ impl SdtModel {
     /// Required columns: container_id, date_time, + any value columns.
     /// All columns loaded as strings — use parse helpers before passing
     /// to aggregation or mapping methods.
-    fn load_container_timeseries(&self, filename: &str) -> PyResult<PyDataFrame> {
-        let df = self.read_csv_as_strings(filename, None)?;
+    #[pyo3(signature = (filename, preserve_dtypes=false, encoding=None, trim_values=false))]
+    fn load_container_timeseries(
+        &self,
+        filename: &str,
+        preserve_dtypes: bool,
+        encoding: Option<String>,
+        trim_values: bool,
+    ) -> PyResult<PyDataFrame> {
+        let options = ReadOptions {
+            preserve_dtypes,
+            encoding,
+            trim_values,
+            ..Default::default()
+        };
+        let df = self.read_table(filename, &options)?;
         Self::require_columns(&df, &[container::CONTAINER_ID, timeseries::DATE_TIME])?;
         let df = Self::parse_datetime_column(df, timeseries::DATE_TIME, "%Y-%m-%d %H:%M:%S")?;
         Ok(PyDataFrame(df))
     }
 
+    /// Write a DataFrame to a file under base_path.
+    ///
+    /// `format` is one of "csv", "parquet" or "arrow" (Arrow IPC / Feather). When omitted
+    /// it is taken from the file extension.
+    #[pyo3(signature = (df, filename, format=None))]
+    fn export(&self, df: PyDataFrame, filename: &str, format: Option<&str>) -> PyResult<()> {
+        let path = self.base_path.join(filename);
+        let format = match format {
+            Some(f) => f.to_lowercase(),
+            None => path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+        };
+
+        let mut df = df.0;
+        let file = std::fs::File::create(&path).map_err(SdtError::from)?;
+        match format.as_str() {
+            "csv" => {
+                CsvWriter::new(file)
+                    .finish(&mut df)
+                    .map_err(SdtError::from)?;
+            }
+            "parquet" => {
+                ParquetWriter::new(file)
+                    .finish(&mut df)
+                    .map_err(SdtError::from)?;
+            }
+            "arrow" | "feather" | "ipc" => {
+                IpcWriter::new(file)
+                    .finish(&mut df)
+                    .map_err(SdtError::from)?;
+            }
+            other => {
+                return Err(SdtError::Validation(format!(
+                    "Unsupported export format '{other}'. Use 'csv', 'parquet' or 'arrow'"
+                ))
+                .into())
+            }
+        }
+        Ok(())
+    }
+
     // ── Parse helpers ───────────────────────────────────────────────────────
 
     /// Parse a string column to Datetime using the given format string.
     ///
-    /// Example formats: "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d/%m/%Y"
+    /// Example formats: "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d/%m/%Y".
+    /// "epoch_s", "epoch_ms" and "epoch_us" read Unix epoch integers instead.
     #[staticmethod]
     fn parse_datetime(df: PyDataFrame, column: &str, format: &str) -> PyResult<PyDataFrame> {
         let result = Self::parse_datetime_column(df.0, column, format)?;
@@ -278,261 +788,1121 @@ impl SdtModel {
     }
 
     /// Parse a string column to Float64.
+    ///
+    /// `strip_chars` lists characters removed anywhere in the value (e.g. "$ kg") and
+    /// `thousands` is a separator to remove, so "1,234.50 kg" parses as 1234.5.
     #[staticmethod]
-    fn parse_float(df: PyDataFrame, column: &str) -> PyResult<PyDataFrame> {
+    #[pyo3(signature = (df, column, strip_chars=None, thousands=None))]
+    fn parse_float(
+        df: PyDataFrame,
+        column: &str,
+        strip_chars: Option<String>,
+        thousands: Option<char>,
+    ) -> PyResult<PyDataFrame> {
+        let cleaned = Self::clean_numeric_string(column, strip_chars.as_deref(), thousands);
         let result =
             df.0.lazy()
-                .with_columns([col(column)
-                    .str()
-                    .strip_chars(lit(" \t\r\n"))
-                    .cast(DataType::Float64)])
+                .with_columns([cleaned.cast(DataType::Float64)])
                 .collect()
                 .map_err(SdtError::from)?;
         Ok(PyDataFrame(result))
     }
 
-    /// Parse a string column to Int64.
+    /// Parse a string column to Int64. Takes the same `strip_chars` / `thousands`
+    /// options as `parse_float`.
     #[staticmethod]
-    fn parse_int(df: PyDataFrame, column: &str) -> PyResult<PyDataFrame> {
+    #[pyo3(signature = (df, column, strip_chars=None, thousands=None))]
+    fn parse_int(
+        df: PyDataFrame,
+        column: &str,
+        strip_chars: Option<String>,
+        thousands: Option<char>,
+    ) -> PyResult<PyDataFrame> {
+        let cleaned = Self::clean_numeric_string(column, strip_chars.as_deref(), thousands);
         let result =
             df.0.lazy()
-                .with_columns([col(column)
-                    .str()
-                    .strip_chars(lit(" \t\r\n"))
-                    .cast(DataType::Int64)])
+                .with_columns([cleaned.cast(DataType::Int64)])
                 .collect()
                 .map_err(SdtError::from)?;
         Ok(PyDataFrame(result))
     }
 
-    // ── Tracing ─────────────────────────────────────────────────────────────
+    /// Clamp a numeric column to its per-group quantile bounds (winsorizing).
+    ///
+    /// Values below the `lower_q` quantile are raised to it and values above the
+    /// `upper_q` quantile are lowered to it. Quantiles are computed within each
+    /// `group_by` group, or over the whole frame when no groups are given.
+    /// The column is cast to Float64.
+    #[staticmethod]
+    #[pyo3(signature = (df, column, lower_q=0.01, upper_q=0.99, group_by=None))]
+    fn winsorize(
+        df: PyDataFrame,
+        column: &str,
+        lower_q: f64,
+        upper_q: f64,
+        group_by: Option<Vec<String>>,
+    ) -> PyResult<PyDataFrame> {
+        if !(0.0..=1.0).contains(&lower_q) || !(0.0..=1.0).contains(&upper_q) || lower_q > upper_q {
+            return Err(SdtError::Validation(format!(
+                "Quantiles must satisfy 0 <= lower_q <= upper_q <= 1, got {lower_q} and {upper_q}"
+            ))
+            .into());
+        }
+        Self::require_columns(&df.0, &[column])?;
 
-    /// Trace segments from a DataFrame containing a `segment_id` column.
-    fn trace_segments(&mut self, origin_df: PyDataFrame) -> PyResult<PyDataFrame> {
-        let tracer = self.get_or_build_tracer().map_err(SdtError::from)?;
-        let ids: Vec<String> = origin_df
-            .0
-            .column(segment::SEGMENT_ID)
-            .map_err(SdtError::from)?
-            .str()
-            .map_err(SdtError::from)?
-            .into_iter()
-            .filter_map(|v| v.map(|s| s.to_string()))
-            .collect();
+        let value = col(column).cast(DataType::Float64);
+        let mut lower = value.clone().quantile(lit(lower_q), QuantileMethod::Linear);
+        let mut upper = value.clone().quantile(lit(upper_q), QuantileMethod::Linear);
+        if let Some(keys) = group_by.filter(|k| !k.is_empty()) {
+            let keys: Vec<Expr> = keys.iter().map(col).collect();
+            lower = lower.over(keys.clone());
+            upper = upper.over(keys);
+        }
 
-        let result = tracer.trace(&ids).map_err(SdtError::from)?;
+        let result =
+            df.0.lazy()
+                .with_columns([when(value.clone().lt(lower.clone()))
+                    .then(lower)
+                    .when(value.clone().gt(upper.clone()))
+                    .then(upper)
+                    .otherwise(value)
+                    .alias(column)])
+                .collect()
+                .map_err(SdtError::from)?;
         Ok(PyDataFrame(result))
     }
 
-    // ── Filtering ───────────────────────────────────────────────────────────
+    // ── Tracing ─────────────────────────────────────────────────────────────
 
-    fn get_segments_active_at(&self, timestamp: Bound<PyDateTime>) -> PyResult<PyDataFrame> {
-        // Reject timezone-aware datetimes
-        if !timestamp.getattr("tzinfo")?.is_none() {
-            return Err(PyValueError::new_err(
-                "aqua-tracekit requires naive datetime objects (no timezone info). \
-                 Use datetime(2024, 6, 15, 12, 0, 0) instead of datetime(..., tzinfo=...)",
-            ));
+    /// Trace segments from a DataFrame containing a `segment_id` column.
+    ///
+    /// Results are cached per origin list until transfers change; rows follow the order
+    /// of `origin_df`, so the same origins in another order are traced afresh.
+    /// `progress_callback(done, total)` is called periodically while tracing origins.
+    /// `origin_attrs` names segment columns of the origin to add to every row,
+    /// prefixed `origin_` (requires loaded segments). `segment_id`, repeated names and
+    /// names whose `origin_` column the trace already has are rejected before tracing.
+    /// `distance_metric` ("hops" or "neglog_factor") adds a `trace_distance` column with
+    /// the shortest-path distance from the origin (see `DistanceMetric`), over the
+    /// paths `stop_ids` and `min_factor` leave.
+    /// `stop_ids` are segments not traced past, e.g. harvest segments at a regulatory
+    /// boundary: they appear in the result, but nothing beyond them does.
+    /// `drop_zero_factor` omits non-identity rows whose four factors are all zero.
+    /// `min_factor` stops following transfers that move less than that share of count
+    /// (`share_count_forward` downstream, `share_count_backward` upstream).
+    /// `detail=true` adds `n_paths`, `min_path_factor` and `max_path_factor`: how many
+    /// transfer paths connect origin and traced segment, and the range of their
+    /// individual share_count products in the trace direction.
+    /// `as_result=true` returns a `TraceResult` instead of the bare frame. The frame
+    /// stays the default because existing callers pass the return value straight to
+    /// polars and `aggregate_trace`; returning a `TraceResult` unasked would break them.
+    /// Options left out fall back to those set with `configure_trace`.
+    #[pyo3(signature = (
+        origin_df,
+        progress_callback=None,
+        origin_attrs=None,
+        distance_metric=None,
+        stop_ids=None,
+        drop_zero_factor=None,
+        min_factor=None,
+        detail=None,
+        as_result=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn trace_segments(
+        &mut self,
+        py: Python<'_>,
+        origin_df: PyDataFrame,
+        progress_callback: Option<PyObject>,
+        origin_attrs: Option<Vec<String>>,
+        distance_metric: Option<&str>,
+        stop_ids: Option<Vec<String>>,
+        drop_zero_factor: Option<bool>,
+        min_factor: Option<f64>,
+        detail: Option<bool>,
+        as_result: bool,
+    ) -> PyResult<PyObject> {
+        let mut options = self.trace_defaults.clone();
+        if let Some(metric) = distance_metric {
+            options.distance_metric = Some(DistanceMetric::parse(metric)?);
+        }
+        if origin_attrs.is_some() {
+            options.origin_attrs = origin_attrs;
+        }
+        if let Some(stop_ids) = stop_ids {
+            options.stop_ids = stop_ids;
+        }
+        if let Some(drop_zero_factor) = drop_zero_factor {
+            options.drop_zero_factor = drop_zero_factor;
+        }
+        if min_factor.is_some() {
+            options.min_factor = min_factor;
+        }
+        if let Some(detail) = detail {
+            options.detail = detail;
         }
 
-        let dt: NaiveDateTime = timestamp.extract()?;
-        let timestamp_us = dt.and_utc().timestamp_micros();
+        let ids = Self::origin_ids(&origin_df.0)?;
+        let mut callback_error = None;
+        let result = self.trace_with_options(
+            &ids,
+            &options,
+            Self::progress_reporter(py, progress_callback.as_ref(), &mut callback_error),
+        )?;
+        if let Some(e) = callback_error {
+            return Err(e);
+        }
+        if as_result {
+            return Ok(Py::new(py, TraceResult { df: result })?.into_any());
+        }
+        Ok(PyDataFrame(result).into_pyobject(py)?.unbind())
+    }
+
+    /// Set default `trace_segments` options for this model, e.g.
+    /// `configure_trace(origin_attrs=["site"], distance_metric="hops")`.
+    ///
+    /// Later `trace_segments` calls use these unless they pass the option themselves.
+    /// Each call replaces all defaults; `configure_trace()` clears them.
+    ///
+    /// Every `trace_segments` option but `progress_callback` and `as_result` can be set.
+    /// The tracer has no depth, path-count or direction limit to configure: it always
+    /// follows every path both ways and emits the identity row, so keep one direction
+    /// or drop identity rows by filtering the `direction` column, and check
+    /// `path_count` for pairs that would be slow to trace.
+    #[pyo3(signature = (
+        *,
+        origin_attrs=None,
+        distance_metric=None,
+        stop_ids=None,
+        drop_zero_factor=false,
+        min_factor=None,
+        detail=false,
+    ))]
+    fn configure_trace(
+        &mut self,
+        origin_attrs: Option<Vec<String>>,
+        distance_metric: Option<&str>,
+        stop_ids: Option<Vec<String>>,
+        drop_zero_factor: bool,
+        min_factor: Option<f64>,
+        detail: bool,
+    ) -> PyResult<()> {
+        self.trace_defaults = TraceOptions {
+            origin_attrs,
+            distance_metric: distance_metric.map(DistanceMetric::parse).transpose()?,
+            stop_ids: stop_ids.unwrap_or_default(),
+            drop_zero_factor,
+            min_factor,
+            detail,
+        };
+        Ok(())
+    }
+
+    /// Drop all cached `trace_segments` results.
+    fn clear_trace_cache(&mut self) {
+        self.trace_cache.clear();
+    }
+
+    /// Trace `origin_ids` without leaving `scope_ids`.
+    ///
+    /// Only transfers with both ends in the scope are followed, e.g. to get the lineage
+    /// within one site. Origins outside the scope yield just their identity row.
+    /// Uses the `configure_trace` defaults; results are not cached.
+    fn trace_within(&mut self, origin_ids: Vec<String>, scope_ids: Vec<String>) -> PyResult<PyDataFrame> {
+        Ok(PyDataFrame(self.trace_scoped(&origin_ids, &scope_ids)?))
+    }
+
+    /// Number of simple paths from `source_segment_id` to `target_segment_id`.
+    ///
+    /// Tracing enumerates every path between an origin and each traced segment, so a
+    /// large count here means that pair will be slow to trace.
+    fn path_count(&mut self, source_segment_id: &str, target_segment_id: &str) -> PyResult<usize> {
+        let tracer = self.get_or_build_tracer()?;
+        Ok(tracer.path_count(source_segment_id, target_segment_id))
+    }
+
+    /// The nearest common ancestor of two segments: the segment both descend from with
+    /// the fewest transfers back from each (summed). Either segment can be the answer
+    /// if it is an ancestor of the other. None if they share no ancestor.
+    fn lowest_common_ancestor(&mut self, id_a: &str, id_b: &str) -> PyResult<Option<String>> {
+        Ok(self.get_or_build_tracer()?.lowest_common_ancestor(id_a, id_b))
+    }
+
+    /// Describe the lineage between `origin_id` and `target_id` in plain text.
+    ///
+    /// The target may be downstream or upstream of the origin. The first line gives the
+    /// total shares over all paths, followed by each path and the shares moved on each
+    /// of its transfers, e.g. "S3 received 60.0% (by count) and 55.0% (by biomass) of
+    /// its fish from S1 via 2 paths.".
+    fn explain_trace(&mut self, origin_id: &str, target_id: &str) -> PyResult<String> {
+        let tracer = self.get_or_build_tracer()?;
+        Ok(Self::explain_lineage(tracer, origin_id, target_id))
+    }
+
+    /// The four share factors the graph holds for the `source_id` → `dest_id` edge,
+    /// after factor derivation and any merges.
+    ///
+    /// Returns a one-row frame with the factor columns, or an empty frame if there is
+    /// no such edge.
+    fn edge_factors(&mut self, source_id: &str, dest_id: &str) -> PyResult<PyDataFrame> {
+        let values = self.get_or_build_tracer()?.edge_factors(source_id, dest_id);
+        let columns: Vec<Column> = factors::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let v: Vec<f64> = values.iter().map(|vals| vals[i]).collect();
+                Column::new((*name).into(), v)
+            })
+            .collect();
+        let df = DataFrame::new(columns).map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
 
+    /// Everything known about the `source_id` → `dest_id` transfer in one frame.
+    ///
+    /// The transfer row(s) with their stock and factors, joined with the attributes of
+    /// both endpoint segments (prefixed `source_` / `dest_`) and, when containers are
+    /// loaded, of their containers (prefixed `source_container_` / `dest_container_`).
+    /// Empty if there is no such transfer.
+    fn transfer_context(&self, source_id: &str, dest_id: &str) -> PyResult<PyDataFrame> {
+        let transfers = &self.loaded_transfers()?;
         let segments = self
             .segments
             .as_ref()
-            .ok_or_else(|| SdtError::NotLoaded("segments".into()))
-            .map_err(SdtError::from)?;
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
 
-        let df = segments
+        let mut lf = transfers.clone().lazy().filter(
+            col(transfer::SOURCE_SEGMENT_ID)
+                .eq(lit(source_id))
+                .and(col(transfer::DEST_SEGMENT_ID).eq(lit(dest_id))),
+        );
+        for (end, id_col) in [
+            ("source", transfer::SOURCE_SEGMENT_ID),
+            ("dest", transfer::DEST_SEGMENT_ID),
+        ] {
+            lf = lf.join(
+                Self::prefixed(segments, segment::SEGMENT_ID, end),
+                [col(id_col)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Left),
+            );
+            if let Some(containers) = &self.containers {
+                lf = lf.join(
+                    Self::prefixed(containers, container::CONTAINER_ID, &format!("{end}_container")),
+                    [col(format!("{end}_{}", segment::CONTAINER_ID))],
+                    [col(container::CONTAINER_ID)],
+                    JoinArgs::new(JoinType::Left),
+                );
+            }
+        }
+        let df = lf.collect().map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    /// Total stock moved between each pair of containers, as a wide matrix.
+    ///
+    /// Transfers are mapped to the containers of their source and dest segments and
+    /// `by` ("biomass" or "count") is summed per container pair. One row per source
+    /// container (`source_container_id`) and one column per dest container, both
+    /// sorted by id; pairs with no transfers are 0. Transfers whose segments have no
+    /// container are left out. Null stock values count as 0 in a pair's sum; if every
+    /// transfer's stock is null the call fails instead. A dest container whose id is
+    /// `source_container_id` would clash with the key column and is rejected.
+    #[pyo3(signature = (by="biomass"))]
+    fn biomass_flow_matrix(&self, by: &str) -> PyResult<PyDataFrame> {
+        let stock_col = match by.parse::<AggregateBy>()? {
+            AggregateBy::Count => transfer::TRANSFER_COUNT,
+            _ => transfer::TRANSFER_BIOMASS_KG,
+        };
+        let transfers = &self.loaded_transfers()?;
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        Self::require_stock_columns(transfers, "biomass_flow_matrix")?;
+        let stock = transfers.column(stock_col).map_err(SdtError::from)?;
+        if stock.null_count() == stock.len() && !stock.is_empty() {
+            return Err(SdtError::Validation(format!(
+                "Transfers have no '{stock_col}' values to sum"
+            ))
+            .into());
+        }
+
+        let source_container = "source_container_id";
+        let dest_container = "dest_container_id";
+        let container_of = |alias: &str| {
+            segments
+                .clone()
+                .lazy()
+                .select([col(segment::SEGMENT_ID), col(segment::CONTAINER_ID).alias(alias)])
+        };
+        let flows = transfers
             .clone()
             .lazy()
-            .filter(
-                col(segment::START_TIME).lt_eq(lit(timestamp_us)).and(
-                    col(segment::END_TIME)
-                        .gt(lit(timestamp_us))
-                        .or(col(segment::END_TIME).is_null()),
-                ),
+            .join(
+                container_of(source_container),
+                [col(transfer::SOURCE_SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            .join(
+                container_of(dest_container),
+                [col(transfer::DEST_SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Inner),
             )
+            .select([col(source_container), col(dest_container), col(stock_col)])
+            .filter(col(source_container).is_not_null().and(col(dest_container).is_not_null()))
             .collect()
             .map_err(SdtError::from)?;
 
+        let mut dests: Vec<String> = flows
+            .column(dest_container)
+            .and_then(|c| c.unique())
+            .and_then(|c| c.str().map(|ca| ca.into_iter().flatten().map(String::from).collect()))
+            .map_err(SdtError::from)?;
+        dests.sort_unstable();
+        if dests.iter().any(|d| d == source_container) {
+            return Err(SdtError::Validation(format!(
+                "Container id '{source_container}' clashes with the matrix's key column"
+            ))
+            .into());
+        }
+        let pivoted: Vec<Expr> = dests
+            .iter()
+            .map(|d| {
+                col(stock_col)
+                    .filter(col(dest_container).eq(lit(d.as_str())))
+                    .sum()
+                    .alias(d.as_str())
+            })
+            .collect();
+        let df = flows
+            .lazy()
+            .group_by([col(source_container)])
+            .agg(pivoted)
+            .sort([source_container], SortMultipleOptions::default())
+            .collect()
+            .map_err(SdtError::from)?;
         Ok(PyDataFrame(df))
     }
 
-    fn get_segments_incoming(&self) -> PyResult<PyDataFrame> {
+    /// Total stock transferred into `container_id` with a transfer time in
+    /// `start <= t < end`, i.e. transfers whose dest segment is in the container.
+    ///
+    /// The transfer time is the source segment's end_time, falling back to the dest
+    /// segment's start_time, as in the visualization. Returns one row with the summed
+    /// `transfer_count` and `transfer_biomass_kg` and the number of transfers.
+    fn flow_into(
+        &self,
+        container_id: &str,
+        start: Bound<PyDateTime>,
+        end: Bound<PyDateTime>,
+    ) -> PyResult<PyDataFrame> {
+        let window = (Self::naive_timestamp_us(&start)?, Self::naive_timestamp_us(&end)?);
+        Ok(PyDataFrame(self.container_flow(container_id, transfer::DEST_SEGMENT_ID, window)?))
+    }
+
+    /// Total stock transferred out of `container_id` with a transfer time in
+    /// `start <= t < end`, i.e. transfers whose source segment is in the container.
+    /// Counterpart of `flow_into`.
+    fn flow_out_of(
+        &self,
+        container_id: &str,
+        start: Bound<PyDateTime>,
+        end: Bound<PyDateTime>,
+    ) -> PyResult<PyDataFrame> {
+        let window = (Self::naive_timestamp_us(&start)?, Self::naive_timestamp_us(&end)?);
+        Ok(PyDataFrame(self.container_flow(container_id, transfer::SOURCE_SEGMENT_ID, window)?))
+    }
+
+    /// The loaded transfers plus a `transfer_time` Datetime column: the source segment's
+    /// end_time, falling back to the dest segment's start_time. This is the time the
+    /// chart draws the transfer at and `flow_into` / `flow_out_of` filter on; null when
+    /// neither segment is loaded.
+    fn transfers_with_time(&self) -> PyResult<PyDataFrame> {
+        let transfers = &self.loaded_transfers()?;
         let segments = self
             .segments
             .as_ref()
-            .ok_or(SdtError::NotLoaded("segments".into()))
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+
+        let times = visualization::transfer_times_us(transfers, segments)?;
+        // Keep the segments' time zone so the column compares with their times
+        let end_times = segments.column(segment::END_TIME).map_err(SdtError::from)?;
+        let time_zone = match end_times.dtype() {
+            DataType::Datetime(_, tz) => tz.clone(),
+            _ => None,
+        };
+        let times = Series::new(transfer::TRANSFER_TIME.into(), times)
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, time_zone))
             .map_err(SdtError::from)?;
-        let transfers = self
-            .transfers
-            .as_ref()
-            .ok_or(SdtError::NotLoaded("transfers".into()))
+        let mut df = transfers.clone();
+        df.with_column(times).map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    /// Trace the given origins and serialize the result as nested JSON.
+    ///
+    /// One object per origin (in the order given) with a `traced` list holding each
+    /// traced segment's id, direction and four share factors. Traces like
+    /// `trace_segments` with the `configure_trace` defaults.
+    fn trace_to_json(&mut self, ids: Vec<String>) -> PyResult<String> {
+        let options = self.trace_defaults.clone();
+        let df = self.trace_with_options(&ids, &options, |_, _| {})?;
+        Ok(Self::nest_trace(&df)?)
+    }
+
+    /// Which segments each origin reaches, as a wide boolean frame.
+    ///
+    /// One row per segment reached by any origin (the origins included), sorted by
+    /// `traced_segment_id`, and one column per origin id, true where that origin
+    /// reaches the row's segment. `direction` is "forward" (downstream, default),
+    /// "backward" (upstream) or "both". Only reachability is computed, so this is much
+    /// cheaper than deriving the same from `trace_segments`. Repeated ids give one
+    /// column; an id equal to `traced_segment_id` is rejected.
+    #[pyo3(signature = (ids, direction="forward"))]
+    fn reachability_matrix(&mut self, ids: Vec<String>, direction: &str) -> PyResult<PyDataFrame> {
+        let directions: &[Direction] = match direction {
+            direction::FORWARD => &[Direction::Outgoing],
+            direction::BACKWARD => &[Direction::Incoming],
+            "both" => &[Direction::Outgoing, Direction::Incoming],
+            other => {
+                return Err(SdtError::Validation(format!(
+                    "Invalid direction '{other}'. Must be 'forward', 'backward' or 'both'"
+                ))
+                .into())
+            }
+        };
+        if ids.iter().any(|id| id == traceability::TRACED_SEGMENT_ID) {
+            return Err(SdtError::Validation(format!(
+                "Origin id '{}' would collide with the row id column",
+                traceability::TRACED_SEGMENT_ID
+            ))
+            .into());
+        }
+        let tracer = self.get_or_build_tracer()?;
+
+        // First occurrence of each id, keeping the given order
+        let mut seen: HashSet<&str> = HashSet::new();
+        let origins: Vec<&str> = ids
+            .iter()
+            .map(String::as_str)
+            .filter(|id| seen.insert(id))
+            .collect();
+        let reached: Vec<HashSet<&str>> = origins
+            .iter()
+            .map(|&origin| {
+                let mut set: HashSet<&str> = directions
+                    .iter()
+                    .flat_map(|&dir| tracer.reachable_ids(origin, dir))
+                    .collect();
+                set.insert(origin);
+                set
+            })
+            .collect();
+        let rows: BTreeSet<&str> = reached.iter().flatten().copied().collect();
+
+        let mut columns = vec![Column::new(
+            traceability::TRACED_SEGMENT_ID.into(),
+            rows.iter().copied().collect::<Vec<_>>(),
+        )];
+        for (origin, set) in origins.iter().zip(&reached) {
+            let cells: Vec<bool> = rows.iter().map(|row| set.contains(row)).collect();
+            columns.push(Column::new((*origin).into(), cells));
+        }
+        let df = DataFrame::new(columns).map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    /// Segments with no incoming transfers, as a single `segment_id` column.
+    fn root_segments(&mut self) -> PyResult<PyDataFrame> {
+        let ids = self.get_or_build_tracer()?.roots();
+        let df = DataFrame::new(vec![Column::new(segment::SEGMENT_ID.into(), ids)])
             .map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
 
-        let dest_segments = transfers
-            .column(transfer::DEST_SEGMENT_ID)
-            .map_err(SdtError::from)?
-            .as_materialized_series()
-            .clone();
+    /// Segments with no outgoing transfers, as a single `segment_id` column.
+    fn leaf_segments(&mut self) -> PyResult<PyDataFrame> {
+        let ids = self.get_or_build_tracer()?.leaves();
+        let df = DataFrame::new(vec![Column::new(segment::SEGMENT_ID.into(), ids)])
+            .map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
 
-        let df = segments
+    /// Trace origins one at a time, yielding one traceability frame per origin.
+    ///
+    /// Same rows as `trace_segments` with the `configure_trace` defaults, but nothing is
+    /// concatenated up front, so long runs can report progress and write results
+    /// incrementally. The tracer is built before the iterator is returned, so loading
+    /// errors surface immediately.
+    fn trace_iter(slf: Bound<'_, Self>, ids: Vec<String>) -> PyResult<TraceIter> {
+        slf.borrow_mut().get_or_build_tracer()?;
+        Ok(TraceIter {
+            model: slf.unbind(),
+            ids,
+            pos: 0,
+        })
+    }
+
+    // ── Validation ──────────────────────────────────────────────────────────
+
+    /// Check that a traceability frame can feed weighted aggregations, e.g. one built
+    /// outside this library: a string `direction` column (forward/backward/identity)
+    /// and the four share factor columns as Float64. Raises listing every problem.
+    #[staticmethod]
+    fn validate_trace_frame(df: PyDataFrame) -> PyResult<()> {
+        validate_trace_frame(&df.0)?;
+        Ok(())
+    }
+
+    /// Describe the input files the loaders expect: per file its required and optional
+    /// columns, their types after loading and which the loader derives when absent.
+    ///
+    /// Returns a dict keyed by file kind ("transfers", "segments", ...), built from the
+    /// same schema constants the loaders use, e.g. for onboarding docs or checking
+    /// uploads before loading them.
+    #[staticmethod]
+    fn expected_schema(py: Python<'_>) -> PyResult<PyObject> {
+        let json = serde_json::to_string(&Self::input_file_specs())
+            .map_err(|e| SdtError::General(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+    }
+
+    /// Check that share factors on each transfer are consistent with each other and the stock.
+    ///
+    /// For rows with stock values, each factor must match the stock share it implies, i.e.
+    /// `share_count_forward * source_total == transfer_count == share_count_backward * dest_total`
+    /// (and the same for biomass). For all rows, forward factors must sum to 1 per source
+    /// segment and backward factors must sum to 1 per destination segment. Transfers
+    /// loaded without stock columns (`create_missing_stock=False`) only get the sum checks.
+    ///
+    /// Returns the offending transfer rows with a `reciprocity_issue` column naming the
+    /// failed checks. An empty frame means the factors are consistent.
+    #[pyo3(signature = (tolerance=1e-6))]
+    fn check_factor_reciprocity(&self, tolerance: f64) -> PyResult<PyDataFrame> {
+        let transfers = &self.loaded_transfers()?;
+
+        let src = || [col(transfer::SOURCE_SEGMENT_ID)];
+        let dst = || [col(transfer::DEST_SEGMENT_ID)];
+        let off = |actual: Expr, expected: Expr| (actual - expected).abs().gt(lit(tolerance));
+
+        let checks: Vec<(&str, Expr)> = vec![
+            (
+                factors::SHARE_COUNT_FORWARD,
+                off(
+                    col(factors::SHARE_COUNT_FORWARD),
+                    col(transfer::TRANSFER_COUNT) / col(transfer::TRANSFER_COUNT).sum().over(src()),
+                ),
+            ),
+            (
+                factors::SHARE_BIOMASS_FORWARD,
+                off(
+                    col(factors::SHARE_BIOMASS_FORWARD),
+                    col(transfer::TRANSFER_BIOMASS_KG)
+                        / col(transfer::TRANSFER_BIOMASS_KG).sum().over(src()),
+                ),
+            ),
+            (
+                factors::SHARE_COUNT_BACKWARD,
+                off(
+                    col(factors::SHARE_COUNT_BACKWARD),
+                    col(transfer::TRANSFER_COUNT) / col(transfer::TRANSFER_COUNT).sum().over(dst()),
+                ),
+            ),
+            (
+                factors::SHARE_BIOMASS_BACKWARD,
+                off(
+                    col(factors::SHARE_BIOMASS_BACKWARD),
+                    col(transfer::TRANSFER_BIOMASS_KG)
+                        / col(transfer::TRANSFER_BIOMASS_KG).sum().over(dst()),
+                ),
+            ),
+            (
+                "share_count_forward_sum",
+                off(col(factors::SHARE_COUNT_FORWARD).sum().over(src()), lit(1.0)),
+            ),
+            (
+                "share_biomass_forward_sum",
+                off(col(factors::SHARE_BIOMASS_FORWARD).sum().over(src()), lit(1.0)),
+            ),
+            (
+                "share_count_backward_sum",
+                off(col(factors::SHARE_COUNT_BACKWARD).sum().over(dst()), lit(1.0)),
+            ),
+            (
+                "share_biomass_backward_sum",
+                off(col(factors::SHARE_BIOMASS_BACKWARD).sum().over(dst()), lit(1.0)),
+            ),
+        ];
+
+        // Null comparisons (rows without stock) fall through to null and are skipped
+        let issue_parts: Vec<Expr> = checks
+            .into_iter()
+            .map(|(name, failed)| when(failed).then(lit(name)).otherwise(lit(NULL)))
+            .collect();
+
+        // Without stock columns (create_missing_stock=False) only the sums are checked
+        let schema = transfers.schema();
+        let missing_stock: Vec<&str> = [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]
+            .into_iter()
+            .filter(|c| !schema.contains(c))
+            .collect();
+        let placeholders: Vec<Expr> = missing_stock
+            .iter()
+            .map(|c| lit(NULL).cast(DataType::Float64).alias(*c))
+            .collect();
+        let df = transfers
             .clone()
             .lazy()
-            .filter(
-                col(segment::SEGMENT_ID)
-                    .is_in(lit(dest_segments), false)
-                    .not(),
-            )
+            .with_columns(placeholders)
+            .with_column(concat_str(issue_parts, ", ", true).alias("reciprocity_issue"))
+            .filter(col("reciprocity_issue").str().len_chars().gt(lit(0)))
+            .drop(cols(missing_stock))
             .collect()
             .map_err(SdtError::from)?;
 
         Ok(PyDataFrame(df))
     }
 
-    fn get_segments_outgoing(&self) -> PyResult<PyDataFrame> {
+    /// Adjacent segments within a container that leave a gap in the timeline.
+    ///
+    /// Segments are sorted per container by `start_time`; each consecutive pair whose
+    /// `next.start_time - prev.end_time` exceeds `min_gap_us` is returned with
+    /// `container_id`, `prev_segment_id`, `next_segment_id`, `prev_end_time`,
+    /// `next_start_time` and `gap_us`. Segments without an end_time are not compared.
+    #[pyo3(signature = (min_gap_us=0))]
+    fn segments_with_gaps(&self, min_gap_us: i64) -> PyResult<PyDataFrame> {
         let segments = self
             .segments
             .as_ref()
-            .ok_or(SdtError::NotLoaded("segments".into()))
-            .map_err(SdtError::from)?;
-        let transfers = self
-            .transfers
-            .as_ref()
-            .ok_or(SdtError::NotLoaded("transfers".into()))
-            .map_err(SdtError::from)?;
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
 
-        let source_segments = transfers
-            .column(transfer::SOURCE_SEGMENT_ID)
-            .map_err(SdtError::from)?
-            .as_materialized_series()
-            .clone();
+        let by_container = || [col(segment::CONTAINER_ID)];
+        let prev = |name: &str| col(name).shift(lit(1)).over(by_container());
+        let gap_us = Self::column_us(segment::START_TIME) - Self::column_us("prev_end_time");
 
         let df = segments
             .clone()
             .lazy()
-            .filter(
-                col(segment::SEGMENT_ID)
-                    .is_in(lit(source_segments), false)
-                    .not(),
+            .sort(
+                [segment::CONTAINER_ID, segment::START_TIME],
+                SortMultipleOptions::default(),
             )
+            .with_columns([
+                prev(segment::SEGMENT_ID).alias("prev_segment_id"),
+                prev(segment::END_TIME).alias("prev_end_time"),
+            ])
+            .with_column(gap_us.alias("gap_us"))
+            .filter(col("gap_us").gt(lit(min_gap_us)))
+            .select([
+                col(segment::CONTAINER_ID),
+                col("prev_segment_id"),
+                col(segment::SEGMENT_ID).alias("next_segment_id"),
+                col("prev_end_time"),
+                col(segment::START_TIME).alias("next_start_time"),
+                col("gap_us"),
+            ])
             .collect()
             .map_err(SdtError::from)?;
 
         Ok(PyDataFrame(df))
     }
 
-    // ── Data |ing ────────────────────────────────────────────────────────
+    /// Check that the loaded transfers form a DAG without building the tracer.
+    ///
+    /// Returns None when there is no cycle, otherwise the transfer rows that lie on
+    /// a cycle (including self-loops).
+    fn validate_acyclic(&self) -> PyResult<Option<PyDataFrame>> {
+        let transfers = &self.loaded_transfers()?;
 
-    /// Merge traced segment data with time-series or other segment-level data.
-    #[staticmethod]
-    fn add_data_to_trace(
-        segment_data: PyDataFrame,
-        traceability_index: PyDataFrame,
-    ) -> PyResult<PyDataFrame> {
-        let df = traceability_index
-            .0
+        let rows = cyclic_transfer_rows(transfers)?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let idx = IdxCa::from_vec("idx".into(), rows);
+        let cycle = transfers.take(&idx).map_err(SdtError::from)?;
+        Ok(Some(PyDataFrame(cycle)))
+    }
+
+    // ── Derived columns ─────────────────────────────────────────────────────
+
+    /// Segments with their lifespan (`end_time - start_time`) added as
+    /// `lifespan_us` (Int64 microseconds) and `lifespan_days` (Float64).
+    ///
+    /// Segments without an end_time are measured up to `as_of` when given,
+    /// otherwise their lifespan is null.
+    #[pyo3(signature = (as_of=None))]
+    fn segment_lifespan(&self, as_of: Option<Bound<PyDateTime>>) -> PyResult<PyDataFrame> {
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+
+        let end_us = match as_of {
+            Some(ts) => {
+                let as_of_us = Self::naive_timestamp_us(&ts)?;
+                Self::column_us(segment::END_TIME).fill_null(lit(as_of_us))
+            }
+            None => Self::column_us(segment::END_TIME),
+        };
+        let lifespan_us = end_us - Self::column_us(segment::START_TIME);
+
+        let df = segments
+            .clone()
             .lazy()
-            .join(
-                segment_data.0.lazy(),
-                [col(traceability::TRACED_SEGMENT_ID)],
-                [col(segment::SEGMENT_ID)],
-                JoinArgs::new(JoinType::Left),
-            )
+            .with_columns([
+                lifespan_us.clone().alias(segment::LIFESPAN_US),
+                (lifespan_us.cast(DataType::Float64) / lit(MICROS_PER_DAY))
+                    .alias(segment::LIFESPAN_DAYS),
+            ])
             .collect()
             .map_err(SdtError::from)?;
 
         Ok(PyDataFrame(df))
     }
 
-    /// Map container-level timeseries to segments.
-    /// Joins on container_id and filters to each segment's active period.
-    ///
-    /// A row matches if:
-    ///   segment.container_id == container_data.container_id
-    ///   AND segment.start_time <= date_time < segment.end_time
-    ///   (null end_time means still active)
+    /// Attach a derived attribute (e.g. a risk score) to the loaded segments, so later
+    /// calls such as `visualize_trace` or `transfer_context` see it.
     ///
-    /// The date_time column must be parsed to Datetime before calling this method.
-    #[pyo3(signature = (container_data, include_unmatched=true, allow_multiple=true))]
-    fn map_container_data_to_segments(
-        &self,
-        container_data: PyDataFrame,
-        include_unmatched: bool,
-        allow_multiple: bool,
+    /// `values` holds `segment_id` and a `name` column with at most one row per
+    /// segment; segments without a row get null. Ids unknown to the model raise
+    /// `ValidationError` with those rows. An existing column is only replaced with
+    /// `replace=true`, and then must keep its dtype. Returns the updated segments.
+    #[pyo3(signature = (name, values, replace=false))]
+    fn add_segment_column(
+        &mut self,
+        name: &str,
+        values: PyDataFrame,
+        replace: bool,
     ) -> PyResult<PyDataFrame> {
         let segments = self
             .segments
             .as_ref()
-            .ok_or(SdtError::NotLoaded("segments".into()))
-            .map_err(SdtError::from)?;
-
-        let input_cols: Vec<String> = container_data
-            .0
-            .get_column_names_str()
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-
-        // Join and filter to active period
-        let join_type = if include_unmatched {
-            JoinType::Left
-        } else {
-            JoinType::Inner
-        };
-
-        let mut output_cols: Vec<Expr> = input_cols.iter().map(|c| col(c)).collect();
-        output_cols.push(col(segment::SEGMENT_ID));
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let values = values.0;
+        Self::require_columns(&values, &[segment::SEGMENT_ID, name])?;
+        if name == segment::SEGMENT_ID {
+            return Err(SdtError::Validation("Cannot replace the segment_id column".into()).into());
+        }
+        let new_dtype = values.column(name).map_err(SdtError::from)?.dtype();
+        if let Ok(existing) = segments.column(name) {
+            if !replace {
+                return Err(SdtError::Validation(format!(
+                    "Segments already have a '{name}' column; pass replace=True to overwrite it"
+                ))
+                .into());
+            }
+            if existing.dtype() != new_dtype {
+                return Err(SdtError::Validation(format!(
+                    "Column '{name}' is {}, values are {new_dtype}",
+                    existing.dtype()
+                ))
+                .into());
+            }
+        }
 
-        let matched = container_data
-            .0
+        let values = values
+            .lazy()
+            .select([col(segment::SEGMENT_ID).cast(DataType::String), col(name)])
+            .collect()
+            .map_err(SdtError::from)?;
+        let ids = values.column(segment::SEGMENT_ID).map_err(SdtError::from)?;
+        let duplicates = ids.len() - ids.n_unique().map_err(SdtError::from)?;
+        if duplicates > 0 {
+            return Err(SdtError::Validation(format!(
+                "values has {duplicates} duplicate segment_id rows; give one value per segment"
+            ))
+            .into());
+        }
+        let unknown = values
+            .clone()
             .lazy()
             .join(
-                segments.clone().lazy(),
-                [col(container::CONTAINER_ID)],
-                [col(segment::CONTAINER_ID)],
-                JoinArgs::new(join_type),
+                segments.clone().lazy().select([col(segment::SEGMENT_ID)]),
+                [col(segment::SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Anti),
             )
-            .filter(
-                // start_time <= date_time
-                col(segment::START_TIME)
-                    .lt_eq(col(timeseries::DATE_TIME))
-                    .and(
-                        // date_time < end_time OR end_time is null (still active)
-                        col(segment::END_TIME)
-                            .is_null()
-                            .or(col(timeseries::DATE_TIME).lt(col(segment::END_TIME))),
-                    )
-                    // Also keep unmatched rows (where segment columns are null)
-                    .or(col(segment::SEGMENT_ID).is_null()),
+            .collect()
+            .map_err(SdtError::from)?;
+        if unknown.height() > 0 {
+            return Err(SdtError::InvalidRows {
+                message: format!("{} segment_id values are not loaded segments", unknown.height()),
+                column: Some(segment::SEGMENT_ID.to_string()),
+                rows: unknown,
+            }
+            .into());
+        }
+
+        let mut updated = segments.clone();
+        if updated.column(name).is_ok() {
+            updated = updated.drop(name).map_err(SdtError::from)?;
+        }
+        let updated = updated
+            .lazy()
+            .join(
+                values.lazy(),
+                [col(segment::SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs {
+                    // Keep the segments in load order
+                    maintain_order: MaintainOrderJoin::Left,
+                    ..JoinArgs::new(JoinType::Left)
+                },
             )
-            .select(output_cols)
             .collect()
             .map_err(SdtError::from)?;
+        self.segments = Some(updated.clone());
+        Ok(PyDataFrame(updated))
+    }
 
-        // Check for multiple matches if not allowed
-        if !allow_multiple {
-            let counts = matched
-                .clone()
-                .lazy()
-                .filter(col(segment::SEGMENT_ID).is_not_null())
-                .group_by(input_cols.iter().map(|c| col(c)).collect::<Vec<_>>())
-                .agg([col(segment::SEGMENT_ID).count().alias("_match_count")])
-                .filter(col("_match_count").gt(lit(1)))
-                .collect()
-                .map_err(SdtError::from)?;
+    // ── Filtering ───────────────────────────────────────────────────────────
 
-            if counts.height() > 0 {
+    /// Segments active at `timestamp`, with endpoints handled per `interval_closed`.
+    fn get_segments_active_at(&self, timestamp: Bound<PyDateTime>) -> PyResult<PyDataFrame> {
+        let timestamp_us = Self::naive_timestamp_us(&timestamp)?;
+
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+
+        let df = segments
+            .clone()
+            .lazy()
+            .filter(self.interval_closed.contains(lit(timestamp_us), None))
+            .collect()
+            .map_err(SdtError::from)?;
+
+        Ok(PyDataFrame(df))
+    }
+
+    /// Number of active segments at regular ticks, e.g. for a capacity utilization chart.
+    ///
+    /// Ticks run from `start` to `end` inclusive, stepping by `freq` (a Polars duration
+    /// such as "1d", "6h" or "1mo"). Defaults are the earliest segment start and the
+    /// latest segment start or end. A segment counts at a tick when the tick is within
+    /// its interval per `interval_closed`, as in `get_segments_active_at`.
+    /// Returns `date_time` and `active_count` columns.
+    #[pyo3(signature = (freq, start=None, end=None))]
+    fn segments_active_histogram(
+        &self,
+        freq: &str,
+        start: Option<Bound<PyDateTime>>,
+        end: Option<Bound<PyDateTime>>,
+    ) -> PyResult<PyDataFrame> {
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let step = Duration::try_parse(freq).map_err(SdtError::from)?;
+        if step.negative() || step.is_zero() {
+            return Err(SdtError::Validation(format!("freq '{freq}' must be a positive duration")).into());
+        }
+
+        // Segment bounds as microseconds, so ticks compare as plain integers
+        let bounds = segments
+            .clone()
+            .lazy()
+            .select([
+                Self::column_us(segment::START_TIME).alias(segment::START_TIME),
+                Self::column_us(segment::END_TIME).alias(segment::END_TIME),
+            ])
+            .filter(col(segment::START_TIME).is_not_null())
+            .collect()
+            .map_err(SdtError::from)?;
+        let as_us = |name: &str| -> Result<Vec<Option<i64>>, SdtError> {
+            Ok(bounds.column(name)?.i64()?.into_iter().collect())
+        };
+        let intervals: Vec<(i64, Option<i64>)> = as_us(segment::START_TIME)?
+            .into_iter()
+            .zip(as_us(segment::END_TIME)?)
+            .filter_map(|(s, e)| Some((s?, e)))
+            .collect();
+
+        let start_us = match &start {
+            Some(t) => Self::naive_timestamp_us(t)?,
+            None => intervals.iter().map(|(s, _)| *s).min().unwrap_or(0),
+        };
+        let end_us = match &end {
+            Some(t) => Self::naive_timestamp_us(t)?,
+            None => intervals
+                .iter()
+                .map(|(s, e)| e.unwrap_or(*s).max(*s))
+                .max()
+                .unwrap_or(start_us),
+        };
+
+        let mut ticks = Vec::new();
+        let mut t = start_us;
+        while t <= end_us {
+            if ticks.len() == MAX_HISTOGRAM_TICKS {
                 return Err(SdtError::Validation(format!(
-                    "{} rows matched multiple segments while allow_multiple=false",
-                    counts.height()
+                    "freq '{freq}' gives more than {MAX_HISTOGRAM_TICKS} ticks between start and end; use a coarser freq or a shorter range"
                 ))
                 .into());
             }
+            ticks.push(t);
+            t = step.add_us(t, None).map_err(SdtError::from)?;
+        }
+
+        // Sorted bounds, so each tick is counted with binary searches. Zero-duration
+        // segments match only their own instant; segments ending before they start never.
+        let mut starts = Vec::new();
+        let mut ends = Vec::new();
+        let mut instants = Vec::new();
+        for (s, e) in intervals {
+            match e {
+                Some(e) if e == s => instants.push(s),
+                Some(e) if e < s => {}
+                _ => {
+                    starts.push(s);
+                    ends.extend(e);
+                }
+            }
         }
+        starts.sort_unstable();
+        ends.sort_unstable();
+        instants.sort_unstable();
+        let (start_closed, end_closed) = match self.interval_closed {
+            IntervalClosed::Left => (true, false),
+            IntervalClosed::Right => (false, true),
+            IntervalClosed::Both => (true, true),
+            IntervalClosed::Neither => (false, false),
+        };
+        let counts: Vec<i64> = ticks
+            .iter()
+            .map(|&t| {
+                let started = if start_closed {
+                    starts.partition_point(|&s| s <= t)
+                } else {
+                    starts.partition_point(|&s| s < t)
+                };
+                // An interval that has ended by `t` has also started
+                let ended = if end_closed {
+                    ends.partition_point(|&e| e < t)
+                } else {
+                    ends.partition_point(|&e| e <= t)
+                };
+                let at_instant =
+                    instants.partition_point(|&s| s <= t) - instants.partition_point(|&s| s < t);
+                (started - ended + at_instant) as i64
+            })
+            .collect();
+
+        let date_time = Column::new(timeseries::DATE_TIME.into(), ticks)
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+            .map_err(SdtError::from)?;
+        let df = DataFrame::new(vec![date_time, Column::new("active_count".into(), counts)])
+            .map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    fn get_segments_incoming(&self) -> PyResult<PyDataFrame> {
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or(SdtError::NotLoaded("segments".into()))?;
+        let transfers = self
+            .transfers
+            .as_ref()
+            .ok_or(SdtError::NotLoaded("transfers".into()))?;
+
+        let dest_segments = transfers
+            .column(transfer::DEST_SEGMENT_ID)
+            .map_err(SdtError::from)?
+            .as_materialized_series()
+            .clone();
+
+        let df = segments
+            .clone()
+            .lazy()
+            .filter(
+                col(segment::SEGMENT_ID)
+                    .is_in(lit(dest_segments), false)
+                    .not(),
+            )
+            .collect()
+            .map_err(SdtError::from)?;
+
+        Ok(PyDataFrame(df))
+    }
+
+    fn get_segments_outgoing(&self) -> PyResult<PyDataFrame> {
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or(SdtError::NotLoaded("segments".into()))?;
+        let transfers = self
+            .transfers
+            .as_ref()
+            .ok_or(SdtError::NotLoaded("transfers".into()))?;
+
+        let source_segments = transfers
+            .column(transfer::SOURCE_SEGMENT_ID)
+            .map_err(SdtError::from)?
+            .as_materialized_series()
+            .clone();
+
+        let df = segments
+            .clone()
+            .lazy()
+            .filter(
+                col(segment::SEGMENT_ID)
+                    .is_in(lit(source_segments), false)
+                    .not(),
+            )
+            .collect()
+            .map_err(SdtError::from)?;
+
+        Ok(PyDataFrame(df))
+    }
+
+    // ── Data |ing ────────────────────────────────────────────────────────
+
+    /// Merge traced segment data with time-series or other segment-level data.
+    #[staticmethod]
+    fn add_data_to_trace(
+        segment_data: PyDataFrame,
+        traceability_index: PyDataFrame,
+    ) -> PyResult<PyDataFrame> {
+        let df = Self::join_segment_data(traceability_index.0, segment_data.0)?;
+        Ok(PyDataFrame(df))
+    }
 
+    /// Map container-level timeseries to segments.
+    /// Joins on container_id and filters to each segment's active period.
+    ///
+    /// A row matches if:
+    ///   segment.container_id == container_data.container_id
+    ///   AND date_time is within the segment interval per `interval_closed`
+    ///   (by default segment.start_time <= date_time < segment.end_time;
+    ///   null end_time means still active)
+    ///
+    /// `end_inclusive` overrides the end bound for this call: `true` gives
+    /// `date_time <= segment.end_time`, so a reading taken exactly at a transfer matches
+    /// the segment that ends there (and also the one that starts there, if any).
+    ///
+    /// The date_time column must be parsed to Datetime before calling this method.
+    /// Use `time_col` when the timestamp column has another name (default: date_time).
+    #[pyo3(signature = (container_data, include_unmatched=true, allow_multiple=true, time_col=None, end_inclusive=None))]
+    fn map_container_data_to_segments(
+        &self,
+        container_data: PyDataFrame,
+        include_unmatched: bool,
+        allow_multiple: bool,
+        time_col: Option<&str>,
+        end_inclusive: Option<bool>,
+    ) -> PyResult<PyDataFrame> {
+        let matched = self.container_data_segments(
+            container_data.0,
+            include_unmatched,
+            allow_multiple,
+            time_col.unwrap_or(timeseries::DATE_TIME),
+            end_inclusive,
+        )?;
         Ok(PyDataFrame(matched))
     }
 
@@ -541,84 +1911,112 @@ impl SdtModel {
     /// Aggregate traced data using built-in Rust aggregations.
     ///
     /// `aggregations`: list of `Aggregation` objects.
-    /// `group_by`: column names to group by.
+    /// `group_by`: column names to group by. Pass an empty list to aggregate the
+    /// whole frame into a single row. `None` (the default) groups by
+    /// `[origin_segment_id, date_time]`, or just `origin_segment_id` when the frame
+    /// has no `date_time` column (no timeseries joined).
+    ///
+    /// Weighted aggregations pick the weight for each row from that row's own
+    /// direction, so `direction` can be part of `group_by` to get per-direction
+    /// rollups: a forward group uses the forward rules only, a backward group the
+    /// backward rules only. The identity row (the origin itself, weight 1.0) then
+    /// forms its own group; with `identity_in_each_direction=True` it is counted in
+    /// both the forward and the backward group instead.
+    ///
+    /// With `origin_normalize=True` a traced segment reached from several origins has
+    /// its share factors divided by that number of origins, so pooling the origins
+    /// counts its contribution once instead of once per origin.
+    ///
+    /// `traced_data` does not have to come from `trace_segments`: any frame with a
+    /// `direction` column and the share factor columns works, e.g. a traceability
+    /// index maintained elsewhere. Only the factors the aggregations weight by are
+    /// required (the count pair for `aggregate_by="count"`, the biomass pair for
+    /// "biomass"); they are checked like `validate_trace_frame` does.
+    ///
+    /// Aggregations of empty or zero-weight groups give NaN; with `nan_to_null=True`
+    /// (the default) these come back as nulls so Python sees `None`. Pass `False` to
+    /// keep NaN distinct from missing.
+    ///
+    /// `progress_callback(done, total)` is called periodically while aggregating groups.
     #[staticmethod]
-    #[pyo3(signature = (traced_data, aggregations, group_by=None))]
+    #[pyo3(signature = (
+        traced_data,
+        aggregations,
+        group_by=None,
+        identity_in_each_direction=false,
+        progress_callback=None,
+        origin_normalize=false,
+        nan_to_null=true,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn aggregate_traced_data(
+        py: Python<'_>,
         traced_data: PyDataFrame,
         aggregations: Vec<Aggregation>,
         group_by: Option<Vec<String>>,
+        identity_in_each_direction: bool,
+        progress_callback: Option<PyObject>,
+        origin_normalize: bool,
+        nan_to_null: bool,
     ) -> PyResult<PyDataFrame> {
-        use crate::aggregation::apply_builtin_aggregations;
-
-        let group_cols = group_by.unwrap_or_else(|| {
-            vec![
-                traceability::ORIGIN_SEGMENT_ID.to_string(),
-                timeseries::DATE_TIME.to_string(),
-            ]
-        });
-
-        let df = &traced_data.0;
-
-        // Partition into group DataFrames
-        let partitions = df
-            .partition_by(group_cols.as_slice(), true)
-            .map_err(SdtError::from)?;
-
-        // Determine output column names from first group (or return empty)
-        if partitions.is_empty() {
-            return Ok(traced_data);
-        }
-
-        let sample_results =
-            apply_builtin_aggregations(&partitions[0], &aggregations).map_err(SdtError::from)?;
-        let agg_names: Vec<String> = sample_results
-            .iter()
-            .map(|(name, _)| name.clone())
-            .collect();
-
-        // Build column vectors: group key columns + aggregation result columns
-        // Group keys: take first row of each partition
-        let mut key_columns: Vec<Vec<AnyValue>> = vec![vec![]; group_cols.len()];
-        let mut agg_columns: Vec<Vec<AnyValue>> = vec![vec![]; agg_names.len()];
-
-        for partition in &partitions {
-            // Extract group key values from first row
-            for (i, gc) in group_cols.iter().enumerate() {
-                let val = partition
-                    .column(gc)
-                    .map_err(SdtError::from)?
-                    .get(0)
-                    .map_err(SdtError::from)?;
-                key_columns[i].push(val.into_static());
-            }
-
-            // Apply aggregations
-            let results =
-                apply_builtin_aggregations(partition, &aggregations).map_err(SdtError::from)?;
-            for (i, (_name, val)) in results.into_iter().enumerate() {
-                agg_columns[i].push(val);
-            }
-        }
-
-        // Build the output DataFrame
-        let mut columns: Vec<Column> = Vec::new();
-
-        for (i, gc) in group_cols.iter().enumerate() {
-            let series = Series::from_any_values(gc.into(), &key_columns[i], true)
-                .map_err(SdtError::from)?;
-            columns.push(series.into());
-        }
-
-        for (i, name) in agg_names.iter().enumerate() {
-            let series = Series::from_any_values(name.into(), &agg_columns[i], true)
-                .map_err(SdtError::from)?;
-            columns.push(series.into());
+        let mut callback_error = None;
+        let result = Self::aggregate_frame(
+            traced_data.0,
+            &aggregations,
+            group_by,
+            identity_in_each_direction,
+            origin_normalize,
+            nan_to_null,
+            Self::progress_reporter(py, progress_callback.as_ref(), &mut callback_error),
+        )?;
+        if let Some(e) = callback_error {
+            return Err(e);
         }
+        Ok(PyDataFrame(result))
+    }
 
-        let result = DataFrame::new(columns).map_err(SdtError::from)?;
+    /// Trace `origins`, join `data` onto the traced segments and aggregate, in one call.
+    ///
+    /// Gives the same frame as `trace_segments` → `add_data_to_trace` →
+    /// `aggregate_traced_data`, without handing the traced and joined intermediates
+    /// back to Python. The trace uses the `configure_trace` defaults, and the remaining
+    /// options are as in `aggregate_traced_data`.
+    #[pyo3(signature = (
+        origins,
+        data,
+        aggregations,
+        group_by=None,
+        identity_in_each_direction=false,
+        origin_normalize=false,
+        nan_to_null=true,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn trace_and_aggregate(
+        &mut self,
+        origins: PyDataFrame,
+        data: PyDataFrame,
+        aggregations: Vec<Aggregation>,
+        group_by: Option<Vec<String>>,
+        identity_in_each_direction: bool,
+        origin_normalize: bool,
+        nan_to_null: bool,
+    ) -> PyResult<PyDataFrame> {
+        let ids = Self::origin_ids(&origins.0)?;
+        let options = self.trace_defaults.clone();
+        let traced = self.trace_with_options(&ids, &options, |_, _| {})?;
+        let joined = Self::join_segment_data(traced, data.0)?;
+        let result = Self::aggregate_frame(
+            joined,
+            &aggregations,
+            group_by,
+            identity_in_each_direction,
+            origin_normalize,
+            nan_to_null,
+            |_, _| {},
+        )?;
         Ok(PyDataFrame(result))
     }
+
     // ── Column mapping utility ──────────────────────────────────────────────
 
     fn map_column(
@@ -652,9 +2050,36 @@ impl SdtModel {
 
     // ── Properties ──────────────────────────────────────────────────────────
 
+    /// Which segment interval endpoints count as active: "left" (default), "right",
+    /// "both" or "neither".
+    #[getter]
+    fn interval_closed(&self) -> &'static str {
+        self.interval_closed.as_str()
+    }
+
+    #[setter]
+    fn set_interval_closed(&mut self, value: &str) -> PyResult<()> {
+        self.interval_closed = IntervalClosed::parse(value)?;
+        Ok(())
+    }
+
+    /// Transfer columns the loader added because the file(s) did not have them: the
+    /// share factors calculated from stock and, for factor-only files, the null stock
+    /// columns. Drop these from `transfers_df` to re-export the file as provided.
+    ///
+    /// A factor column present in the file but with some rows filled from stock is
+    /// file-provided. Set by `load_transfers` and `load_transfers_merge`, and updated by
+    /// `append_transfers`, where a column any of the files had is file-provided.
+    fn derived_columns(&self) -> Vec<String> {
+        self.derived_columns.clone()
+    }
+
     #[getter]
     fn transfers_df(&self) -> PyResult<Option<PyDataFrame>> {
-        Ok(self.transfers.clone().map(PyDataFrame))
+        if self.transfers.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(PyDataFrame(self.loaded_transfers()?)))
     }
 
     #[getter]
@@ -667,25 +2092,112 @@ impl SdtModel {
         Ok(self.segments.clone().map(PyDataFrame))
     }
 
-    // ── Visualization ───────────────────────────────────────────────────
+    // ── Graph export ────────────────────────────────────────────────────────
 
-    /// Visualize the trace as an interactive timeline chart.
+    /// Transfers as an edge list for `networkx.from_pandas_edgelist`.
     ///
-    /// Returns a self-contained HTML string with SVG and JS.
-    /// Use with `IPython.display.HTML(model.visualize_trace(...))` in Jupyter.
+    /// Columns are `source`, `target` and `weight` (a copy of the `weight` column,
+    /// by default `share_count_forward`), followed by the remaining transfer columns
+    /// as edge attributes.
+    #[pyo3(signature = (weight=factors::SHARE_COUNT_FORWARD))]
+    fn to_edge_frame(&self, weight: &str) -> PyResult<PyDataFrame> {
+        let transfers = &self.loaded_transfers()?;
+        Self::require_columns(transfers, &[weight])?;
+
+        let attrs: Vec<Expr> = transfers
+            .get_column_names()
+            .into_iter()
+            .filter(|c| {
+                ![transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID, "weight"].contains(&c.as_str())
+            })
+            .map(|c| col(c.as_str()))
+            .collect();
+        let mut exprs = vec![
+            col(transfer::SOURCE_SEGMENT_ID).alias("source"),
+            col(transfer::DEST_SEGMENT_ID).alias("target"),
+            col(weight).cast(DataType::Float64).alias("weight"),
+        ];
+        exprs.extend(attrs);
+
+        let df = transfers
+            .clone()
+            .lazy()
+            .select(exprs)
+            .collect()
+            .map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    /// All segments in the transfer graph and the segments table as a node list.
     ///
-    /// Args:
-    ///     container_label_col: Column from containers df for y-axis labels
-    ///                         (default: "container_id")
+    /// One row per `segment_id` (transfer endpoints first, then segments without
+    /// transfers) with the segment columns joined on as node attributes; they are
+    /// null for ids missing from the segments table.
+    fn to_node_frame(&self) -> PyResult<PyDataFrame> {
+        let transfers = &self.loaded_transfers()?;
+
+        let mut ids = vec![
+            transfers
+                .clone()
+                .lazy()
+                .select([col(transfer::SOURCE_SEGMENT_ID).alias(segment::SEGMENT_ID)]),
+            transfers
+                .clone()
+                .lazy()
+                .select([col(transfer::DEST_SEGMENT_ID).alias(segment::SEGMENT_ID)]),
+        ];
+        if let Some(segments) = &self.segments {
+            ids.push(segments.clone().lazy().select([col(segment::SEGMENT_ID)]));
+        }
+        let mut nodes = concat(ids, UnionArgs::default())
+            .map_err(SdtError::from)?
+            .unique_stable(None, UniqueKeepStrategy::First);
+        if let Some(segments) = &self.segments {
+            nodes = nodes.join(
+                segments.clone().lazy(),
+                [col(segment::SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Left),
+            );
+        }
+
+        let df = nodes.collect().map_err(SdtError::from)?;
+        Ok(PyDataFrame(df))
+    }
+
+    // ── Visualization ───────────────────────────────────────────────────
+
+    /// Visualize the trace as an interactive timeline chart.
+    ///
+    /// Returns a self-contained HTML string with SVG and JS.
+    /// Use with `IPython.display.HTML(model.visualize_trace(...))` in Jupyter.
+    /// Transfers whose segments are not in the segments data, or that have no transfer
+    /// time (source end_time and dest start_time both null), are skipped and reported
+    /// with a `UserWarning`.
+    ///
+    /// Args:
+    ///     container_label_col: Column from containers df for y-axis labels
+    ///                         (default: "container_id")
     ///     segment_label_col: Column from segments df to display on rectangles
     ///                          (default: "segment_id")
     ///     segment_tooltip_cols: Columns from segments df to show on hover
     ///                             (default: [])
     ///     transfer_tooltip_cols: Columns from transfers df to show on transfer hover
-    ///                           (default: ["transfer_count", "transfer_biomass_kg"])
+    ///                           (default: ["transfer_count", "transfer_biomass_kg"], or the
+    ///                           forward share factors when the file has no stock values)
     ///     gap_px: Pixel width of gap inserted at each transfer time (default: 32)
     ///     lane_height_px: Pixel height per container lane (default: 24)
     ///     initial_zoom: Initial zoom level (default: 1.0)
+    ///     show_gap_duration: Label each transfer gap with the real time until the
+    ///                        next event (default: False)
+    ///     gap_merge_tolerance_us: Merge transfer times within this many microseconds
+    ///                             into a single gap (default: 0)
+    ///     stripe_lanes: Alternate lane background shading (default: True)
+    ///     lane_sublabel_col: Column from containers df shown beneath each lane label
+    ///     orientation: "horizontal" (default) or "vertical", where time runs top to
+    ///                  bottom and containers become columns
+    ///     missing_value_text: Text shown for null and NaN tooltip values, e.g. "—"
+    ///                         (default: None, leaving those fields out)
     #[pyo3(signature = (
     container_label_col = None,
     segment_label_col = None,
@@ -694,9 +2206,17 @@ impl SdtModel {
     gap_px = 32,
     lane_height_px = 24,
     initial_zoom = 1.0,
+    show_gap_duration = false,
+    gap_merge_tolerance_us = 0,
+    stripe_lanes = true,
+    lane_sublabel_col = None,
+    orientation = "horizontal",
+    missing_value_text = None,
 ))]
+    #[allow(clippy::too_many_arguments)]
     fn visualize_trace(
         &self,
+        py: Python<'_>,
         container_label_col: Option<&str>,
         segment_label_col: Option<&str>,
         segment_tooltip_cols: Option<Vec<String>>,
@@ -704,126 +2224,3213 @@ impl SdtModel {
         gap_px: u32,
         lane_height_px: u32,
         initial_zoom: f64,
+        show_gap_duration: bool,
+        gap_merge_tolerance_us: i64,
+        stripe_lanes: bool,
+        lane_sublabel_col: Option<String>,
+        orientation: &str,
+        missing_value_text: Option<String>,
     ) -> PyResult<String> {
+        let config = VisualizationConfig::new(
+            container_label_col.map(str::to_string),
+            segment_label_col.map(str::to_string),
+            segment_tooltip_cols,
+            transfer_tooltip_cols,
+            gap_px,
+            lane_height_px,
+            initial_zoom,
+            show_gap_duration,
+            gap_merge_tolerance_us,
+            stripe_lanes,
+            lane_sublabel_col,
+            orientation.to_string(),
+            missing_value_text,
+        );
+        self.visualize_trace_with(py, &config)
+    }
+
+    /// Same as `visualize_trace`, but takes a reusable `VisualizationConfig`.
+    fn visualize_trace_with(&self, py: Python<'_>, config: &VisualizationConfig) -> PyResult<String> {
         let segments = self
             .segments
             .as_ref()
             .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let transfers = &self.loaded_transfers()?;
+        self.render_trace(py, segments, transfers, config.clone())
+    }
+
+    /// Pixel rectangle of each segment in the `visualize_trace_with(config)` chart at
+    /// its initial zoom, computed without a browser: `segment_id`, `container_id`,
+    /// `lane`, `x`, `y`, `width`, `height`. `config` defaults to `VisualizationConfig()`.
+    #[pyo3(signature = (config=None))]
+    fn trace_layout(
+        &self,
+        py: Python<'_>,
+        config: Option<VisualizationConfig>,
+    ) -> PyResult<PyDataFrame> {
+        let config = match config {
+            Some(config) => config,
+            None => py.get_type::<VisualizationConfig>().call0()?.extract()?,
+        };
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let transfers = &self.loaded_transfers()?;
         let containers = self
             .containers
             .as_ref()
             .ok_or_else(|| SdtError::NotLoaded("containers".into()))?;
+        let layout =
+            visualization::compute_trace_layout(segments, containers, transfers, &config)?;
+        Ok(PyDataFrame(layout))
+    }
+
+    /// A single-lane strip of one container's segments, for quick tank reviews.
+    ///
+    /// Transfers into or out of the container are drawn as short dashed stub arrows.
+    /// `viz_opts` are the `VisualizationConfig` arguments (e.g. `gap_px=16`).
+    #[pyo3(signature = (container_id, **viz_opts))]
+    fn container_timeline(
+        &self,
+        py: Python<'_>,
+        container_id: &str,
+        viz_opts: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<String> {
+        let mut config: VisualizationConfig = py
+            .get_type::<VisualizationConfig>()
+            .call((), viz_opts)?
+            .extract()?;
+        config.transfer_stubs = true;
+
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let transfers = &self.loaded_transfers()?;
+
+        let segments = segments
+            .clone()
+            .lazy()
+            .filter(col(segment::CONTAINER_ID).eq(lit(container_id)))
+            .collect()
+            .map_err(SdtError::from)?;
+        let ids = segments
+            .column(segment::SEGMENT_ID)
+            .map_err(SdtError::from)?
+            .as_materialized_series()
+            .clone();
+        let transfers = transfers
+            .clone()
+            .lazy()
+            .filter(
+                col(transfer::SOURCE_SEGMENT_ID)
+                    .is_in(lit(ids.clone()), false)
+                    .or(col(transfer::DEST_SEGMENT_ID).is_in(lit(ids), false)),
+            )
+            .collect()
+            .map_err(SdtError::from)?;
+        self.render_trace(py, &segments, &transfers, config)
+    }
+}
+
+// ── Trace iterator ──────────────────────────────────────────────────────────
+
+/// Python iterator returned by `SdtModel.trace_iter`.
+#[pyclass]
+pub struct TraceIter {
+    model: Py<SdtModel>,
+    ids: Vec<String>,
+    pos: usize,
+}
+
+#[pymethods]
+impl TraceIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyDataFrame>> {
+        let Some(id) = self.ids.get(self.pos) else {
+            return Ok(None);
+        };
+        self.pos += 1;
+        let mut model = self.model.borrow_mut(py);
+        let options = model.trace_defaults.clone();
+        let result = model.trace_with_options(std::slice::from_ref(id), &options, |_, _| {})?;
+        Ok(Some(PyDataFrame(result)))
+    }
+
+    fn __len__(&self) -> usize {
+        self.ids.len() - self.pos
+    }
+}
+
+// ── Trace result ────────────────────────────────────────────────────────────
+
+/// Traceability frame with shortcuts for the usual filters, returned by
+/// `SdtModel.trace_segments(as_result=True)`. Filters return a new `TraceResult`,
+/// so they chain: `result.forward().for_origin("S1").to_frame()`.
+#[pyclass]
+pub struct TraceResult {
+    df: DataFrame,
+}
+
+#[pymethods]
+impl TraceResult {
+    /// Wrap a traceability frame, e.g. one from `trace_segments`.
+    #[new]
+    fn new(frame: PyDataFrame) -> PyResult<Self> {
+        SdtModel::require_columns(&frame.0, &traceability::ALL)?;
+        Ok(Self { df: frame.0 })
+    }
+
+    /// Rows traced downstream of their origin.
+    fn forward(&self) -> PyResult<Self> {
+        Ok(self.filtered(col(traceability::TRACE_DIRECTION).eq(lit(direction::FORWARD)))?)
+    }
+
+    /// Rows traced upstream of their origin.
+    fn backward(&self) -> PyResult<Self> {
+        Ok(self.filtered(col(traceability::TRACE_DIRECTION).eq(lit(direction::BACKWARD)))?)
+    }
+
+    /// Rows traced from `origin_id`.
+    fn for_origin(&self, origin_id: &str) -> PyResult<Self> {
+        Ok(self.filtered(col(traceability::ORIGIN_SEGMENT_ID).eq(lit(origin_id)))?)
+    }
+
+    /// The underlying traceability frame.
+    fn to_frame(&self) -> PyDataFrame {
+        PyDataFrame(self.df.clone())
+    }
+
+    fn __len__(&self) -> usize {
+        self.df.height()
+    }
+}
+
+impl TraceResult {
+    fn filtered(&self, predicate: Expr) -> Result<Self, SdtError> {
+        let df = self.df.clone().lazy().filter(predicate).collect()?;
+        Ok(Self { df })
+    }
+}
+
+// ── Rust API ────────────────────────────────────────────────────────────────
+
+/// Builds an `SdtModel` from in-memory DataFrames, for use from Rust without
+/// Python or CSV files.
+///
+/// Transfers go through the same validation and factor calculation as
+/// `load_transfers`. Segment start/end times may be Datetime columns or strings
+/// in the default "%Y-%m-%d %H:%M:%S" format.
+#[derive(Default)]
+pub struct SdtModelBuilder {
+    base_path: PathBuf,
+    transfers: Option<DataFrame>,
+    containers: Option<DataFrame>,
+    segments: Option<DataFrame>,
+    filenames: HashMap<String, String>,
+    categorical_ids: bool,
+}
+
+impl SdtModelBuilder {
+    /// Base path used by the CSV loaders on the built model.
+    pub fn base_path(mut self, base_path: impl Into<PathBuf>) -> Self {
+        self.base_path = base_path.into();
+        self
+    }
+
+    pub fn transfers(mut self, df: DataFrame) -> Self {
+        self.transfers = Some(df);
+        self
+    }
+
+    pub fn containers(mut self, df: DataFrame) -> Self {
+        self.containers = Some(df);
+        self
+    }
+
+    pub fn segments(mut self, df: DataFrame) -> Self {
+        self.segments = Some(df);
+        self
+    }
+
+    /// File the built model's `kind` loader ("transfers", "segments" or "containers")
+    /// reads when called without one.
+    pub fn filename(mut self, kind: impl Into<String>, filename: impl Into<String>) -> Self {
+        self.filenames.insert(kind.into(), filename.into());
+        self
+    }
+
+    /// Keep the transfer id columns as Categorical (see `SdtModel::new`).
+    pub fn categorical_ids(mut self, categorical_ids: bool) -> Self {
+        self.categorical_ids = categorical_ids;
+        self
+    }
+
+    pub fn build(self) -> Result<SdtModel, SdtError> {
+        SdtModel::check_filenames(&self.filenames)?;
+        let mut derived_columns = Vec::new();
         let transfers = self
             .transfers
-            .as_ref()
-            .ok_or_else(|| SdtError::NotLoaded("transfers".into()))?;
+            .map(|df| {
+                let file_cols = df.get_column_names_owned();
+                let prepared = SdtModel::prepare_transfers(df, true)?;
+                derived_columns = SdtModel::columns_not_in(&prepared, &file_cols);
+                SdtModel::stored_ids(prepared, self.categorical_ids)
+            })
+            .transpose()?;
 
-        let config = VisualizationConfig {
-            container_label_col: container_label_col
-                .map(|s| s.to_string())
-                .or_else(|| Some(container::CONTAINER_ID.to_string())),
-            segment_label_col: segment_label_col
-                .map(|s| s.to_string())
-                .or_else(|| Some(segment::SEGMENT_ID.to_string())),
-            segment_tooltip_cols: segment_tooltip_cols.unwrap_or_default(),
-            transfer_tooltip_cols: transfer_tooltip_cols.unwrap_or_else(|| {
-                vec![
-                    transfer::TRANSFER_COUNT.to_string(),
-                    transfer::TRANSFER_BIOMASS_KG.to_string(),
-                ]
-            }),
-            gap_px,
-            lane_height_px,
-            initial_zoom,
+        if let Some(df) = &self.containers {
+            SdtModel::require_columns(df, &[container::CONTAINER_ID])?;
+        }
+
+        let segments = match self.segments {
+            Some(df) => {
+                SdtModel::require_columns(
+                    &df,
+                    &[
+                        segment::SEGMENT_ID,
+                        segment::CONTAINER_ID,
+                        segment::START_TIME,
+                        segment::END_TIME,
+                    ],
+                )?;
+                let mut df = df;
+                for column in [segment::START_TIME, segment::END_TIME] {
+                    if df.column(column)?.dtype() == &DataType::String {
+                        df = SdtModel::parse_datetime_column(df, column, "%Y-%m-%d %H:%M:%S")?;
+                    }
+                }
+                Some(df)
+            }
+            None => None,
         };
 
-        visualization::generate_trace_html(segments, containers, transfers, &config)
-            .map_err(|e| e.into())
+        Ok(SdtModel {
+            base_path: self.base_path,
+            transfers,
+            containers: self.containers,
+            segments,
+            tracer: None,
+            trace_cache: TraceCache::new(DEFAULT_TRACE_CACHE_SIZE),
+            interval_closed: IntervalClosed::default(),
+            derived_columns,
+            trace_defaults: TraceOptions::default(),
+            filenames: self.filenames,
+            categorical_ids: self.categorical_ids,
+        })
+    }
+}
+
+impl SdtModel {
+    /// Start building a model from in-memory DataFrames.
+    pub fn builder() -> SdtModelBuilder {
+        SdtModelBuilder::default()
+    }
+
+    /// Trace the given origin segment ids. Builds the tracer on first use.
+    pub fn trace(&mut self, origin_ids: &[String]) -> Result<DataFrame, SdtError> {
+        self.get_or_build_tracer()?.trace(origin_ids)
+    }
+
+    /// The loaded transfers as stored, i.e. with Categorical id columns when built
+    /// with `categorical_ids`.
+    pub fn transfers(&self) -> Option<&DataFrame> {
+        self.transfers.as_ref()
+    }
+
+    pub fn containers(&self) -> Option<&DataFrame> {
+        self.containers.as_ref()
+    }
+
+    pub fn segments(&self) -> Option<&DataFrame> {
+        self.segments.as_ref()
+    }
+
+    /// The tracer for the loaded transfers. Builds it on first use.
+    pub fn tracer(&mut self) -> Result<&DagTracer, SdtError> {
+        self.get_or_build_tracer()
     }
 }
 
 // ── Private helpers ─────────────────────────────────────────────────────────
 
 impl SdtModel {
-    /// Read a CSV file with all columns as String dtype.
-    /// Trims whitespace from column names and applies optional rename.
-    fn read_csv_as_strings(
+    /// Render a chart of `segments` and `transfers`, filling in data-dependent config
+    /// defaults and raising chart warnings as `UserWarning`s.
+    fn render_trace(
         &self,
-        filename: &str,
-        rename: Option<HashMap<String, String>>,
-    ) -> Result<DataFrame, SdtError> {
-        let path = self.base_path.join(filename);
-        let mut df = CsvReadOptions::default()
-            .with_has_header(true)
-            .with_infer_schema_length(Some(0)) // all columns as String
-            .try_into_reader_with_file_path(Some(path))?
-            .finish()?;
+        py: Python<'_>,
+        segments: &DataFrame,
+        transfers: &DataFrame,
+        mut config: VisualizationConfig,
+    ) -> PyResult<String> {
+        let containers = self
+            .containers
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("containers".into()))?;
 
-        // Trim whitespace from column names
-        let trimmed: Vec<String> = df
-            .get_column_names_str()
-            .iter()
-            .map(|c| c.trim().to_string())
-            .collect();
-        df.set_column_names(trimmed.as_slice())?;
+        // Fill in the defaults that depend on the loaded data
+        config
+            .container_label_col
+            .get_or_insert_with(|| container::CONTAINER_ID.to_string());
+        config
+            .segment_label_col
+            .get_or_insert_with(|| segment::SEGMENT_ID.to_string());
+        config
+            .transfer_tooltip_cols
+            .get_or_insert_with(|| Self::default_transfer_tooltip_cols(transfers));
 
-        // Apply optional column rename
-        if let Some(map) = rename {
-            let old: Vec<&str> = map.keys().map(|s| s.as_str()).collect();
-            let new: Vec<&str> = map.values().map(|s| s.as_str()).collect();
-            df = df.lazy().rename(old, new, true).collect()?;
+        let rendered = visualization::generate_trace_html(segments, containers, transfers, &config)?;
+        for warning in &rendered.warnings {
+            let message = CString::new(warning.as_str()).unwrap_or_default();
+            PyErr::warn(py, &py.get_type::<PyUserWarning>(), &message, 1)?;
         }
+        Ok(rendered.html)
+    }
 
-        Ok(df)
+    /// Fill null end_times with the start_time of the next segment in the same container,
+    /// keeping the row order.
+    fn infer_end_times(df: DataFrame) -> Result<DataFrame, SdtError> {
+        let order = "_row";
+        let next_start = col(segment::START_TIME)
+            .shift(lit(-1))
+            .over([col(segment::CONTAINER_ID)]);
+        Ok(df
+            .lazy()
+            .with_row_index(order, None)
+            .sort(
+                [segment::CONTAINER_ID, segment::START_TIME],
+                SortMultipleOptions::default(),
+            )
+            .with_column(col(segment::END_TIME).fill_null(next_start))
+            .sort([order], SortMultipleOptions::default())
+            .drop(cols([order]))
+            .collect()?)
     }
 
-    fn get_or_build_tracer(&mut self) -> Result<&DagTracer, SdtError> {
-        if self.tracer.is_none() {
-            let transfers = self
-                .transfers
-                .as_ref()
-                .ok_or_else(|| SdtError::NotLoaded("transfers".into()))?;
-            self.tracer = Some(DagTracer::from_transfers(transfers)?);
+    /// Default `group_by` of the aggregation methods: `origin_segment_id`, plus
+    /// `date_time` when `df` has it.
+    fn default_group_by(df: &DataFrame) -> Vec<String> {
+        let mut cols = vec![traceability::ORIGIN_SEGMENT_ID.to_string()];
+        if df.column(timeseries::DATE_TIME).is_ok() {
+            cols.push(timeseries::DATE_TIME.to_string());
         }
-        Ok(self.tracer.as_ref().unwrap())
+        cols
+    }
+
+    /// Sum the stock of transfers whose `end_col` segment is in `container_id` and whose
+    /// transfer time (µs) is in `[start, end)`. Shared by `flow_into` and `flow_out_of`.
+    fn container_flow(
+        &self,
+        container_id: &str,
+        end_col: &str,
+        (start, end): (i64, i64),
+    ) -> Result<DataFrame, SdtError> {
+        let transfers = &self.loaded_transfers()?;
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+        let method = if end_col == transfer::DEST_SEGMENT_ID { "flow_into" } else { "flow_out_of" };
+        Self::require_stock_columns(transfers, method)?;
+
+        let times = visualization::transfer_times_us(transfers, segments)?;
+        let time_col = "_transfer_time_us";
+        let in_container = segments
+            .clone()
+            .lazy()
+            .filter(col(segment::CONTAINER_ID).eq(lit(container_id)))
+            .select([col(segment::SEGMENT_ID)]);
+        let df = transfers
+            .clone()
+            .lazy()
+            .with_column(lit(Series::new(time_col.into(), times)))
+            .filter(col(time_col).gt_eq(lit(start)).and(col(time_col).lt(lit(end))))
+            .join(
+                in_container,
+                [col(end_col)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Inner),
+            )
+            .select([
+                col(transfer::TRANSFER_COUNT).sum(),
+                col(transfer::TRANSFER_BIOMASS_KG).sum(),
+                len().cast(DataType::Int64).alias("n_transfers"),
+            ])
+            .collect()?;
+        Ok(df)
     }
 
-    fn require_columns(df: &DataFrame, required: &[&str]) -> PyResult<()> {
-        for &col_name in required {
-            if df.column(col_name).is_err() {
-                return Err(SdtError::MissingColumn(col_name.to_string()).into());
+    /// Core of `trace_segments`; see its docs. Every method that traces for Python
+    /// goes through here (or `trace_within`), so all use the `configure_trace` defaults.
+    fn trace_with_options(
+        &mut self,
+        ids: &[String],
+        options: &TraceOptions,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<DataFrame, SdtError> {
+        let origin_segments = self.origin_segments(options)?;
+        let result = self.trace_ids_cached(
+            ids,
+            &options.stop_ids,
+            options.min_factor,
+            options.detail,
+            progress,
+        )?;
+        Self::finish_trace(result, self.get_or_build_tracer()?, options, origin_segments)
+    }
+
+    /// `segment_id` and the `origin_`-prefixed `origin_attrs` columns of the segments,
+    /// for joining onto a trace. Fails on bad attribute names, so call before tracing.
+    fn origin_segments(&self, options: &TraceOptions) -> Result<Option<LazyFrame>, SdtError> {
+        Ok(match &options.origin_attrs {
+            Some(attrs) => {
+                Self::check_origin_attrs(attrs, options)?;
+                let segments = self
+                    .segments
+                    .as_ref()
+                    .ok_or_else(|| SdtError::NotLoaded("segments".into()))?;
+                let names: Vec<&str> = attrs.iter().map(String::as_str).collect();
+                Self::require_columns(segments, &names)?;
+                let select: Vec<Expr> = std::iter::once(col(segment::SEGMENT_ID))
+                    .chain(attrs.iter().map(|c| col(c.as_str()).alias(format!("origin_{c}"))))
+                    .collect();
+                Some(segments.clone().lazy().select(select))
             }
-        }
-        Ok(())
+            None => None,
+        })
     }
 
-    /// Parse a string column to Datetime. Handles null values gracefully.
-    fn parse_datetime_column(
-        df: DataFrame,
-        column: &str,
-        format: &str,
+    /// Apply the options that act on a finished trace of `tracer`: `drop_zero_factor`,
+    /// `distance_metric` and the `origin_segments` join.
+    fn finish_trace(
+        mut result: DataFrame,
+        tracer: &DagTracer,
+        options: &TraceOptions,
+        origin_segments: Option<LazyFrame>,
     ) -> Result<DataFrame, SdtError> {
-        if df.column(column).is_ok() {
-            let df = df
+        if options.drop_zero_factor {
+            let all_zero = factors::ALL
+                .iter()
+                .fold(lit(true), |acc, c| acc.and(col(*c).eq(lit(0.0))));
+            let zero_row = col(traceability::TRACE_DIRECTION)
+                .neq(lit(direction::IDENTITY))
+                .and(all_zero);
+            result = result.lazy().filter(zero_row.not()).collect()?;
+        }
+        if let Some(metric) = options.distance_metric {
+            result = tracer.with_trace_distance(
+                &result,
+                metric,
+                &options.stop_ids,
+                options.min_factor,
+            )?;
+        }
+        if let Some(origin_segments) = origin_segments {
+            result = result
                 .lazy()
-                .with_columns([col(column)
-                    .str()
-                    .strip_chars(lit(" \t\r\n"))
-                    .str()
-                    .to_datetime(
-                        Some(TimeUnit::Microseconds),
-                        None,
-                        StrptimeOptions {
-                            format: Some(format.into()),
-                            strict: true,
-                            ..Default::default()
-                        },
-                        lit("raise"),
-                    )])
+                .join(
+                    origin_segments,
+                    [col(traceability::ORIGIN_SEGMENT_ID)],
+                    [col(segment::SEGMENT_ID)],
+                    JoinArgs::new(JoinType::Left),
+                )
                 .collect()?;
-            Ok(df)
-        } else {
-            Ok(df)
         }
+        Ok(result)
+    }
+
+    /// The non-null `segment_id`s of an origin frame, in row order.
+    fn origin_ids(origin_df: &DataFrame) -> Result<Vec<String>, SdtError> {
+        // Cast so an empty (untyped) id column still yields the full trace schema
+        Ok(origin_df
+            .column(segment::SEGMENT_ID)?
+            .cast(&DataType::String)?
+            .str()?
+            .into_iter()
+            .filter_map(|v| v.map(|s| s.to_string()))
+            .collect())
+    }
+
+    /// Core of `trace_within`; see its docs.
+    fn trace_scoped(&mut self, ids: &[String], scope_ids: &[String]) -> Result<DataFrame, SdtError> {
+        let options = self.trace_defaults.clone();
+        let origin_segments = self.origin_segments(&options)?;
+        let scoped = self.get_or_build_tracer()?.subgraph(scope_ids);
+        let result = scoped.trace_with_limits(
+            ids,
+            &options.stop_ids,
+            options.min_factor,
+            options.detail,
+            |_, _| {},
+        )?;
+        Self::finish_trace(result, &scoped, &options, origin_segments)
+    }
+
+    /// Reject `origin_attrs` that cannot be added as `origin_{name}` columns: the join
+    /// key `segment_id`, repeated names, and names whose prefixed column the trace
+    /// already has.
+    fn check_origin_attrs(attrs: &[String], options: &TraceOptions) -> Result<(), SdtError> {
+        let mut trace_cols: Vec<String> = DagTracer::trace_schema()
+            .iter_names()
+            .map(|name| name.to_string())
+            .collect();
+        if options.detail {
+            trace_cols.extend(
+                [
+                    traceability::N_PATHS,
+                    traceability::MIN_PATH_FACTOR,
+                    traceability::MAX_PATH_FACTOR,
+                ]
+                .map(String::from),
+            );
+        }
+        if options.distance_metric.is_some() {
+            trace_cols.push(traceability::TRACE_DISTANCE.to_string());
+        }
+
+        let mut seen = HashSet::new();
+        for attr in attrs {
+            let reason = if attr == segment::SEGMENT_ID {
+                "it is the join key"
+            } else if !seen.insert(attr.as_str()) {
+                "it is listed more than once"
+            } else if trace_cols.contains(&format!("origin_{attr}")) {
+                "the trace already has that column"
+            } else {
+                continue;
+            };
+            return Err(SdtError::Validation(format!(
+                "origin_attrs cannot include '{attr}' (origin_{attr}): {reason}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Trace `ids` without passing `stop_ids` or transfers below `min_factor`, reusing a
+    /// cached result for the same origins and options.
+    ///
+    /// The key holds the origins in the order given, since the rows of a trace follow
+    /// that order; the same origins in another order are traced again.
+    fn trace_ids_cached(
+        &mut self,
+        ids: &[String],
+        stop_ids: &[String],
+        min_factor: Option<f64>,
+        detail: bool,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<DataFrame, SdtError> {
+        let mut stop_key = stop_ids.to_vec();
+        stop_key.sort_unstable();
+        let key = (ids.to_vec(), stop_key, min_factor.map(f64::to_bits), detail);
+        if let Some(cached) = self.trace_cache.get(&key) {
+            progress(ids.len(), ids.len());
+            return Ok(cached);
+        }
+
+        let result = self
+            .get_or_build_tracer()?
+            .trace_with_limits(ids, stop_ids, min_factor, detail, progress)?;
+        self.trace_cache.insert(key, result.clone());
+        Ok(result)
+    }
+
+    /// Core of `map_container_data_to_segments`; see its docs.
+    fn container_data_segments(
+        &self,
+        container_data: DataFrame,
+        include_unmatched: bool,
+        allow_multiple: bool,
+        time_col: &str,
+        end_inclusive: Option<bool>,
+    ) -> Result<DataFrame, SdtError> {
+        let segments = self
+            .segments
+            .as_ref()
+            .ok_or(SdtError::NotLoaded("segments".into()))?;
+
+        Self::require_columns(&container_data, &[container::CONTAINER_ID, time_col])?;
+        let time_dtype = container_data.column(time_col)?.dtype();
+        if !matches!(time_dtype, DataType::Datetime(_, _)) {
+            return Err(SdtError::Validation(format!(
+                "Column '{time_col}' must be Datetime, found {time_dtype}. Parse it with parse_datetime first."
+            )));
+        }
+
+        let input_cols: Vec<String> = container_data
+            .get_column_names_str()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        // Join and filter to active period
+        let join_type = if include_unmatched {
+            JoinType::Left
+        } else {
+            JoinType::Inner
+        };
+
+        let mut output_cols: Vec<Expr> = input_cols.iter().map(col).collect();
+        output_cols.push(col(segment::SEGMENT_ID));
+
+        let matched = container_data
+            .lazy()
+            .join(
+                segments.clone().lazy(),
+                [col(container::CONTAINER_ID)],
+                [col(segment::CONTAINER_ID)],
+                JoinArgs::new(join_type),
+            )
+            .filter(
+                self.interval_closed
+                    .contains(col(time_col), end_inclusive)
+                    // Also keep unmatched rows (where segment columns are null)
+                    .or(col(segment::SEGMENT_ID).is_null()),
+            )
+            .select(output_cols)
+            .collect()?;
+
+        // Check for multiple matches if not allowed
+        if !allow_multiple {
+            let counts = matched
+                .clone()
+                .lazy()
+                .filter(col(segment::SEGMENT_ID).is_not_null())
+                .group_by(input_cols.iter().map(col).collect::<Vec<_>>())
+                .agg([col(segment::SEGMENT_ID).count().alias("match_count")])
+                .filter(col("match_count").gt(lit(1)))
+                .collect()?;
+
+            if counts.height() > 0 {
+                return Err(SdtError::InvalidRows {
+                    message: format!(
+                        "{} rows matched multiple segments while allow_multiple=false",
+                        counts.height()
+                    ),
+                    column: None,
+                    rows: counts,
+                });
+            }
+        }
+
+        Ok(matched)
+    }
+
+    /// Core of `aggregate_traced_data`; see its docs. Shared with `trace_and_aggregate`.
+    fn aggregate_frame(
+        traced: DataFrame,
+        aggregations: &[Aggregation],
+        group_by: Option<Vec<String>>,
+        identity_in_each_direction: bool,
+        origin_normalize: bool,
+        nan_to_null: bool,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<DataFrame, SdtError> {
+        let group_cols = group_by.unwrap_or_else(|| Self::default_group_by(&traced));
+
+        let by_direction = group_cols
+            .iter()
+            .any(|c| c == traceability::TRACE_DIRECTION);
+        if aggregations.iter().any(Aggregation::uses_trace_factors) {
+            let mut factor_cols: Vec<&str> =
+                aggregations.iter().flat_map(Aggregation::factor_columns).collect();
+            factor_cols.sort_unstable();
+            factor_cols.dedup();
+            validate_trace_frame_for(&traced, &factor_cols)?;
+        }
+        let df = if origin_normalize {
+            Self::normalize_by_origin_count(traced)?
+        } else {
+            traced
+        };
+        let df = if by_direction && identity_in_each_direction {
+            Self::split_identity_rows(df)?
+        } else {
+            df
+        };
+
+        let result = aggregate_groups_with_progress(&df, aggregations, &group_cols, progress)?;
+        if nan_to_null {
+            Self::nan_to_null(result, &group_cols)
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Validate a raw transfers frame and fill in missing share factors.
+    /// Shared by `load_transfers` and `append_transfers`.
+    fn prepare_transfers(raw: DataFrame, create_missing_stock: bool) -> Result<DataFrame, SdtError> {
+        Self::prepare_transfers_grouped(raw, create_missing_stock, None, None)
+    }
+
+    /// `filename`, else the configured or default file for the `kind` loader.
+    fn filename_or_default(&self, filename: Option<&str>, kind: &str) -> String {
+        if let Some(filename) = filename {
+            return filename.to_string();
+        }
+        self.filenames.get(kind).cloned().unwrap_or_else(|| {
+            DEFAULT_FILENAMES
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, default)| default.to_string())
+                .expect("known loader kind")
+        })
+    }
+
+    /// Reject `filenames` keys that name no loader.
+    fn check_filenames(filenames: &HashMap<String, String>) -> Result<(), SdtError> {
+        for kind in filenames.keys() {
+            if !DEFAULT_FILENAMES.iter().any(|(k, _)| k == kind) {
+                return Err(SdtError::Validation(format!(
+                    "Unknown filenames key '{kind}'. Must be 'transfers', 'segments' or 'containers'"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Columns of `df` whose names are not in `file_cols`, in frame order.
+    fn columns_not_in(df: &DataFrame, file_cols: &[PlSmallStr]) -> Vec<String> {
+        df.get_column_names()
+            .into_iter()
+            .filter(|c| !file_cols.contains(c))
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    /// Set negative factor values to 0.0, returning the frame and the number of values
+    /// clamped. Fails if any factor is NaN or infinite.
+    fn clamp_negative_factors(df: DataFrame) -> Result<(DataFrame, usize), SdtError> {
+        let mut clamped = 0;
+        for factor_col in factors::ALL {
+            let values = df.column(factor_col)?.f64()?;
+            let non_finite = values.into_iter().flatten().filter(|v| !v.is_finite()).count();
+            if non_finite > 0 {
+                return Err(SdtError::InvalidRows {
+                    message: format!("Column '{factor_col}' has {non_finite} NaN or infinite values"),
+                    column: Some(factor_col.to_string()),
+                    rows: df.clone().lazy().filter(col(factor_col).is_finite().not()).collect()?,
+                });
+            }
+            clamped += values.into_iter().flatten().filter(|v| *v < 0.0).count();
+        }
+        if clamped == 0 {
+            return Ok((df, 0));
+        }
+
+        let clamp: Vec<Expr> = factors::ALL
+            .iter()
+            .map(|c| {
+                when(col(*c).lt(lit(0.0)))
+                    .then(lit(0.0))
+                    .otherwise(col(*c))
+                    .alias(*c)
+            })
+            .collect();
+        let df = df.lazy().with_columns(clamp).collect()?;
+        Ok((df, clamped))
+    }
+
+    /// Convert the factor columns present in `raw` from percentages to fractions.
+    fn factors_from_percent(raw: DataFrame) -> Result<DataFrame, SdtError> {
+        let scaled: Vec<Expr> = factors::ALL
+            .iter()
+            .filter(|name| raw.column(name).is_ok())
+            .map(|&name| (col(name).cast(DataType::Float64) / lit(100.0)).alias(name))
+            .collect();
+        Ok(raw.lazy().with_columns(scaled).collect()?)
+    }
+
+    /// `prepare_transfers` with the partitions used to calculate forward / backward
+    /// factors from stock (default: source / dest segment id).
+    fn prepare_transfers_grouped(
+        raw: DataFrame,
+        create_missing_stock: bool,
+        forward_group_cols: Option<&[String]>,
+        backward_group_cols: Option<&[String]>,
+    ) -> Result<DataFrame, SdtError> {
+        Self::require_columns(&raw, &[transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID])?;
+
+        let partition = |group_cols: Option<&[String]>, default: &str| -> Result<Vec<Expr>, SdtError> {
+            match group_cols {
+                Some([]) => Err(SdtError::Validation(
+                    "Factor group columns must not be empty".into(),
+                )),
+                Some(cols) => {
+                    let names: Vec<&str> = cols.iter().map(String::as_str).collect();
+                    Self::require_columns(&raw, &names)?;
+                    Ok(names.into_iter().map(col).collect())
+                }
+                None => Ok(vec![col(default)]),
+            }
+        };
+        let forward_over = partition(forward_group_cols, transfer::SOURCE_SEGMENT_ID)?;
+        let backward_over = partition(backward_group_cols, transfer::DEST_SEGMENT_ID)?;
+
+        let schema = raw.schema();
+        let has_stock_cols = schema.contains(transfer::TRANSFER_COUNT)
+            && schema.contains(transfer::TRANSFER_BIOMASS_KG);
+        let has_factor_cols = schema.contains(factors::SHARE_COUNT_FORWARD)
+            && schema.contains(factors::SHARE_BIOMASS_FORWARD)
+            && schema.contains(factors::SHARE_COUNT_BACKWARD)
+            && schema.contains(factors::SHARE_BIOMASS_BACKWARD);
+
+        if !has_stock_cols && !has_factor_cols {
+            return Err(SdtError::InvalidData(
+                "Transfers CSV must contain either (transfer_count, transfer_biomass_kg) \
+             or all share factor columns"
+                    .to_string(),
+            ));
+        }
+
+        let mut lazy = raw.lazy();
+
+        // Cast stock columns if present, otherwise create null columns
+        if has_stock_cols {
+            lazy = lazy.with_columns([
+                col(transfer::TRANSFER_COUNT).cast(DataType::Float64),
+                col(transfer::TRANSFER_BIOMASS_KG).cast(DataType::Float64),
+            ]);
+        } else {
+            lazy = lazy.with_columns([
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(transfer::TRANSFER_COUNT),
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(transfer::TRANSFER_BIOMASS_KG),
+            ]);
+        }
+
+        // Cast or create factor columns
+        if has_factor_cols {
+            lazy = lazy.with_columns([
+                col(factors::SHARE_COUNT_FORWARD).cast(DataType::Float64),
+                col(factors::SHARE_BIOMASS_FORWARD).cast(DataType::Float64),
+                col(factors::SHARE_COUNT_BACKWARD).cast(DataType::Float64),
+                col(factors::SHARE_BIOMASS_BACKWARD).cast(DataType::Float64),
+            ]);
+        } else {
+            lazy = lazy.with_columns([
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(factors::SHARE_COUNT_FORWARD),
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(factors::SHARE_BIOMASS_FORWARD),
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(factors::SHARE_COUNT_BACKWARD),
+                lit(NULL)
+                    .cast(DataType::Float64)
+                    .alias(factors::SHARE_BIOMASS_BACKWARD),
+            ]);
+        }
+
+        // Calculate factors from stock (for rows that need it)
+        let calc_forward_count = col(transfer::TRANSFER_COUNT)
+            / col(transfer::TRANSFER_COUNT)
+                .sum()
+                .over(forward_over.clone());
+        let calc_forward_biomass = col(transfer::TRANSFER_BIOMASS_KG)
+            / col(transfer::TRANSFER_BIOMASS_KG)
+                .sum()
+                .over(forward_over);
+        let calc_backward_count = col(transfer::TRANSFER_COUNT)
+            / col(transfer::TRANSFER_COUNT)
+                .sum()
+                .over(backward_over.clone());
+        let calc_backward_biomass = col(transfer::TRANSFER_BIOMASS_KG)
+            / col(transfer::TRANSFER_BIOMASS_KG)
+                .sum()
+                .over(backward_over);
+
+        // For each factor: use file value if present, otherwise calculate from stock
+        lazy = lazy.with_columns([
+            when(col(factors::SHARE_COUNT_FORWARD).is_not_null())
+                .then(col(factors::SHARE_COUNT_FORWARD))
+                .otherwise(calc_forward_count)
+                .alias(factors::SHARE_COUNT_FORWARD),
+            when(col(factors::SHARE_BIOMASS_FORWARD).is_not_null())
+                .then(col(factors::SHARE_BIOMASS_FORWARD))
+                .otherwise(calc_forward_biomass)
+                .alias(factors::SHARE_BIOMASS_FORWARD),
+            when(col(factors::SHARE_COUNT_BACKWARD).is_not_null())
+                .then(col(factors::SHARE_COUNT_BACKWARD))
+                .otherwise(calc_backward_count)
+                .alias(factors::SHARE_COUNT_BACKWARD),
+            when(col(factors::SHARE_BIOMASS_BACKWARD).is_not_null())
+                .then(col(factors::SHARE_BIOMASS_BACKWARD))
+                .otherwise(calc_backward_biomass)
+                .alias(factors::SHARE_BIOMASS_BACKWARD),
+        ]);
+
+        // Stock placeholders were only needed for the factor calculation above
+        if !has_stock_cols && !create_missing_stock {
+            lazy = lazy.drop(cols([transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]));
+        }
+
+        let df = lazy.collect().map_err(SdtError::from)?;
+
+        // Validate that all rows have complete factor data
+        let factor_cols = [
+            factors::SHARE_COUNT_FORWARD,
+            factors::SHARE_BIOMASS_FORWARD,
+            factors::SHARE_COUNT_BACKWARD,
+            factors::SHARE_BIOMASS_BACKWARD,
+        ];
+
+        for factor_col in &factor_cols {
+            let null_count = df.column(factor_col).map_err(SdtError::from)?.null_count();
+            if null_count > 0 {
+                return Err(SdtError::InvalidRows {
+                    message: format!("All rows must have valid factor values. Column '{}' has {} null values. \
+                     Provide either factor values or stock values (transfer_count, transfer_biomass_kg) for all rows.",
+                     factor_col, null_count),
+                    column: Some(factor_col.to_string()),
+                    rows: df.clone().lazy().filter(col(*factor_col).is_null()).collect()?,
+                });
+            }
+        }
+        Ok(df)
+    }
+
+    /// Convert a naive Python datetime to microseconds since the epoch.
+    /// Timezone-aware datetimes are rejected.
+    fn naive_timestamp_us(timestamp: &Bound<PyDateTime>) -> PyResult<i64> {
+        if !timestamp.getattr("tzinfo")?.is_none() {
+            return Err(PyValueError::new_err(
+                "aqua-tracekit requires naive datetime objects (no timezone info). \
+                 Use datetime(2024, 6, 15, 12, 0, 0) instead of datetime(..., tzinfo=...)",
+            ));
+        }
+        let dt: NaiveDateTime = timestamp.extract()?;
+        Ok(dt.and_utc().timestamp_micros())
+    }
+
+    /// Microseconds since the epoch of the Datetime column `name`, whatever its time unit.
+    fn column_us(name: &str) -> Expr {
+        col(name).dt().timestamp(TimeUnit::Microseconds)
+    }
+
+    /// Replace NaN with null in the float aggregation columns (group keys are left as is).
+    fn nan_to_null(df: DataFrame, group_cols: &[String]) -> Result<DataFrame, SdtError> {
+        let exprs: Vec<Expr> = df
+            .get_columns()
+            .iter()
+            .filter(|c| c.dtype().is_float() && !group_cols.iter().any(|g| g == c.name().as_str()))
+            .map(|c| col(c.name().clone()).fill_nan(lit(NULL)))
+            .collect();
+        if exprs.is_empty() {
+            return Ok(df);
+        }
+        Ok(df.lazy().with_columns(exprs).collect()?)
+    }
+
+    /// Left-join segment-level `data` onto `traced` by traced segment id.
+    fn join_segment_data(traced: DataFrame, data: DataFrame) -> Result<DataFrame, SdtError> {
+        Ok(traced
+            .lazy()
+            .join(
+                data.lazy(),
+                [col(traceability::TRACED_SEGMENT_ID)],
+                [col(segment::SEGMENT_ID)],
+                JoinArgs::new(JoinType::Left),
+            )
+            .collect()?)
+    }
+
+    /// `df` with every column except `key` renamed to `{prefix}_{name}`, for joining
+    /// attributes of several rows side by side.
+    fn prefixed(df: &DataFrame, key: &str, prefix: &str) -> LazyFrame {
+        let select: Vec<Expr> = df
+            .get_column_names()
+            .into_iter()
+            .map(|c| {
+                if c == key {
+                    col(c.as_str())
+                } else {
+                    col(c.as_str()).alias(format!("{prefix}_{c}"))
+                }
+            })
+            .collect();
+        df.clone().lazy().select(select)
+    }
+
+    /// Prose for `explain_trace`. Shares are summed over paths and multiplied along each
+    /// path, as in tracing.
+    fn explain_lineage(tracer: &DagTracer, origin_id: &str, target_id: &str) -> String {
+        if origin_id == target_id {
+            return format!("{origin_id} is the origin itself.");
+        }
+        let downstream = tracer.paths(origin_id, target_id);
+        let (source, dest, paths) = if downstream.is_empty() {
+            (target_id, origin_id, tracer.paths(target_id, origin_id))
+        } else {
+            (origin_id, target_id, downstream)
+        };
+        if paths.is_empty() {
+            return format!("No transfers connect {origin_id} and {target_id}.");
+        }
+
+        let pct = |v: f64| format!("{:.1}%", v * 100.0);
+        let path_factors: Vec<Vec<[f64; 4]>> = paths
+            .iter()
+            .map(|path| {
+                path.windows(2)
+                    .map(|hop| tracer.edge_factors(&hop[0], &hop[1]).unwrap_or([0.0; 4]))
+                    .collect()
+            })
+            .collect();
+        let mut totals = [0.0f64; 4];
+        for hops in &path_factors {
+            for (i, total) in totals.iter_mut().enumerate() {
+                *total += hops.iter().map(|f| f[i]).product::<f64>();
+            }
+        }
+
+        let plural = if paths.len() == 1 { "path" } else { "paths" };
+        let mut lines = vec![
+            format!(
+                "{dest} received {} (by count) and {} (by biomass) of its fish from {source} via {} {plural}.",
+                pct(totals[2]),
+                pct(totals[3]),
+                paths.len(),
+            ),
+            format!(
+                "{source} sent {} (by count) and {} (by biomass) of its fish to {dest}.",
+                pct(totals[0]),
+                pct(totals[1]),
+            ),
+        ];
+        for (i, (path, hops)) in paths.iter().zip(&path_factors).enumerate() {
+            lines.push(format!("Path {}: {}", i + 1, path.join(" → ")));
+            for (hop, f) in path.windows(2).zip(hops) {
+                lines.push(format!(
+                    "  {} received {} (by count) and {} (by biomass) from {}, which sent {} (by count) and {} (by biomass) of its fish.",
+                    hop[1],
+                    pct(f[2]),
+                    pct(f[3]),
+                    hop[0],
+                    pct(f[0]),
+                    pct(f[1]),
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Divide the share factors of each row by the number of distinct origins reaching
+    /// its traced segment.
+    fn normalize_by_origin_count(df: DataFrame) -> Result<DataFrame, SdtError> {
+        Self::require_columns(
+            &df,
+            &[traceability::ORIGIN_SEGMENT_ID, traceability::TRACED_SEGMENT_ID],
+        )?;
+        let origin_count = col(traceability::ORIGIN_SEGMENT_ID)
+            .n_unique()
+            .over([col(traceability::TRACED_SEGMENT_ID)])
+            .cast(DataType::Float64);
+        let scaled: Vec<Expr> = factors::ALL
+            .iter()
+            .filter(|name| df.column(name).is_ok())
+            .map(|&name| (col(name) / origin_count.clone()).alias(name))
+            .collect();
+        Ok(df.lazy().with_columns(scaled).collect()?)
+    }
+
+    /// Replace each identity row with a forward and a backward copy, so per-direction
+    /// rollups both include the origin. Identity factors are all 1.0, so the copies
+    /// keep weight 1.0 under either direction's rules.
+    fn split_identity_rows(df: DataFrame) -> Result<DataFrame, SdtError> {
+        let is_identity = col(traceability::TRACE_DIRECTION).eq(lit(direction::IDENTITY));
+        let identity = df.clone().lazy().filter(is_identity.clone());
+        let relabel = |dir: &str| {
+            identity
+                .clone()
+                .with_column(lit(dir.to_string()).alias(traceability::TRACE_DIRECTION))
+        };
+        let df = concat(
+            [
+                df.lazy().filter(is_identity.not()),
+                relabel(direction::FORWARD),
+                relabel(direction::BACKWARD),
+            ],
+            UnionArgs::default(),
+        )?
+        .collect()?;
+        Ok(df)
+    }
+
+    /// Read an input file: CSV, or Arrow IPC for .arrow / .feather / .ipc files.
+    ///
+    /// Columns are returned as strings like `read_csv_as_strings`, unless
+    /// `preserve_dtypes` is set for an Arrow file. Arrow Datetime columns always stay
+    /// Datetime. Id columns are always strings, trimmed of surrounding whitespace.
+    fn read_table(&self, filename: &str, options: &ReadOptions) -> Result<DataFrame, SdtError> {
+        let is_ipc = Path::new(filename)
+            .extension()
+            .map(|e| matches!(e.to_string_lossy().to_lowercase().as_str(), "arrow" | "feather" | "ipc"))
+            .unwrap_or(false);
+        let df = if is_ipc {
+            self.read_ipc(filename, options)?
+        } else {
+            self.read_csv_as_strings(filename, options)?
+        };
+        Self::trim_string_values(df, options.trim_values)
+    }
+
+    /// Read an Arrow IPC file, converting columns to strings unless `preserve_dtypes`.
+    /// Datetime columns are kept: their string form carries fractional seconds that
+    /// the default time format would not parse back.
+    fn read_ipc(&self, filename: &str, options: &ReadOptions) -> Result<DataFrame, SdtError> {
+        if Self::is_cloud_path(&self.base_path) {
+            return Err(SdtError::General(
+                "Arrow IPC input is only supported for local base paths".into(),
+            ));
+        }
+
+        let file = std::fs::File::open(self.base_path.join(filename))?;
+        let df = IpcReader::new(file).finish()?;
+        let mut df = Self::normalize_headers(df, options)?;
+
+        let is_id = |c: &Column| ID_COLUMNS.contains(&c.name().as_str());
+        let to_string: Vec<Expr> = df
+            .get_columns()
+            .iter()
+            .filter(|c| c.dtype() != &DataType::String)
+            .filter(|c| !matches!(c.dtype(), DataType::Datetime(_, _)) || is_id(c))
+            .filter(|c| !options.preserve_dtypes || is_id(c))
+            .map(|c| col(c.name().clone()).cast(DataType::String))
+            .collect();
+        if !to_string.is_empty() {
+            df = df.lazy().with_columns(to_string).collect()?;
+        }
+
+        Ok(df)
+    }
+
+    /// Strip leading/trailing whitespace from the id columns, or from every string
+    /// column when `all` is set.
+    fn trim_string_values(df: DataFrame, all: bool) -> Result<DataFrame, SdtError> {
+        let trimmed: Vec<Expr> = df
+            .get_columns()
+            .iter()
+            .filter(|c| c.dtype() == &DataType::String)
+            .filter(|c| all || ID_COLUMNS.contains(&c.name().as_str()))
+            .map(|c| col(c.name().clone()).str().strip_chars(lit(NULL)))
+            .collect();
+        if trimmed.is_empty() {
+            return Ok(df);
+        }
+        Ok(df.lazy().with_columns(trimmed).collect()?)
+    }
+
+    /// Read a CSV file with all columns as String dtype.
+    /// Trims whitespace from column names and applies optional rename.
+    /// Files in another encoding than UTF-8 are decoded first (`options.encoding`).
+    fn read_csv_as_strings(
+        &self,
+        filename: &str,
+        options: &ReadOptions,
+    ) -> Result<DataFrame, SdtError> {
+        let encoding = match options.encoding.as_deref() {
+            Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+                SdtError::Validation(format!("Unknown encoding '{label}'"))
+            })?),
+            None => None,
+        }
+        .filter(|enc| *enc != encoding_rs::UTF_8);
+
+        let csv_options = CsvReadOptions::default()
+            .with_has_header(!options.headerless)
+            .with_infer_schema_length(Some(0)); // all columns as String
+
+        let df = if Self::is_cloud_path(&self.base_path) {
+            if encoding.is_some() {
+                return Err(SdtError::General(
+                    "Non-UTF-8 encodings are only supported for local base paths".into(),
+                ));
+            }
+            self.read_cloud_csv(filename, !options.headerless)?
+        } else if let Some(enc) = encoding {
+            let bytes = std::fs::read(self.base_path.join(filename))?;
+            let (text, _, _) = enc.decode(&bytes);
+            csv_options
+                .into_reader_with_file_handle(Cursor::new(text.into_owned().into_bytes()))
+                .finish()?
+        } else {
+            let path = self.base_path.join(filename);
+            csv_options
+                .try_into_reader_with_file_path(Some(path))?
+                .finish()?
+        };
+
+        Self::normalize_headers(df, options)
+    }
+
+    /// Trim whitespace from column names, or replace them with `options.column_names`,
+    /// then apply the optional rename map.
+    fn normalize_headers(mut df: DataFrame, options: &ReadOptions) -> Result<DataFrame, SdtError> {
+        let names: Vec<String> = match &options.column_names {
+            Some(names) if names.len() != df.width() => {
+                return Err(SdtError::Validation(format!(
+                    "{} column names given, but the file has {} columns",
+                    names.len(),
+                    df.width()
+                )))
+            }
+            Some(names) => names.iter().map(|c| c.trim().to_string()).collect(),
+            None => df
+                .get_column_names_str()
+                .iter()
+                .map(|c| c.trim().to_string())
+                .collect(),
+        };
+        df.set_column_names(names.as_slice())?;
+
+        if let Some(map) = &options.rename {
+            let old: Vec<&str> = map.keys().map(|s| s.as_str()).collect();
+            let new: Vec<&str> = map.values().map(|s| s.as_str()).collect();
+            df = df.lazy().rename(old, new, true).collect()?;
+        }
+
+        Ok(df)
+    }
+
+    /// True when the base path is an object-store URL (s3://, gs://, ...) rather than a local directory.
+    fn is_cloud_path(base_path: &Path) -> bool {
+        let base = base_path.to_string_lossy();
+        ["s3://", "s3a://", "gs://", "gcs://"]
+            .iter()
+            .any(|scheme| base.starts_with(scheme))
+    }
+
+    /// Read a CSV from an object store with all columns as String dtype.
+    /// Credentials are picked up from the environment (AWS_*, GOOGLE_* variables).
+    #[cfg(feature = "cloud")]
+    fn read_cloud_csv(&self, filename: &str, has_header: bool) -> Result<DataFrame, SdtError> {
+        let base = self.base_path.to_string_lossy();
+        let url = format!("{}/{}", base.trim_end_matches('/'), filename);
+        let cloud_options =
+            polars::io::cloud::CloudOptions::from_untyped_config(&url, std::iter::empty::<(&str, String)>())?;
+        let df = LazyCsvReader::new(PlPath::new(&url))
+            .with_has_header(has_header)
+            .with_infer_schema_length(Some(0)) // all columns as String
+            .with_cloud_options(Some(cloud_options))
+            .finish()?
+            .collect()?;
+        Ok(df)
+    }
+
+    #[cfg(not(feature = "cloud"))]
+    fn read_cloud_csv(&self, _filename: &str, _has_header: bool) -> Result<DataFrame, SdtError> {
+        Err(SdtError::General(format!(
+            "base_path '{}' is an object-store URL, but aqua-tracekit was built without the `cloud` feature",
+            self.base_path.display()
+        )))
+    }
+
+    /// `df` as kept on the model: with `categorical`, the transfer id columns are cast
+    /// to Categorical so each id is stored once.
+    fn stored_ids(df: DataFrame, categorical: bool) -> Result<DataFrame, SdtError> {
+        if !categorical {
+            return Ok(df);
+        }
+        let dtype = DataType::from_categories(Categories::global());
+        Ok(df
+            .lazy()
+            .with_columns(
+                [transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID]
+                    .map(|c| col(c).cast(dtype.clone())),
+            )
+            .collect()?)
+    }
+
+    /// The loaded transfers with String id columns, cast back from Categorical when
+    /// the model keeps them that way.
+    fn loaded_transfers(&self) -> Result<DataFrame, SdtError> {
+        let transfers = self
+            .transfers
+            .as_ref()
+            .ok_or_else(|| SdtError::NotLoaded("transfers".into()))?;
+        if !self.categorical_ids {
+            return Ok(transfers.clone());
+        }
+        Ok(transfers
+            .clone()
+            .lazy()
+            .with_columns(
+                [transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID]
+                    .map(|c| col(c).cast(DataType::String)),
+            )
+            .collect()?)
+    }
+
+    fn get_or_build_tracer(&mut self) -> Result<&DagTracer, SdtError> {
+        if self.tracer.is_none() {
+            let transfers = &self.loaded_transfers()?;
+            self.tracer = Some(DagTracer::from_transfers(transfers)?);
+        }
+        Ok(self.tracer.as_ref().unwrap())
+    }
+
+    /// Reject `method` when the transfers have no stock columns, i.e. a factor-only
+    /// file loaded with `create_missing_stock=False`.
+    fn require_stock_columns(transfers: &DataFrame, method: &str) -> Result<(), SdtError> {
+        let schema = transfers.schema();
+        if [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]
+            .iter()
+            .all(|c| schema.contains(c))
+        {
+            return Ok(());
+        }
+        Err(SdtError::Validation(format!(
+            "{method} needs '{}' and '{}', but the transfers were loaded without stock \
+             columns (create_missing_stock=False)",
+            transfer::TRANSFER_COUNT,
+            transfer::TRANSFER_BIOMASS_KG
+        )))
+    }
+
+    /// Stock columns when the file provided them, otherwise the forward share factors,
+    /// so factor-only transfer files don't end up with empty tooltips.
+    fn default_transfer_tooltip_cols(transfers: &DataFrame) -> Vec<String> {
+        let has_stock = [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]
+            .iter()
+            .all(|c| {
+                transfers
+                    .column(c)
+                    .map(|col| col.null_count() < col.len())
+                    .unwrap_or(false)
+            });
+        let cols = if has_stock {
+            [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]
+        } else {
+            [factors::SHARE_COUNT_FORWARD, factors::SHARE_BIOMASS_FORWARD]
+        };
+        cols.iter().map(|c| c.to_string()).collect()
+    }
+
+    /// Core of `merge_segments`; see its docs.
+    fn merge_segment_ids(&mut self, mapping: &HashMap<String, String>) -> Result<DataFrame, SdtError> {
+        let transfers = &self.loaded_transfers()?;
+
+        let mut remapped = transfers.clone();
+        for column in [transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID] {
+            let ids = Self::remap_ids(remapped.column(column)?.str()?, mapping);
+            remapped.with_column(ids)?;
+        }
+
+        let keys = [transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID];
+        let stock_cols = [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG];
+        let stock_complete = stock_cols
+            .iter()
+            .all(|c| remapped.column(c).is_ok_and(|s| s.null_count() == 0));
+        let summed: Vec<&str> = if stock_complete {
+            stock_cols.to_vec()
+        } else {
+            factors::ALL.to_vec()
+        };
+
+        let schema = remapped.schema();
+        let mut aggs: Vec<Expr> = Vec::new();
+        for name in schema.iter_names() {
+            let name = name.as_str();
+            if keys.contains(&name) || (stock_complete && factors::ALL.contains(&name)) {
+                continue;
+            }
+            aggs.push(if summed.contains(&name) {
+                col(name).sum()
+            } else {
+                col(name).first()
+            });
+        }
+
+        let mut lazy = remapped
+            .lazy()
+            .filter(col(transfer::SOURCE_SEGMENT_ID).neq(col(transfer::DEST_SEGMENT_ID)))
+            .group_by_stable(keys.map(col))
+            .agg(aggs);
+        if !stock_complete {
+            // Stock no longer matches the summed factors
+            lazy = lazy.with_columns(stock_cols.map(|c| lit(NULL).cast(DataType::Float64).alias(c)));
+        }
+        let grouped = lazy.collect()?;
+        if !stock_complete {
+            Self::reject_factor_sums_above_one(&grouped)?;
+        }
+        let merged = Self::prepare_transfers(grouped, true)?;
+
+        let tracer = DagTracer::from_transfers(&merged)?;
+        if tracer.has_cycle() {
+            return Err(SdtError::Validation(
+                "Merging these segments would introduce a cycle in the transfer graph".into(),
+            ));
+        }
+
+        if let Some(segments) = &self.segments {
+            let mut segments = segments.clone();
+            let ids = Self::remap_ids(
+                segments.column(segment::SEGMENT_ID)?.str()?,
+                mapping,
+            );
+            segments.with_column(ids)?;
+            let end_dtype = segments.column(segment::END_TIME)?.dtype().clone();
+            let aggs: Vec<Expr> = segments
+                .get_column_names()
+                .into_iter()
+                .filter(|name| name.as_str() != segment::SEGMENT_ID)
+                .map(|name| match name.as_str() {
+                    segment::START_TIME => col(segment::START_TIME).min(),
+                    // A part still open keeps the merged segment open
+                    segment::END_TIME => when(col(segment::END_TIME).null_count().gt(lit(0)))
+                        .then(lit(NULL).cast(end_dtype.clone()))
+                        .otherwise(col(segment::END_TIME).max())
+                        .alias(segment::END_TIME),
+                    other => col(other).first(),
+                })
+                .collect();
+            let segments = segments
+                .lazy()
+                .group_by_stable([col(segment::SEGMENT_ID)])
+                .agg(aggs)
+                .collect()
+                ?;
+            self.segments = Some(segments);
+        }
+
+        self.transfers = Some(Self::stored_ids(merged.clone(), self.categorical_ids)?);
+        self.tracer = Some(tracer);
+        self.trace_cache.clear();
+        Ok(merged)
+    }
+
+    /// Core of `append_transfers`; see its docs.
+    fn append_transfer_file(
+        &mut self,
+        filename: &str,
+        options: &ReadOptions,
+    ) -> Result<DataFrame, SdtError> {
+        let existing = self.loaded_transfers()?;
+
+        let raw = self.read_table(filename, options)?;
+        let file_cols = raw.get_column_names_owned();
+        let df = Self::prepare_transfers(raw, true)?;
+        let appended_derived = Self::columns_not_in(&df, &file_cols);
+
+        let ids = |name: &str| -> Result<Series, SdtError> {
+            Ok(existing.column(name)?.as_materialized_series().clone())
+        };
+        let shared = df
+            .clone()
+            .lazy()
+            .filter(
+                col(transfer::SOURCE_SEGMENT_ID)
+                    .is_in(lit(ids(transfer::SOURCE_SEGMENT_ID)?), false)
+                    .or(col(transfer::DEST_SEGMENT_ID)
+                        .is_in(lit(ids(transfer::DEST_SEGMENT_ID)?), false)),
+            )
+            .collect()?;
+        let stacked = concat_lf_diagonal(
+            [existing.clone().lazy(), df.clone().lazy()],
+            UnionArgs::default(),
+        )?;
+
+        let recalculated = shared.height() > 0;
+        let (combined, appended) = if recalculated {
+            // A shared segment's factors depend on its transfers in both frames
+            let derived = |columns: &[String]| {
+                factors::ALL.iter().all(|f| columns.iter().any(|c| c == f))
+            };
+            if !derived(&self.derived_columns) || !derived(&appended_derived) {
+                return Err(SdtError::InvalidRows {
+                    message: format!(
+                        "{} appended transfers share a source or dest segment with the loaded \
+                         transfers, and share factors read from file can't be recalculated over both",
+                        shared.height()
+                    ),
+                    column: None,
+                    rows: shared,
+                });
+            }
+            let stock_only = stacked.drop(cols(factors::ALL)).collect()?;
+            let combined = Self::prepare_transfers(stock_only, true)?;
+            let appended = combined.slice(existing.height() as i64, df.height());
+            (combined, appended)
+        } else {
+            (stacked.collect()?, df)
+        };
+
+        match self.tracer.as_mut() {
+            Some(tracer) if !recalculated => tracer.add_transfers(&appended)?,
+            _ => {
+                if !cyclic_transfer_rows(&combined)?.is_empty() {
+                    return Err(SdtError::Validation(
+                        "Appended transfers would introduce a cycle in the transfer graph".into(),
+                    ));
+                }
+                self.tracer = None;
+            }
+        }
+        // A column is derived only if neither the loaded nor the appended file had it
+        let read: Vec<PlSmallStr> = existing
+            .get_column_names_owned()
+            .into_iter()
+            .filter(|c| !self.derived_columns.iter().any(|d| d == c.as_str()))
+            .chain(file_cols)
+            .collect();
+        self.derived_columns = Self::columns_not_in(&combined, &read);
+        self.transfers = Some(Self::stored_ids(combined, self.categorical_ids)?);
+        self.trace_cache.clear();
+        Ok(appended)
+    }
+
+    /// Core of `diff_transfers`; see its docs.
+    fn diff_transfer_file(
+        &self,
+        filename: &str,
+        tolerance: f64,
+        options: &ReadOptions,
+    ) -> Result<DataFrame, SdtError> {
+        let existing = &self.loaded_transfers()?;
+
+        let raw = self.read_table(filename, options)?;
+        let file_cols = raw.get_column_names_owned();
+        let other = Self::prepare_transfers(raw, true)?;
+        let other_derived = Self::columns_not_in(&other, &file_cols);
+        Self::reject_duplicate_pairs(existing, "loaded transfers")?;
+        Self::reject_duplicate_pairs(&other, filename)?;
+
+        let keys = [transfer::SOURCE_SEGMENT_ID, transfer::DEST_SEGMENT_ID];
+        // Stock is only compared where both sides read it; factors always exist
+        let read_by = |df: &DataFrame, derived: &[String], c: &str| {
+            df.column(c).is_ok() && !derived.iter().any(|d| d == c)
+        };
+        let values: Vec<&str> = [transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG]
+            .into_iter()
+            .filter(|c| {
+                read_by(existing, &self.derived_columns, c) && read_by(&other, &other_derived, c)
+            })
+            .chain(factors::ALL)
+            .collect();
+
+        let old = existing
+            .clone()
+            .lazy()
+            .select(keys.iter().chain(&values).map(|c| col(*c)).collect::<Vec<_>>())
+            .with_column(lit(true).alias("_in_old"));
+        let new = other
+            .lazy()
+            .select(
+                keys.iter()
+                    .map(|c| col(*c))
+                    .chain(values.iter().map(|c| col(*c).alias(format!("{c}_new"))))
+                    .collect::<Vec<_>>(),
+            )
+            .with_column(lit(true).alias("_in_new"));
+
+        // A column differs if exactly one side is null or both are set and far apart
+        let differs = |c: &str| {
+            let (a, b) = (col(c), col(format!("{c}_new")));
+            a.clone().is_null().neq(b.clone().is_null()).or((a - b).abs().gt(lit(tolerance)))
+        };
+        let any_differs = values
+            .iter()
+            .map(|c| differs(c))
+            .reduce(|a, b| a.or(b))
+            .unwrap_or(lit(false));
+        let changed_parts: Vec<Expr> = values
+            .iter()
+            .map(|c| when(differs(c)).then(lit(*c)).otherwise(lit(NULL)))
+            .collect();
+
+        let key_cols: Vec<Expr> = keys.iter().map(|c| col(*c)).collect();
+        let result = old
+            .join(
+                new,
+                key_cols.clone(),
+                key_cols,
+                JoinArgs::new(JoinType::Full).with_coalesce(JoinCoalesce::CoalesceColumns),
+            )
+            .with_column(
+                when(col("_in_old").is_null())
+                    .then(lit("added"))
+                    .when(col("_in_new").is_null())
+                    .then(lit("removed"))
+                    .when(any_differs)
+                    .then(lit("changed"))
+                    .otherwise(lit(NULL))
+                    .alias("change"),
+            )
+            .filter(col("change").is_not_null())
+            .with_column(
+                when(col("change").eq(lit("changed")))
+                    .then(concat_str(changed_parts, ", ", true))
+                    .otherwise(lit(NULL))
+                    .alias("changed_columns"),
+            )
+            .select([
+                col(transfer::SOURCE_SEGMENT_ID),
+                col(transfer::DEST_SEGMENT_ID),
+                col("change"),
+                col("changed_columns"),
+            ])
+            .collect()?;
+        Ok(result)
+    }
+
+    /// Fail with the rows of `transfers` that share a (source, dest) pair with another
+    /// row; `what` names the frame in the message.
+    fn reject_duplicate_pairs(transfers: &DataFrame, what: &str) -> Result<(), SdtError> {
+        let rows = transfers
+            .clone()
+            .lazy()
+            .filter(
+                len()
+                    .over([col(transfer::SOURCE_SEGMENT_ID), col(transfer::DEST_SEGMENT_ID)])
+                    .gt(lit(1)),
+            )
+            .collect()?;
+        if rows.height() > 0 {
+            return Err(SdtError::InvalidRows {
+                message: format!(
+                    "{what} has {} rows sharing a (source_segment_id, dest_segment_id) pair",
+                    rows.height()
+                ),
+                column: None,
+                rows,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail with the offending rows if summed share factors leave a source's forward
+    /// (or a dest's backward) factors adding up to more than 1. Without stock there is
+    /// nothing to reweight merged segments by, so such a merge can't be represented.
+    fn reject_factor_sums_above_one(transfers: &DataFrame) -> Result<(), SdtError> {
+        let over_one = |factor: &str, key: &str| {
+            col(factor).sum().over([col(key)]).gt(lit(1.0 + 1e-9))
+        };
+        let exceeds = over_one(factors::SHARE_COUNT_FORWARD, transfer::SOURCE_SEGMENT_ID)
+            .or(over_one(factors::SHARE_BIOMASS_FORWARD, transfer::SOURCE_SEGMENT_ID))
+            .or(over_one(factors::SHARE_COUNT_BACKWARD, transfer::DEST_SEGMENT_ID))
+            .or(over_one(factors::SHARE_BIOMASS_BACKWARD, transfer::DEST_SEGMENT_ID));
+        let rows = transfers.clone().lazy().filter(exceeds).collect()?;
+        if rows.height() > 0 {
+            return Err(SdtError::InvalidRows {
+                message: format!(
+                    "{} merged transfers would have share factors summing above 1; \
+                     merging segments without complete stock columns can't reweight them",
+                    rows.height()
+                ),
+                column: None,
+                rows,
+            });
+        }
+        Ok(())
+    }
+
+    /// Core of `merge_containers`; see its docs.
+    fn merge_container_ids(&mut self, mapping: &HashMap<String, String>) -> Result<(), SdtError> {
+        if self.containers.is_none() && self.segments.is_none() {
+            return Err(SdtError::NotLoaded("containers or segments".into()));
+        }
+
+        let segments = match &self.segments {
+            Some(segments) => {
+                let mut segments = segments.clone();
+                let ids = Self::remap_ids(segments.column(segment::CONTAINER_ID)?.str()?, mapping);
+                segments.with_column(ids)?;
+                let targets: HashSet<&str> = mapping.values().map(String::as_str).collect();
+                let overlaps = Self::overlapping_segments(
+                    &segments,
+                    targets.into_iter().collect(),
+                    self.interval_closed,
+                )?;
+                if overlaps.height() > 0 {
+                    return Err(SdtError::InvalidRows {
+                        message: format!(
+                            "{} consecutive pairs of segments would overlap in merged containers",
+                            overlaps.height()
+                        ),
+                        column: Some(segment::CONTAINER_ID.to_string()),
+                        rows: overlaps,
+                    });
+                }
+                Some(segments)
+            }
+            None => None,
+        };
+
+        let containers = match &self.containers {
+            Some(containers) => {
+                let mut containers = containers.clone();
+                let ids =
+                    Self::remap_ids(containers.column(container::CONTAINER_ID)?.str()?, mapping);
+                containers.with_column(ids)?;
+                let aggs: Vec<Expr> = containers
+                    .get_column_names()
+                    .into_iter()
+                    .filter(|name| name.as_str() != container::CONTAINER_ID)
+                    .map(|name| col(name.as_str()).first())
+                    .collect();
+                let containers = containers
+                    .lazy()
+                    .group_by_stable([col(container::CONTAINER_ID)])
+                    .agg(aggs)
+                    .collect()?;
+                Some(containers)
+            }
+            None => None,
+        };
+
+        if segments.is_some() {
+            self.segments = segments;
+        }
+        if containers.is_some() {
+            self.containers = containers;
+        }
+        Ok(())
+    }
+
+    /// Consecutive segments (by `start_time`) of the `container_ids` containers whose
+    /// intervals share an instant under `closed`: the next one starts before the
+    /// previous one ends, after one without an end_time, or exactly at its end when
+    /// both bounds are closed (always for "both"). Columns as in `segments_with_gaps`,
+    /// without `gap_us`.
+    fn overlapping_segments(
+        segments: &DataFrame,
+        container_ids: Vec<&str>,
+        closed: IntervalClosed,
+    ) -> Result<DataFrame, SdtError> {
+        let by_container = || [col(segment::CONTAINER_ID)];
+        let prev = |name: &str| col(name).shift(lit(1)).over(by_container());
+        let ids = Series::new("container_ids".into(), container_ids);
+        // Instant segments contain their single time point whatever `closed` says
+        let prev_end_closed = match closed {
+            IntervalClosed::Right | IntervalClosed::Both => lit(true),
+            IntervalClosed::Left | IntervalClosed::Neither => {
+                col("prev_start_time").eq(col("prev_end_time"))
+            }
+        };
+        let next_start_closed = match closed {
+            IntervalClosed::Left | IntervalClosed::Both => lit(true),
+            IntervalClosed::Right | IntervalClosed::Neither => {
+                col(segment::START_TIME).eq(col(segment::END_TIME))
+            }
+        };
+        let touching = col(segment::START_TIME)
+            .eq(col("prev_end_time"))
+            .and(prev_end_closed)
+            .and(next_start_closed);
+        let overlaps = col("prev_segment_id").is_not_null().and(
+            col("prev_end_time")
+                .is_null()
+                .or(col(segment::START_TIME).lt(col("prev_end_time")))
+                .or(touching),
+        );
+
+        Ok(segments
+            .clone()
+            .lazy()
+            .filter(col(segment::CONTAINER_ID).is_in(lit(ids), false))
+            .sort(
+                [segment::CONTAINER_ID, segment::START_TIME],
+                SortMultipleOptions::default(),
+            )
+            .with_columns([
+                prev(segment::SEGMENT_ID).alias("prev_segment_id"),
+                prev(segment::START_TIME).alias("prev_start_time"),
+                prev(segment::END_TIME).alias("prev_end_time"),
+            ])
+            .filter(overlaps)
+            .select([
+                col(segment::CONTAINER_ID),
+                col("prev_segment_id"),
+                col(segment::SEGMENT_ID).alias("next_segment_id"),
+                col("prev_end_time"),
+                col(segment::START_TIME).alias("next_start_time"),
+            ])
+            .collect()?)
+    }
+
+    /// The `expected_schema` description of every input file, keyed by file kind.
+    fn input_file_specs() -> BTreeMap<&'static str, FileSpec> {
+        let factor_description = [
+            "Share of the source segment's count moved by this transfer",
+            "Share of the source segment's biomass moved by this transfer",
+            "Share of the dest segment's count that came from the source",
+            "Share of the dest segment's biomass that came from the source",
+        ];
+        let mut transfer_columns = vec![
+            ColumnSpec::new(transfer::SOURCE_SEGMENT_ID, "string", true, "Segment the fish left"),
+            ColumnSpec::new(transfer::DEST_SEGMENT_ID, "string", true, "Segment the fish entered"),
+            ColumnSpec::new(transfer::TRANSFER_COUNT, "float", false, "Number of fish moved")
+                .derived(),
+            ColumnSpec::new(transfer::TRANSFER_BIOMASS_KG, "float", false, "Biomass moved in kg")
+                .derived(),
+        ];
+        transfer_columns.extend(
+            factors::ALL
+                .iter()
+                .zip(factor_description)
+                .map(|(name, description)| ColumnSpec::new(name, "float", false, description).derived()),
+        );
+
+        let segment_columns = vec![
+            ColumnSpec::new(segment::SEGMENT_ID, "string", true, "Unique segment id"),
+            ColumnSpec::new(segment::CONTAINER_ID, "string", true, "Container holding the segment"),
+            ColumnSpec::new(segment::START_TIME, "datetime", true, "Start of the segment"),
+            ColumnSpec::new(
+                segment::END_TIME,
+                "datetime",
+                true,
+                "End of the segment; empty while still active (see infer_end_time)",
+            ),
+        ];
+        let timeseries_columns = |id: &'static str, description: &'static str| {
+            vec![
+                ColumnSpec::new(id, "string", true, description),
+                ColumnSpec::new(timeseries::DATE_TIME, "datetime", true, "Time of the reading"),
+            ]
+        };
+
+        BTreeMap::from([
+            (
+                "transfers",
+                FileSpec {
+                    default_filename: Some(DEFAULT_FILENAMES[0].1),
+                    loader: "load_transfers",
+                    columns: transfer_columns,
+                    requires_one_of: vec![
+                        vec![transfer::TRANSFER_COUNT, transfer::TRANSFER_BIOMASS_KG],
+                        factors::ALL.to_vec(),
+                    ],
+                    extra_columns_kept: true,
+                },
+            ),
+            (
+                "segments",
+                FileSpec {
+                    default_filename: Some(DEFAULT_FILENAMES[1].1),
+                    loader: "load_segments",
+                    columns: segment_columns,
+                    requires_one_of: Vec::new(),
+                    extra_columns_kept: true,
+                },
+            ),
+            (
+                "containers",
+                FileSpec {
+                    default_filename: Some(DEFAULT_FILENAMES[2].1),
+                    loader: "load_containers",
+                    columns: vec![ColumnSpec::new(
+                        container::CONTAINER_ID,
+                        "string",
+                        true,
+                        "Unique container id",
+                    )],
+                    requires_one_of: Vec::new(),
+                    extra_columns_kept: true,
+                },
+            ),
+            (
+                "segment_timeseries",
+                FileSpec {
+                    default_filename: None,
+                    loader: "load_segment_timeseries",
+                    columns: timeseries_columns(segment::SEGMENT_ID, "Segment the reading belongs to"),
+                    requires_one_of: Vec::new(),
+                    extra_columns_kept: true,
+                },
+            ),
+            (
+                "container_timeseries",
+                FileSpec {
+                    default_filename: None,
+                    loader: "load_container_timeseries",
+                    columns: timeseries_columns(
+                        container::CONTAINER_ID,
+                        "Container the reading was taken in",
+                    ),
+                    requires_one_of: Vec::new(),
+                    extra_columns_kept: true,
+                },
+            ),
+        ])
+    }
+
+    /// Group a flat trace frame by origin into the `trace_to_json` shape.
+    fn nest_trace(df: &DataFrame) -> Result<String, SdtError> {
+        let origins = df.column(traceability::ORIGIN_SEGMENT_ID)?.str()?;
+        let traced = df.column(traceability::TRACED_SEGMENT_ID)?.str()?;
+        let directions = df.column(traceability::TRACE_DIRECTION)?.str()?;
+        let factor_cols = factors::ALL
+            .iter()
+            .map(|name| df.column(name).and_then(|c| c.f64()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut result: Vec<OriginTrace> = Vec::new();
+        for i in 0..df.height() {
+            let origin = origins.get(i).unwrap_or_default();
+            if result.last().is_none_or(|o| o.origin_segment_id != origin) {
+                result.push(OriginTrace {
+                    origin_segment_id: origin.to_string(),
+                    traced: Vec::new(),
+                });
+            }
+            let factor = |j: usize| factor_cols[j].get(i).unwrap_or(f64::NAN);
+            result.last_mut().unwrap().traced.push(TracedSegment {
+                segment_id: traced.get(i).unwrap_or_default().to_string(),
+                direction: directions.get(i).unwrap_or_default().to_string(),
+                share_count_forward: factor(0),
+                share_biomass_forward: factor(1),
+                share_count_backward: factor(2),
+                share_biomass_backward: factor(3),
+            });
+        }
+
+        serde_json::to_string(&result).map_err(|e| SdtError::General(e.to_string()))
+    }
+
+    /// Remove `strip_chars` and the `thousands` separator from a string column, then
+    /// trim whitespace, ready for a numeric cast.
+    fn clean_numeric_string(column: &str, strip_chars: Option<&str>, thousands: Option<char>) -> Expr {
+        let mut expr = col(column);
+        for c in strip_chars.unwrap_or_default().chars().chain(thousands) {
+            expr = expr.str().replace_all(lit(c.to_string()), lit(""), true);
+        }
+        expr.str().strip_chars(lit(" \t\r\n"))
+    }
+
+    /// Adapt an optional Python `callback(done, total)` to the Rust progress hooks.
+    ///
+    /// Calls are throttled to about `PROGRESS_UPDATES` per run plus the final step.
+    /// The first exception raised by the callback is kept in `error` and stops
+    /// further calls; the caller returns it once the work is done.
+    fn progress_reporter<'a>(
+        py: Python<'a>,
+        callback: Option<&'a PyObject>,
+        error: &'a mut Option<PyErr>,
+    ) -> impl FnMut(usize, usize) + 'a {
+        move |done, total| {
+            let Some(callback) = callback else { return };
+            let step = (total / PROGRESS_UPDATES).max(1);
+            if error.is_some() || (done % step != 0 && done != total) {
+                return;
+            }
+            if let Err(e) = callback.call1(py, (done, total)) {
+                *error = Some(e);
+            }
+        }
+    }
+
+    /// Rewrite ids found in `mapping`, keeping the column name.
+    fn remap_ids(ids: &StringChunked, mapping: &HashMap<String, String>) -> Column {
+        let remapped: StringChunked = ids
+            .into_iter()
+            .map(|id| id.map(|s| mapping.get(s).map(String::as_str).unwrap_or(s)))
+            .collect();
+        remapped.with_name(ids.name().clone()).into_column()
+    }
+
+    /// Fail with every required column that is missing, listing the columns present.
+    fn require_columns(df: &DataFrame, required: &[&str]) -> Result<(), SdtError> {
+        let missing: Vec<String> = required
+            .iter()
+            .filter(|&&col_name| df.column(col_name).is_err())
+            .map(|col_name| col_name.to_string())
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let found = df
+            .get_column_names()
+            .into_iter()
+            .map(|c| c.to_string())
+            .collect();
+        Err(SdtError::MissingColumns { missing, found })
+    }
+
+    /// Parse a string column to Datetime. Handles null values gracefully.
+    /// Columns that are not strings (e.g. already-typed Arrow input) are left as is.
+    fn parse_datetime_column(
+        df: DataFrame,
+        column: &str,
+        format: &str,
+    ) -> Result<DataFrame, SdtError> {
+        let us_per_unit = match format {
+            "epoch_s" => Some(1_000_000),
+            "epoch_ms" => Some(1_000),
+            "epoch_us" => Some(1),
+            _ => None,
+        };
+        if let Some(us_per_unit) = us_per_unit {
+            return Self::parse_epoch_column(df, column, us_per_unit);
+        }
+        if df.column(column).is_ok_and(|c| c.dtype() == &DataType::String) {
+            let df = df
+                .lazy()
+                .with_columns([col(column)
+                    .str()
+                    .strip_chars(lit(" \t\r\n"))
+                    .str()
+                    .to_datetime(
+                        Some(TimeUnit::Microseconds),
+                        None,
+                        StrptimeOptions {
+                            format: Some(format.into()),
+                            strict: true,
+                            ..Default::default()
+                        },
+                        lit("raise"),
+                    )])
+                .collect()?;
+            Ok(df)
+        } else {
+            Ok(df)
+        }
+    }
+
+    /// Convert an epoch column (strings or integers) counting units of `us_per_unit`
+    /// microseconds to Datetime. Values that are not integers raise.
+    fn parse_epoch_column(df: DataFrame, column: &str, us_per_unit: i64) -> Result<DataFrame, SdtError> {
+        let dtype = match df.column(column) {
+            Ok(c) => c.dtype().clone(),
+            Err(_) => return Ok(df),
+        };
+        let ints = match dtype {
+            DataType::String => col(column)
+                .str()
+                .strip_chars(lit(" \t\r\n"))
+                .strict_cast(DataType::Int64),
+            dt if dt.is_integer() => col(column).cast(DataType::Int64),
+            _ => return Ok(df),
+        };
+        let df = df
+            .lazy()
+            .with_columns([(ints * lit(us_per_unit))
+                .cast(DataType::Datetime(TimeUnit::Microseconds, None))
+                .alias(column)])
+            .collect()?;
+        Ok(df)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::AggKind;
+
+    const HOUR_MS: i64 = 3_600_000;
+    const HOUR_US: i64 = 3_600_000_000;
+
+    /// Datetime column in milliseconds, so tests also cover non-microsecond inputs.
+    fn datetime_ms(name: &str, hours: &[Option<i64>]) -> Column {
+        let ms: Vec<Option<i64>> = hours.iter().map(|h| h.map(|h| h * HOUR_MS)).collect();
+        Column::new(name.into(), ms)
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))
+            .unwrap()
+    }
+
+    /// Segments `(id, container, start hour, end hour)`.
+    fn segments(rows: &[(&str, &str, i64, Option<i64>)]) -> DataFrame {
+        let starts: Vec<Option<i64>> = rows.iter().map(|r| Some(r.2)).collect();
+        let ends: Vec<Option<i64>> = rows.iter().map(|r| r.3).collect();
+        DataFrame::new(vec![
+            Column::new(
+                segment::SEGMENT_ID.into(),
+                rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                segment::CONTAINER_ID.into(),
+                rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+            ),
+            datetime_ms(segment::START_TIME, &starts),
+            datetime_ms(segment::END_TIME, &ends),
+        ])
+        .unwrap()
+    }
+
+    /// Transfers `(source, dest, count)` with biomass equal to count, so the loader
+    /// derives the share factors.
+    fn transfers(rows: &[(&str, &str, f64)]) -> DataFrame {
+        DataFrame::new(vec![
+            Column::new(
+                transfer::SOURCE_SEGMENT_ID.into(),
+                rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                transfer::DEST_SEGMENT_ID.into(),
+                rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                transfer::TRANSFER_COUNT.into(),
+                rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                transfer::TRANSFER_BIOMASS_KG.into(),
+                rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+            ),
+        ])
+        .unwrap()
+    }
+
+    fn str_values(df: &DataFrame, name: &str) -> Vec<Option<String>> {
+        let c = df.column(name).unwrap().str().unwrap();
+        c.into_iter().map(|v| v.map(String::from)).collect()
+    }
+
+    fn i64_values(df: &DataFrame, name: &str) -> Vec<Option<i64>> {
+        df.column(name).unwrap().i64().unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn segment_lifespan_normalizes_time_unit() {
+        let model = SdtModel::builder()
+            .segments(segments(&[("S1", "C1", 0, Some(24)), ("S2", "C1", 24, None)]))
+            .build()
+            .unwrap();
+        let PyDataFrame(df) = model.segment_lifespan(None).unwrap();
+        assert_eq!(
+            i64_values(&df, segment::LIFESPAN_US),
+            vec![Some(24 * HOUR_US), None]
+        );
+        let days: Vec<Option<f64>> = df
+            .column(segment::LIFESPAN_DAYS)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(days, vec![Some(1.0), None]);
+    }
+
+    #[test]
+    fn segments_active_histogram_counts_per_tick() {
+        let mut model = SdtModel::builder()
+            .segments(segments(&[
+                ("S1", "C1", 0, Some(2)),
+                ("S2", "C1", 2, Some(3)),
+                ("S3", "C2", 1, None),
+            ]))
+            .build()
+            .unwrap();
+        let PyDataFrame(df) = model.segments_active_histogram("1h", None, None).unwrap();
+        assert_eq!(
+            i64_values(&df, "active_count"),
+            vec![Some(1), Some(2), Some(2), Some(1)]
+        );
+        model.interval_closed = IntervalClosed::Both;
+        let PyDataFrame(both) = model.segments_active_histogram("1h", None, None).unwrap();
+        assert_eq!(
+            i64_values(&both, "active_count"),
+            vec![Some(1), Some(2), Some(3), Some(2)]
+        );
+        let ticks: Vec<Option<i64>> = df
+            .column(timeseries::DATE_TIME)
+            .unwrap()
+            .cast(&DataType::Int64)
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(ticks, (0..4).map(|h| Some(h * HOUR_US)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn merge_containers_rejects_overlaps_per_interval_closed() {
+        let touching = segments(&[("S1", "C1", 0, Some(2)), ("S2", "C2", 2, Some(4))]);
+        let mapping = HashMap::from([("C2".to_string(), "C1".to_string())]);
+
+        let mut model = SdtModel::builder()
+            .segments(touching.clone())
+            .build()
+            .unwrap();
+        model.merge_container_ids(&mapping).unwrap();
+        let merged = model.segments().unwrap().column(segment::CONTAINER_ID).unwrap();
+        assert_eq!(merged.str().unwrap().get(1), Some("C1"));
+
+        let mut model = SdtModel::builder().segments(touching).build().unwrap();
+        model.interval_closed = IntervalClosed::Both;
+        match model.merge_container_ids(&mapping) {
+            Err(SdtError::InvalidRows { message, rows, .. }) => {
+                assert!(message.contains("consecutive pairs"), "{message}");
+                assert_eq!(rows.height(), 1);
+            }
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+        let unchanged = model.segments().unwrap().column(segment::CONTAINER_ID).unwrap();
+        assert_eq!(unchanged.str().unwrap().get(1), Some("C2"));
+
+        let mut model = SdtModel::builder()
+            .segments(segments(&[("S1", "C1", 0, Some(3)), ("S2", "C2", 2, None)]))
+            .build()
+            .unwrap();
+        assert!(model.merge_container_ids(&mapping).is_err());
+    }
+
+    #[test]
+    fn reachability_matrix_dedups_and_rejects_row_id_column() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0), ("B", "C", 10.0), ("X", "C", 5.0)]))
+            .build()
+            .unwrap();
+        let ids = vec!["B".to_string(), "A".to_string(), "B".to_string()];
+        let PyDataFrame(df) = model.reachability_matrix(ids, "forward").unwrap();
+        let names: Vec<&str> = df.get_column_names_str();
+        assert_eq!(names, vec![traceability::TRACED_SEGMENT_ID, "B", "A"]);
+        assert_eq!(
+            str_values(&df, traceability::TRACED_SEGMENT_ID),
+            vec![Some("A".into()), Some("B".into()), Some("C".into())]
+        );
+        let b: Vec<Option<bool>> = df.column("B").unwrap().bool().unwrap().into_iter().collect();
+        assert_eq!(b, vec![Some(false), Some(true), Some(true)]);
+
+        let ids = vec!["A".to_string(), traceability::TRACED_SEGMENT_ID.to_string()];
+        assert!(model.reachability_matrix(ids, "forward").is_err());
+    }
+
+    #[test]
+    fn merge_segments_keeps_open_end_and_sums_stock() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A1", "B", 10.0), ("A2", "B", 30.0)]))
+            .segments(segments(&[
+                ("A1", "C1", 0, Some(2)),
+                ("A2", "C2", 1, None),
+                ("B", "C3", 2, Some(4)),
+            ]))
+            .build()
+            .unwrap();
+        let mapping = HashMap::from([
+            ("A1".to_string(), "A".to_string()),
+            ("A2".to_string(), "A".to_string()),
+        ]);
+        let merged = model.merge_segment_ids(&mapping).unwrap();
+        assert_eq!(merged.height(), 1);
+        let count = merged.column(transfer::TRANSFER_COUNT).unwrap().f64().unwrap();
+        assert_eq!(count.get(0), Some(40.0));
+
+        let segments = model.segments().unwrap();
+        assert_eq!(
+            str_values(segments, segment::SEGMENT_ID),
+            vec![Some("A".into()), Some("B".into())]
+        );
+        assert_eq!(segments.column(segment::END_TIME).unwrap().null_count(), 1);
+        let start = segments.column(segment::START_TIME).unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(start.i64().unwrap().get(0), Some(0));
+    }
+
+    #[test]
+    fn merge_segments_rejects_factor_sums_above_one() {
+        let factor_only = DataFrame::new(vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), ["A1", "A2"]),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), ["B", "B"]),
+            Column::new(factors::SHARE_COUNT_FORWARD.into(), [1.0, 1.0]),
+            Column::new(factors::SHARE_BIOMASS_FORWARD.into(), [1.0, 1.0]),
+            Column::new(factors::SHARE_COUNT_BACKWARD.into(), [0.5, 0.5]),
+            Column::new(factors::SHARE_BIOMASS_BACKWARD.into(), [0.5, 0.5]),
+        ])
+        .unwrap();
+        let mut model = SdtModel::builder().transfers(factor_only).build().unwrap();
+        let mapping = HashMap::from([
+            ("A1".to_string(), "A".to_string()),
+            ("A2".to_string(), "A".to_string()),
+        ]);
+        match model.merge_segment_ids(&mapping) {
+            Err(SdtError::InvalidRows { rows, .. }) => assert_eq!(rows.height(), 1),
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+        assert_eq!(model.transfers().unwrap().height(), 2);
+    }
+
+    /// Fresh directory under the system temp dir for tests that read files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aqua_tracekit_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn diff_transfers_skips_missing_stock_and_rejects_duplicate_pairs() {
+        let dir = temp_dir("diff_transfers");
+        std::fs::write(
+            dir.join("with_stock.csv"),
+            "source_segment_id,dest_segment_id,transfer_count,transfer_biomass_kg\n\
+             A,B,10,10\nA,C,30,30\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("duplicates.csv"),
+            "source_segment_id,dest_segment_id,transfer_count,transfer_biomass_kg\n\
+             A,B,10,10\nA,B,5,5\nA,C,30,30\n",
+        )
+        .unwrap();
+        let factor_only = DataFrame::new(vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), ["A", "A"]),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), ["B", "C"]),
+            Column::new(factors::SHARE_COUNT_FORWARD.into(), [0.25, 0.75]),
+            Column::new(factors::SHARE_BIOMASS_FORWARD.into(), [0.25, 0.75]),
+            Column::new(factors::SHARE_COUNT_BACKWARD.into(), [1.0, 1.0]),
+            Column::new(factors::SHARE_BIOMASS_BACKWARD.into(), [1.0, 1.0]),
+        ])
+        .unwrap();
+        let model = SdtModel::builder()
+            .base_path(&dir)
+            .transfers(factor_only)
+            .build()
+            .unwrap();
+
+        let options = ReadOptions::default();
+        let diff = model.diff_transfer_file("with_stock.csv", 1e-9, &options).unwrap();
+        assert_eq!(diff.height(), 0);
+
+        match model.diff_transfer_file("duplicates.csv", 1e-9, &options) {
+            Err(SdtError::InvalidRows { rows, .. }) => assert_eq!(rows.height(), 2),
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+
+        // With stock on both sides it is compared too
+        let model = SdtModel::builder()
+            .base_path(&dir)
+            .transfers(transfers(&[("A", "B", 20.0), ("A", "C", 60.0)]))
+            .build()
+            .unwrap();
+        let diff = model.diff_transfer_file("with_stock.csv", 1e-9, &options).unwrap();
+        assert_eq!(
+            str_values(&diff, "changed_columns"),
+            vec![
+                Some("transfer_count, transfer_biomass_kg".into()),
+                Some("transfer_count, transfer_biomass_kg".into())
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn f64_values(df: &DataFrame, name: &str) -> Vec<Option<f64>> {
+        df.column(name).unwrap().f64().unwrap().into_iter().collect()
+    }
+
+    const STOCK_HEADER: &str =
+        "source_segment_id,dest_segment_id,transfer_count,transfer_biomass_kg\n";
+
+    #[test]
+    fn append_transfers_extends_built_tracer() {
+        let dir = temp_dir("append_disjoint");
+        std::fs::write(dir.join("more.csv"), format!("{STOCK_HEADER}C,D,5,5\n")).unwrap();
+        let mut model = SdtModel::builder()
+            .base_path(&dir)
+            .transfers(transfers(&[("A", "B", 10.0)]))
+            .build()
+            .unwrap();
+
+        // Without a tracer none is built just to append
+        let options = ReadOptions::default();
+        model.append_transfer_file("more.csv", &options).unwrap();
+        assert!(model.tracer.is_none());
+        assert_eq!(model.transfers().unwrap().height(), 2);
+        assert_eq!(model.derived_columns(), factors::ALL.map(String::from).to_vec());
+
+        // Factors read from the appended file make those columns file-provided
+        std::fs::write(
+            dir.join("factors.csv"),
+            "source_segment_id,dest_segment_id,share_count_forward,share_biomass_forward,\
+             share_count_backward,share_biomass_backward\nX,Y,1,1,1,1\n",
+        )
+        .unwrap();
+        model.append_transfer_file("factors.csv", &options).unwrap();
+        assert!(model.derived_columns().is_empty());
+
+        std::fs::write(dir.join("next.csv"), format!("{STOCK_HEADER}D,E,5,5\n")).unwrap();
+        model.tracer().unwrap();
+        model.append_transfer_file("next.csv", &options).unwrap();
+        assert_eq!(model.tracer.as_ref().unwrap().path_count("C", "E"), 1);
+
+        // A cycle is rejected whether or not the tracer is built
+        std::fs::write(dir.join("cycle.csv"), format!("{STOCK_HEADER}E,C,5,5\n")).unwrap();
+        assert!(model.append_transfer_file("cycle.csv", &options).is_err());
+        model.tracer = None;
+        assert!(model.append_transfer_file("cycle.csv", &options).is_err());
+        assert_eq!(model.transfers().unwrap().height(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn append_transfers_recalculates_shared_segment_factors() {
+        let dir = temp_dir("append_shared");
+        std::fs::write(dir.join("more.csv"), format!("{STOCK_HEADER}A,C,30,30\n")).unwrap();
+        let mut model = SdtModel::builder()
+            .base_path(&dir)
+            .transfers(transfers(&[("A", "B", 10.0)]))
+            .build()
+            .unwrap();
+        model.tracer().unwrap();
+
+        let appended = model
+            .append_transfer_file("more.csv", &ReadOptions::default())
+            .unwrap();
+        assert_eq!(f64_values(&appended, factors::SHARE_COUNT_FORWARD), vec![Some(0.75)]);
+        let all = model.transfers().unwrap();
+        assert_eq!(
+            f64_values(all, factors::SHARE_COUNT_FORWARD),
+            vec![Some(0.25), Some(0.75)]
+        );
+        assert!(model.tracer.is_none());
+        assert_eq!(model.tracer().unwrap().edge_factors("A", "B").unwrap()[0], 0.25);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn append_transfers_rejects_shared_segments_with_file_factors() {
+        let dir = temp_dir("append_file_factors");
+        std::fs::write(dir.join("more.csv"), format!("{STOCK_HEADER}A,C,30,30\n")).unwrap();
+        let factor_only = DataFrame::new(vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), ["A"]),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), ["B"]),
+            Column::new(factors::SHARE_COUNT_FORWARD.into(), [1.0]),
+            Column::new(factors::SHARE_BIOMASS_FORWARD.into(), [1.0]),
+            Column::new(factors::SHARE_COUNT_BACKWARD.into(), [1.0]),
+            Column::new(factors::SHARE_BIOMASS_BACKWARD.into(), [1.0]),
+        ])
+        .unwrap();
+        let mut model = SdtModel::builder()
+            .base_path(&dir)
+            .transfers(factor_only)
+            .build()
+            .unwrap();
+        match model.append_transfer_file("more.csv", &ReadOptions::default()) {
+            Err(SdtError::InvalidRows { rows, .. }) => assert_eq!(rows.height(), 1),
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+        assert_eq!(model.transfers().unwrap().height(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_segments_keeps_arrow_datetimes_without_preserve_dtypes() {
+        let dir = temp_dir("arrow_segments");
+        let mut df = segments(&[("S1", "C1", 0, Some(2)), ("S2", "C1", 2, None)]);
+        // Sub-second times render with fractional seconds as strings
+        let start = Column::new(segment::START_TIME.into(), [1500, 2 * HOUR_MS + 1500])
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))
+            .unwrap();
+        df.with_column(start).unwrap();
+        df.with_column(Column::new("pen_count".into(), [3i64, 4])).unwrap();
+        let mut file = std::fs::File::create(dir.join("segments.arrow")).unwrap();
+        IpcWriter::new(&mut file).finish(&mut df).unwrap();
+
+        let mut model = SdtModel::builder().base_path(&dir).build().unwrap();
+        let PyDataFrame(loaded) = model
+            .load_segments(Some("segments.arrow"), false, None, false, false, None, false)
+            .unwrap();
+        assert!(matches!(
+            loaded.column(segment::START_TIME).unwrap().dtype(),
+            DataType::Datetime(TimeUnit::Milliseconds, None)
+        ));
+        assert_eq!(loaded.column("pen_count").unwrap().dtype(), &DataType::String);
+        let start_us: Vec<Option<i64>> = loaded
+            .clone()
+            .lazy()
+            .select([SdtModel::column_us(segment::START_TIME)])
+            .collect()
+            .unwrap()
+            .get_columns()[0]
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(start_us, vec![Some(1_500_000), Some(2 * HOUR_US + 1_500_000)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segments_with_gaps_reports_microseconds() {
+        let model = SdtModel::builder()
+            .segments(segments(&[
+                ("S1", "C1", 0, Some(2)),
+                ("S2", "C1", 3, Some(4)),
+                ("S3", "C1", 4, None),
+            ]))
+            .build()
+            .unwrap();
+        let PyDataFrame(df) = model.segments_with_gaps(0).unwrap();
+        assert_eq!(i64_values(&df, "gap_us"), vec![Some(HOUR_US)]);
+    }
+
+    #[test]
+    fn trace_cache_keeps_requested_origin_order() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0), ("X", "Y", 5.0)]))
+            .build()
+            .unwrap();
+        let ids = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let origins = |df: &DataFrame| {
+            let mut seen: Vec<String> = Vec::new();
+            for id in str_values(df, traceability::ORIGIN_SEGMENT_ID)
+                .into_iter()
+                .flatten()
+            {
+                if seen.last() != Some(&id) {
+                    seen.push(id);
+                }
+            }
+            seen
+        };
+
+        let first = model
+            .trace_ids_cached(&ids(&["A", "X"]), &[], None, false, |_, _| {})
+            .unwrap();
+        assert_eq!(origins(&first), ids(&["A", "X"]));
+        let reversed = model
+            .trace_ids_cached(&ids(&["X", "A"]), &[], None, false, |_, _| {})
+            .unwrap();
+        assert_eq!(origins(&reversed), ids(&["X", "A"]));
+        let again = model
+            .trace_ids_cached(&ids(&["A", "X"]), &[], None, false, |_, _| {})
+            .unwrap();
+        assert!(again.equals(&first));
+        assert_eq!(model.trace_cache.entries.len(), 2);
+    }
+
+    #[test]
+    fn trace_and_aggregate_matches_three_step_pipeline() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[
+                ("A", "B", 10.0),
+                ("A", "C", 30.0),
+                ("D", "C", 10.0),
+            ]))
+            .segments(segments(&[
+                ("A", "C1", 0, Some(1)),
+                ("B", "C2", 1, None),
+                ("C", "C3", 1, None),
+                ("D", "C1", 1, Some(2)),
+            ]))
+            .build()
+            .unwrap();
+        model.trace_defaults = TraceOptions {
+            origin_attrs: Some(vec![segment::CONTAINER_ID.to_string()]),
+            distance_metric: Some(DistanceMetric::Hops),
+            ..TraceOptions::default()
+        };
+        let origins =
+            DataFrame::new(vec![Column::new(segment::SEGMENT_ID.into(), ["A", "D"])]).unwrap();
+        let data = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["A", "B", "C", "D"]),
+            Column::new("mortality".into(), [1.0, 2.0, 3.0, 4.0]),
+        ])
+        .unwrap();
+        let aggregations = vec![
+            Aggregation::new(AggKind::WeightedSum {
+                columns: vec!["mortality".into()],
+                aggregate_by: AggregateBy::Count,
+                include_calculation: false,
+                normalize: false,
+                exclude_identity: false,
+            }),
+            Aggregation::new(AggKind::Max {
+                column: "trace_distance".into(),
+                alias: None,
+            }),
+        ];
+        let group_by = vec![
+            "origin_container_id".to_string(),
+            traceability::TRACE_DIRECTION.to_string(),
+        ];
+
+        let ids = SdtModel::origin_ids(&origins).unwrap();
+        let options = model.trace_defaults.clone();
+        let traced = model.trace_with_options(&ids, &options, |_, _| {}).unwrap();
+        let PyDataFrame(joined) =
+            SdtModel::add_data_to_trace(PyDataFrame(data.clone()), PyDataFrame(traced)).unwrap();
+        let expected = SdtModel::aggregate_frame(
+            joined,
+            &aggregations,
+            Some(group_by.clone()),
+            true,
+            true,
+            true,
+            |_, _| {},
+        )
+        .unwrap();
+        // Groups come back in no particular order
+        let sorted = |df: DataFrame| {
+            df.sort(["origin_container_id", traceability::TRACE_DIRECTION], Default::default())
+                .unwrap()
+        };
+
+        let PyDataFrame(fused) = model
+            .trace_and_aggregate(
+                PyDataFrame(origins),
+                PyDataFrame(data),
+                aggregations,
+                Some(group_by),
+                true,
+                true,
+                true,
+            )
+            .unwrap();
+        assert!(fused.height() > 0);
+        assert!(sorted(fused.clone()).equals_missing(&sorted(expected)));
+        assert_eq!(
+            str_values(&fused, "origin_container_id")
+                .into_iter()
+                .flatten()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["C1".to_string()])
+        );
+    }
+
+    #[test]
+    fn trace_rejects_colliding_origin_attrs() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0)]))
+            .segments(segments(&[("A", "C1", 0, Some(1)), ("B", "C2", 1, None)]))
+            .build()
+            .unwrap();
+        let ids = vec!["A".to_string()];
+        let attrs = |names: &[&str]| TraceOptions {
+            origin_attrs: Some(names.iter().map(|s| s.to_string()).collect()),
+            ..TraceOptions::default()
+        };
+
+        for names in [&["segment_id"][..], &["container_id", "container_id"]] {
+            match model.trace_with_options(&ids, &attrs(names), |_, _| {}) {
+                Err(SdtError::Validation(message)) => {
+                    assert!(message.contains("origin_attrs"), "{message}")
+                }
+                other => panic!("expected a validation error, got {other:?}"),
+            }
+        }
+        assert_eq!(model.trace_cache.entries.len(), 0);
+
+        let traced = model
+            .trace_with_options(&ids, &attrs(&["container_id"]), |_, _| {})
+            .unwrap();
+        assert!(traced.column("origin_container_id").is_ok());
+        assert!(!traced
+            .get_column_names_str()
+            .iter()
+            .any(|c| c.ends_with("_right")));
+    }
+
+    #[test]
+    fn configure_trace_stores_defaults() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0), ("B", "C", 10.0)]))
+            .build()
+            .unwrap();
+        model
+            .configure_trace(None, None, Some(vec!["B".to_string()]), true, Some(0.5), true)
+            .unwrap();
+        let options = model.trace_defaults.clone();
+        let traced = model
+            .trace_with_options(&["A".to_string()], &options, |_, _| {})
+            .unwrap();
+        assert_eq!(
+            str_values(&traced, traceability::TRACED_SEGMENT_ID),
+            vec![Some("A".into()), Some("B".into())]
+        );
+        assert!(traced.column(traceability::N_PATHS).is_ok());
+
+        model
+            .configure_trace(None, None, None, false, None, false)
+            .unwrap();
+        assert!(model.trace_defaults.stop_ids.is_empty());
+        assert!(!model.trace_defaults.drop_zero_factor);
+        assert_eq!(model.trace_defaults.min_factor, None);
+        assert!(!model.trace_defaults.detail);
+    }
+
+    #[test]
+    fn trace_output_joins_onto_segment_data() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0), ("B", "C", 10.0)]))
+            .build()
+            .unwrap();
+        let traced = model.trace(&["B".to_string()]).unwrap();
+        let names: Vec<&str> = traced.get_column_names_str();
+        assert_eq!(&names[..3], &traceability::ALL[..]);
+        assert_eq!(&names[3..], &factors::ALL[..]);
+
+        let data = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["A", "B", "C"]),
+            Column::new("site".into(), ["north", "middle", "south"]),
+        ])
+        .unwrap();
+        let PyDataFrame(joined) =
+            SdtModel::add_data_to_trace(PyDataFrame(data), PyDataFrame(traced.clone())).unwrap();
+        assert_eq!(joined.height(), traced.height());
+        let sites: HashMap<String, String> = str_values(&joined, traceability::TRACED_SEGMENT_ID)
+            .into_iter()
+            .zip(str_values(&joined, "site"))
+            .map(|(id, site)| (id.unwrap(), site.unwrap()))
+            .collect();
+        assert_eq!(
+            sites,
+            HashMap::from([
+                ("A".to_string(), "north".to_string()),
+                ("B".to_string(), "middle".to_string()),
+                ("C".to_string(), "south".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_origin_frame_keeps_trace_schema() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0)]))
+            .build()
+            .unwrap();
+        let populated = model.trace(&["A".to_string()]).unwrap();
+        assert_eq!(**populated.schema(), DagTracer::trace_schema());
+
+        for dtype in [DataType::Null, DataType::String] {
+            let origins =
+                DataFrame::new(vec![Column::new_empty(segment::SEGMENT_ID.into(), &dtype)])
+                    .unwrap();
+            let ids = SdtModel::origin_ids(&origins).unwrap();
+            let options = model.trace_defaults.clone();
+            let traced = model.trace_with_options(&ids, &options, |_, _| {}).unwrap();
+            assert_eq!(traced.height(), 0);
+            assert_eq!(traced.schema(), populated.schema());
+        }
+    }
+
+    #[test]
+    fn direction_groups_weight_each_direction_separately() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[
+                ("A", "B", 10.0),
+                ("X", "B", 30.0),
+                ("B", "C", 40.0),
+            ]))
+            .build()
+            .unwrap();
+        let traced = model.trace(&["B".to_string()]).unwrap();
+        let data = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["A", "B", "C", "X"]),
+            Column::new("mortality".into(), [1.0, 2.0, 3.0, 4.0]),
+        ])
+        .unwrap();
+        let joined = SdtModel::join_segment_data(traced, data).unwrap();
+        let aggregations = vec![Aggregation::new(AggKind::WeightedSum {
+            columns: vec!["mortality".into()],
+            aggregate_by: AggregateBy::Count,
+            include_calculation: false,
+            normalize: false,
+            exclude_identity: false,
+        })];
+        let by_direction = vec![
+            traceability::ORIGIN_SEGMENT_ID.to_string(),
+            traceability::TRACE_DIRECTION.to_string(),
+        ];
+        let aggregate = |df: DataFrame, group_by: &[String], identity_in_each_direction: bool| {
+            let result = SdtModel::aggregate_frame(
+                df,
+                &aggregations,
+                Some(group_by.to_vec()),
+                identity_in_each_direction,
+                false,
+                true,
+                |_, _| {},
+            )
+            .unwrap();
+            let dirs = str_values(&result, traceability::TRACE_DIRECTION);
+            let sums = f64_values(&result, "mortality");
+            dirs.into_iter()
+                .map(|d| d.unwrap())
+                .zip(sums.into_iter().map(|s| s.unwrap()))
+                .collect::<HashMap<String, f64>>()
+        };
+        // A direction's group sums exactly what that direction's rows sum alone
+        let alone = |dir: &str| {
+            let rows = joined
+                .clone()
+                .lazy()
+                .filter(col(traceability::TRACE_DIRECTION).eq(lit(dir)))
+                .collect()
+                .unwrap();
+            aggregate(rows, &by_direction, false)[dir]
+        };
+
+        let split = aggregate(joined.clone(), &by_direction, false);
+        assert_eq!(split.len(), 3);
+        for dir in [direction::FORWARD, direction::BACKWARD, direction::IDENTITY] {
+            assert!((split[dir] - alone(dir)).abs() < 1e-9, "{dir}: {split:?}");
+        }
+        assert!((split[direction::IDENTITY] - 2.0).abs() < 1e-9);
+        // Weighted sums scale forward rows by the backward factor and vice versa
+        for (dir, factor) in [
+            (direction::FORWARD, factors::SHARE_COUNT_BACKWARD),
+            (direction::BACKWARD, factors::SHARE_COUNT_FORWARD),
+        ] {
+            let expected = joined
+                .clone()
+                .lazy()
+                .filter(col(traceability::TRACE_DIRECTION).eq(lit(dir)))
+                .select([(col("mortality") * col(factor)).sum()])
+                .collect()
+                .unwrap();
+            let expected = expected.get_columns()[0].f64().unwrap().get(0).unwrap();
+            assert!((split[dir] - expected).abs() < 1e-9, "{dir}: {split:?}");
+        }
+
+        let with_identity = aggregate(joined, &by_direction, true);
+        assert_eq!(with_identity.len(), 2);
+        for dir in [direction::FORWARD, direction::BACKWARD] {
+            let expected = split[dir] + split[direction::IDENTITY];
+            assert!(
+                (with_identity[dir] - expected).abs() < 1e-9,
+                "{dir}: {with_identity:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn builder_prepares_frames_for_tracing() {
+        let segments = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["A", "B", "C"]),
+            Column::new(segment::CONTAINER_ID.into(), ["C1", "C2", "C3"]),
+            Column::new(
+                segment::START_TIME.into(),
+                [
+                    "2024-01-01 00:00:00",
+                    "2024-01-02 00:00:00",
+                    "2024-01-02 00:00:00",
+                ],
+            ),
+            Column::new(
+                segment::END_TIME.into(),
+                [Some("2024-01-02 00:00:00"), None, None],
+            ),
+        ])
+        .unwrap();
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[("A", "B", 10.0), ("A", "C", 30.0)]))
+            .segments(segments.clone())
+            .build()
+            .unwrap();
+        let parsed = model.segments().unwrap();
+        assert!(matches!(
+            parsed.column(segment::START_TIME).unwrap().dtype(),
+            DataType::Datetime(_, _)
+        ));
+        assert_eq!(parsed.column(segment::END_TIME).unwrap().null_count(), 2);
+        assert_eq!(
+            model.derived_columns(),
+            factors::ALL.map(String::from).to_vec()
+        );
+
+        let traced = model.trace(&["A".to_string()]).unwrap();
+        let direct = DagTracer::from_transfers(model.transfers().unwrap())
+            .unwrap()
+            .trace(&["A".to_string()])
+            .unwrap();
+        assert!(traced.equals(&direct));
+        let forward: HashMap<String, f64> = str_values(&traced, traceability::TRACED_SEGMENT_ID)
+            .into_iter()
+            .zip(f64_values(&traced, factors::SHARE_COUNT_FORWARD))
+            .map(|(id, f)| (id.unwrap(), f.unwrap()))
+            .collect();
+        assert_eq!(forward["B"], 0.25);
+        assert_eq!(forward["C"], 0.75);
+
+        let missing = segments.drop(segment::END_TIME).unwrap();
+        assert!(matches!(
+            SdtModel::builder().segments(missing).build(),
+            Err(SdtError::MissingColumns { .. })
+        ));
+    }
+
+    #[test]
+    fn validation_failures_carry_offending_rows() {
+        let model = SdtModel::builder()
+            .segments(segments(&[
+                ("S1", "C1", 0, Some(2)),
+                ("S2", "C1", 2, Some(4)),
+            ]))
+            .build()
+            .unwrap();
+        let readings = DataFrame::new(vec![
+            Column::new(container::CONTAINER_ID.into(), ["C1", "C1"]),
+            datetime_ms(timeseries::DATE_TIME, &[Some(1), Some(2)]),
+        ])
+        .unwrap();
+        let matched = model
+            .container_data_segments(readings.clone(), true, false, timeseries::DATE_TIME, None)
+            .unwrap();
+        assert_eq!(matched.height(), 2);
+        match model.container_data_segments(
+            readings,
+            true,
+            false,
+            timeseries::DATE_TIME,
+            Some(true),
+        ) {
+            Err(SdtError::InvalidRows {
+                message,
+                column,
+                rows,
+            }) => {
+                assert!(message.contains("allow_multiple=false"), "{message}");
+                assert_eq!(column, None);
+                assert_eq!(rows.height(), 1);
+                let count = rows
+                    .column("match_count")
+                    .unwrap()
+                    .cast(&DataType::Int64)
+                    .unwrap();
+                assert_eq!(count.i64().unwrap().get(0), Some(2));
+            }
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+
+        let mut columns = vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), ["A", "A"]),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), ["B", "C"]),
+            Column::new(factors::SHARE_COUNT_FORWARD.into(), [0.5, f64::NAN]),
+        ];
+        for name in &factors::ALL[1..] {
+            columns.push(Column::new((*name).into(), [0.5, 0.5]));
+        }
+        match SdtModel::clamp_negative_factors(DataFrame::new(columns).unwrap()) {
+            Err(SdtError::InvalidRows { column, rows, .. }) => {
+                assert_eq!(column.as_deref(), Some(factors::SHARE_COUNT_FORWARD));
+                assert_eq!(
+                    str_values(&rows, transfer::DEST_SEGMENT_ID),
+                    vec![Some("C".into())]
+                );
+            }
+            other => panic!("expected InvalidRows, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn trace_iter_applies_configured_defaults() {
+        let mut model = SdtModel::builder()
+            .transfers(transfers(&[
+                ("A", "B", 10.0),
+                ("B", "C", 10.0),
+                ("X", "B", 30.0),
+            ]))
+            .build()
+            .unwrap();
+        model
+            .configure_trace(
+                None,
+                Some("hops"),
+                Some(vec!["B".to_string()]),
+                false,
+                None,
+                true,
+            )
+            .unwrap();
+        let ids = vec!["A".to_string(), "C".to_string()];
+        let options = model.trace_defaults.clone();
+        let expected = model.trace_with_options(&ids, &options, |_, _| {}).unwrap();
+        assert!(!str_values(&expected, traceability::TRACED_SEGMENT_ID).contains(&Some("X".into())));
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let model = Py::new(py, model).unwrap();
+            let mut iter = SdtModel::trace_iter(model.bind(py).clone(), ids).unwrap();
+            let mut frames = Vec::new();
+            while let Some(PyDataFrame(df)) = iter.__next__(py).unwrap() {
+                frames.push(df);
+            }
+            let mut iterated = frames[0].clone();
+            iterated.vstack_mut(&frames[1]).unwrap();
+            assert!(iterated.equals_missing(&expected));
+        });
+    }
+
+    #[test]
+    fn segments_active_histogram_matches_interval_rule() {
+        let mut model = SdtModel::builder()
+            .segments(segments(&[
+                ("S1", "C1", 0, Some(2)),
+                ("S2", "C1", 2, Some(2)),
+                ("S3", "C2", 1, None),
+                ("S4", "C3", 3, Some(1)),
+                ("S5", "C3", 1, Some(4)),
+            ]))
+            .build()
+            .unwrap();
+        for closed in [
+            IntervalClosed::Left,
+            IntervalClosed::Right,
+            IntervalClosed::Both,
+            IntervalClosed::Neither,
+        ] {
+            model.interval_closed = closed;
+            let PyDataFrame(df) = model.segments_active_histogram("1h", None, None).unwrap();
+            let expected: Vec<Option<i64>> = (0..5)
+                .map(|h| {
+                    let active = model
+                        .segments()
+                        .unwrap()
+                        .clone()
+                        .lazy()
+                        .select([
+                            SdtModel::column_us(segment::START_TIME).alias(segment::START_TIME),
+                            SdtModel::column_us(segment::END_TIME).alias(segment::END_TIME),
+                        ])
+                        .filter(closed.contains(lit(h * HOUR_US), None))
+                        .collect()
+                        .unwrap();
+                    Some(active.height() as i64)
+                })
+                .collect();
+            assert_eq!(i64_values(&df, "active_count"), expected, "{closed:?}");
+        }
+
+        assert!(model.segments_active_histogram("1ms", None, None).is_err());
+    }
+
+    #[test]
+    fn factor_only_transfers_without_stock_columns() {
+        let dir = temp_dir("factor_only");
+        std::fs::write(
+            dir.join("factors.csv"),
+            "source_segment_id,dest_segment_id,share_count_forward,share_biomass_forward,\
+             share_count_backward,share_biomass_backward\nS1,S2,1,1,0.5,0.5\nS3,S2,1,1,0.5,0.5\n",
+        )
+        .unwrap();
+        let mut model = SdtModel::builder()
+            .base_path(&dir)
+            .segments(segments(&[
+                ("S1", "C1", 0, Some(1)),
+                ("S3", "C2", 0, Some(1)),
+                ("S2", "C3", 1, None),
+            ]))
+            .build()
+            .unwrap();
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let PyDataFrame(df) = model
+                .load_transfers(
+                    py,
+                    Some("factors.csv"),
+                    false,
+                    false,
+                    None,
+                    false,
+                    None,
+                    None,
+                    false,
+                    false,
+                    false,
+                )
+                .unwrap();
+            assert!(!df.schema().contains(transfer::TRANSFER_COUNT));
+
+            // Only the sum checks run, and the output keeps the loaded columns
+            let PyDataFrame(issues) = model.check_factor_reciprocity(1e-6).unwrap();
+            assert_eq!(issues.height(), 0);
+            assert_eq!(issues.width(), df.width() + 1);
+
+            let window = (0, 10 * HOUR_US);
+            for result in [
+                model.container_flow("C3", transfer::DEST_SEGMENT_ID, window),
+                model.container_flow("C1", transfer::SOURCE_SEGMENT_ID, window),
+            ] {
+                let err = result.unwrap_err().to_string();
+                assert!(err.contains("create_missing_stock=False"), "{err}");
+            }
+            let err = model.biomass_flow_matrix("biomass").unwrap_err();
+            assert!(err.to_string().contains("create_missing_stock=False"));
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn categorical_ids_keep_results_unchanged() {
+        let dir = temp_dir("categorical_ids");
+        std::fs::write(dir.join("more.csv"), format!("{STOCK_HEADER}C,D,5,5\n")).unwrap();
+        let build = |categorical: bool| {
+            SdtModel::builder()
+                .base_path(&dir)
+                .categorical_ids(categorical)
+                .transfers(transfers(&[("A", "B", 10.0), ("A", "C", 30.0)]))
+                .segments(segments(&[
+                    ("A", "C1", 0, Some(1)),
+                    ("B", "C2", 1, None),
+                    ("C", "C3", 1, Some(2)),
+                    ("D", "C2", 2, None),
+                ]))
+                .build()
+                .unwrap()
+        };
+        let (mut plain, mut categorical) = (build(false), build(true));
+        let id_dtype = |model: &SdtModel| {
+            let transfers = model.transfers().unwrap();
+            transfers
+                .column(transfer::SOURCE_SEGMENT_ID)
+                .unwrap()
+                .dtype()
+                .clone()
+        };
+        assert!(id_dtype(&categorical).is_categorical());
+        assert_eq!(id_dtype(&plain), DataType::String);
+
+        let options = ReadOptions::default();
+        for model in [&mut plain, &mut categorical] {
+            model.append_transfer_file("more.csv", &options).unwrap();
+        }
+        assert!(id_dtype(&categorical).is_categorical());
+        assert!(categorical
+            .loaded_transfers()
+            .unwrap()
+            .equals(&plain.loaded_transfers().unwrap()));
+
+        let ids = ["A".to_string(), "D".to_string()];
+        assert!(categorical
+            .trace(&ids)
+            .unwrap()
+            .equals(&plain.trace(&ids).unwrap()));
+        let PyDataFrame(matrix) = categorical.biomass_flow_matrix("biomass").unwrap();
+        assert!(matrix.equals(&plain.biomass_flow_matrix("biomass").unwrap().0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn biomass_flow_matrix_sums_null_stock_as_zero_and_rejects_key_clash() {
+        let build = |dest_container: &str| {
+            let mut df = transfers(&[("A", "B", 10.0), ("A", "C", 30.0), ("D", "B", 5.0)]);
+            let biomass = Column::new(
+                transfer::TRANSFER_BIOMASS_KG.into(),
+                [Some(10.0), None, Some(5.0)],
+            );
+            df.with_column(biomass).unwrap();
+            for factor in factors::ALL {
+                df.with_column(Column::new(factor.into(), [0.5, 0.5, 1.0])).unwrap();
+            }
+            SdtModel::builder()
+                .transfers(df)
+                .segments(segments(&[
+                    ("A", "C1", 0, Some(1)),
+                    ("D", "C1", 0, Some(1)),
+                    ("B", dest_container, 1, None),
+                    ("C", "C3", 1, None),
+                ]))
+                .build()
+                .unwrap()
+        };
+        let PyDataFrame(matrix) = build("C2").biomass_flow_matrix("biomass").unwrap();
+        assert_eq!(f64_values(&matrix, "C2"), vec![Some(15.0)]);
+        assert_eq!(f64_values(&matrix, "C3"), vec![Some(0.0)]);
+
+        pyo3::prepare_freethreaded_python();
+        let err = build("source_container_id")
+            .biomass_flow_matrix("biomass")
+            .unwrap_err();
+        assert!(err.to_string().contains("clashes"), "{err}");
     }
 }
@@ -1,5 +1,5 @@
-/// Column-name constants for aqua-tracekit schema.
-/// Single source of truth - exported to Python via PyO3.
+//! Column-name constants for aqua-tracekit schema.
+//! Single source of truth - exported to Python via PyO3.
 
 // ── Transfer columns ────────────────────────────────────────────────────────
 pub mod transfer {
@@ -7,6 +7,8 @@ pub mod transfer {
     pub const DEST_SEGMENT_ID: &str = "dest_segment_id";
     pub const TRANSFER_COUNT: &str = "transfer_count";
     pub const TRANSFER_BIOMASS_KG: &str = "transfer_biomass_kg";
+    /// Optional column added by `transfers_with_time`
+    pub const TRANSFER_TIME: &str = "transfer_time";
 }
 
 // ── Trace factor columns ────────────────────────────────────────────────────
@@ -43,6 +45,8 @@ pub mod segment {
     pub const CONTAINER_ID: &str = "container_id";
     pub const START_TIME: &str = "start_time";
     pub const END_TIME: &str = "end_time";
+    pub const LIFESPAN_US: &str = "lifespan_us";
+    pub const LIFESPAN_DAYS: &str = "lifespan_days";
 }
 
 // ── Container columns ───────────────────────────────────────────────────────
@@ -55,6 +59,20 @@ pub mod traceability {
     pub const ORIGIN_SEGMENT_ID: &str = "origin_segment_id";
     pub const TRACED_SEGMENT_ID: &str = "traced_segment_id";
     pub const TRACE_DIRECTION: &str = "direction";
+    /// Optional column added by `trace_segments(distance_metric=...)`
+    pub const TRACE_DISTANCE: &str = "trace_distance";
+    /// Optional columns added by `trace_segments(detail=True)`
+    pub const N_PATHS: &str = "n_paths";
+    pub const MIN_PATH_FACTOR: &str = "min_path_factor";
+    pub const MAX_PATH_FACTOR: &str = "max_path_factor";
+
+    /// Version of the trace output layout. Bumped whenever a column is renamed,
+    /// removed or reordered; adding columns at the end does not change it.
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// Leading columns of every trace result, in order. The four factor
+    /// columns (`factors::ALL`) follow.
+    pub const ALL: [&str; 3] = [ORIGIN_SEGMENT_ID, TRACED_SEGMENT_ID, TRACE_DIRECTION];
 }
 
 // ── Time series columns ─────────────────────────────────────────────────────
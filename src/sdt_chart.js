@@ -5,6 +5,7 @@
 // Modified to support:
 // - Multi-line x-axis labels (date on line 1, time on line 2)
 // - Interactive segment selection with backward/forward tracing
+// - Vertical orientation (time top to bottom, containers as columns)
 //
 // Usage (from Rust-injected inline script):
 //   SdtChart.create({ zoom: 1.0, tMin: ..., tMax: ..., ... });
@@ -23,12 +24,15 @@ var SdtChart = (function () {
     var timeScale = cfg.timeScale;
     var gapPx = cfg.gapPx;
     var transferTimes = cfg.transferTimes;
+    var gapDurations = cfg.gapDurations; // null unless show_gap_duration
     var marginLeft = cfg.marginLeft;
     var marginTop = cfg.marginTop;
     var marginRight = cfg.marginRight;
     var marginBottom = cfg.marginBottom;
     var laneHeight = cfg.laneHeight;
     var numLanes = cfg.numLanes;
+    var stripeLanes = cfg.stripeLanes;
+    var transferStubs = cfg.transferStubs; // draw transfers with one end off-chart
     var rectPadding = cfg.rectPadding;
     var segments = cfg.segments;
     var transfers_data = cfg.transfers;
@@ -48,6 +52,8 @@ var SdtChart = (function () {
     // ── Coordinate mapping ────────────────────────────────────────────
 
     // gapMode: true/'after' (rect starts), false/'before' (rect ends), 'middle' (labels)
+    // Rectangle placement is mirrored by compute_trace_layout in visualization.rs;
+    // keep the two in sync.
     function timeToX(tUs, gapMode) {
       var continuous = currentZoom * (tUs - tMin) / timeScale;
       if (gapMode === 'middle') {
@@ -87,6 +93,14 @@ var SdtChart = (function () {
 
     var SVG_NS = 'http://www.w3.org/2000/svg';
 
+    // Compact duration label for gap annotations, e.g. "3d", "5h", "20m"
+    function formatDuration(us) {
+      var minutes = us / 60e6;
+      if (minutes >= 1440) return Math.round(minutes / 1440) + 'd';
+      if (minutes >= 60) return Math.round(minutes / 60) + 'h';
+      return Math.round(minutes) + 'm';
+    }
+
     function svgEl(tag, attrs) {
       var el = document.createElementNS(SVG_NS, tag);
       for (var k in attrs) {
@@ -161,12 +175,56 @@ var SdtChart = (function () {
       rebuild();
     }
 
+    // ── Orientation ───────────────────────────────────────────────────
+    // Drawing works in (time, lane) coordinates; place() maps them to SVG x/y.
+    // Horizontal: time runs along x, lanes stack along y. Vertical: transposed.
+    // Lane labels sit in the margin before the time axis, time labels in the
+    // margin before the lanes.
+
+    var vertical = cfg.orientation === 'vertical';
+    var timeOrigin = vertical ? marginTop : marginLeft;
+    var laneOrigin = vertical ? marginLeft : marginTop;
+    var timeEndMargin = vertical ? marginBottom : marginRight;
+    var laneEndMargin = vertical ? marginRight : marginBottom;
+
+    var STUB_PX = 12; // how far stub arrows reach past their lane
+
+    function place(t, l) {
+      return vertical ? { x: l, y: t } : { x: t, y: l };
+    }
+
+    function box(t, l, tLen, lLen) {
+      var p = place(t, l);
+      var size = place(tLen, lLen);
+      return { x: p.x, y: p.y, width: size.x, height: size.y };
+    }
+
+    function line(t1, l1, t2, l2, attrs) {
+      var a = place(t1, l1);
+      var b = place(t2, l2);
+      attrs.x1 = a.x; attrs.y1 = a.y; attrs.x2 = b.x; attrs.y2 = b.y;
+      return svgEl('line', attrs);
+    }
+
+    // Text at (t, l); in vertical mode rotated by `angle` degrees about its anchor
+    function text(t, l, cls, angle, extra) {
+      var p = place(t, l);
+      var attrs = { x: p.x, y: p.y, 'class': cls };
+      if (vertical && angle) attrs.transform = 'rotate(' + angle + ' ' + p.x + ' ' + p.y + ')';
+      for (var k in extra) {
+        if (extra.hasOwnProperty(k)) attrs[k] = extra[k];
+      }
+      return svgEl('text', attrs);
+    }
+
     // ── Rebuild ───────────────────────────────────────────────────────
 
     function rebuild() {
-      var contentWidth = timeToX(tMax, true) + marginRight;
-      var totalWidth = marginLeft + contentWidth;
-      var totalHeight = marginTop + numLanes * laneHeight + marginBottom;
+      var timeExtent = timeOrigin + timeToX(tMax, true) + timeEndMargin;
+      var laneExtent = laneOrigin + numLanes * laneHeight + laneEndMargin;
+      var size = place(timeExtent, laneExtent);
+      var totalWidth = size.x;
+      var totalHeight = size.y;
 
       var svg = document.getElementById('sdt-svg');
       svg.setAttribute('width', totalWidth);
@@ -183,54 +241,68 @@ var SdtChart = (function () {
       background.addEventListener('click', clearSelection);
       svg.appendChild(background);
 
+      // Vertical lane labels read upwards from just above the lanes
+      var laneLabelAnchor = vertical ? { 'text-anchor': 'start' } : {};
+
       // ── Lane backgrounds + labels ──
       for (var i = 0; i < numLanes; i++) {
-        var y = marginTop + i * laneHeight;
-        svg.appendChild(svgEl('rect', {
-          x: 0, y: y, width: totalWidth, height: laneHeight,
-          fill: i % 2 === 0 ? '#f8f9fa' : '#ffffff',
-          'pointer-events': 'none'
-        }));
-        var txt = svgEl('text', {
-          x: marginLeft - 8, y: y + laneHeight / 2 + 4,
-          'class': 'lane-label'
-        });
+        var l = laneOrigin + i * laneHeight;
+        var bg = box(0, l, timeExtent, laneHeight);
+        bg.fill = stripeLanes && i % 2 === 0 ? '#f8f9fa' : '#ffffff';
+        bg['pointer-events'] = 'none';
+        svg.appendChild(svgEl('rect', bg));
+        // With a sublabel the main label moves up to make room beneath it
+        var sublabel = lanes_data[i].sublabel;
+        var txt = text(timeOrigin - 8, l + laneHeight / 2 + (sublabel != null ? 0 : 4),
+          'lane-label', -90, laneLabelAnchor);
         txt.textContent = lanes_data[i].label;
         svg.appendChild(txt);
+        if (sublabel != null) {
+          var sub = text(timeOrigin - 8, l + laneHeight / 2 + 10, 'lane-sublabel', -90, laneLabelAnchor);
+          sub.textContent = sublabel;
+          svg.appendChild(sub);
+        }
       }
 
       // ── Lane separators ──
       for (var i = 0; i <= numLanes; i++) {
-        var y = marginTop + i * laneHeight;
-        svg.appendChild(svgEl('line', {
-          x1: 0, y1: y, x2: totalWidth, y2: y,
+        var l = laneOrigin + i * laneHeight;
+        svg.appendChild(line(0, l, timeExtent, l, {
           stroke: '#dee2e6', 'stroke-width': 1,
           'pointer-events': 'none'
         }));
       }
 
       // ── Transfer time gap indicators ──
+      var laneEnd = laneExtent - laneEndMargin;
       for (var i = 0; i < transferTimes.length; i++) {
-        var x = marginLeft + timeToX(transferTimes[i], false) + gapPx / 2;
-        svg.appendChild(svgEl('line', {
-          x1: x, y1: marginTop, x2: x, y2: totalHeight - marginBottom,
+        var t = timeOrigin + timeToX(transferTimes[i], false) + gapPx / 2;
+        svg.appendChild(line(t, laneOrigin, t, laneEnd, {
           stroke: '#e0e0e0', 'stroke-width': 1, 'stroke-dasharray': '4,4',
           'pointer-events': 'none'
         }));
+        if (gapDurations) {
+          var gapTxt = text(t, laneEnd + (vertical ? 4 : 12), 'time-label', 0,
+            vertical ? { 'text-anchor': 'start' } : {});
+          gapTxt.textContent = formatDuration(gapDurations[i]);
+          svg.appendChild(gapTxt);
+        }
       }
 
       // ── Time axis labels (hierarchical, zoom-adaptive, multi-line) ──
       var axisResult = SdtTimeAxis.generateTicks(tMin, tMax, currentZoom, timeScale);
       for (var i = 0; i < axisResult.ticks.length; i++) {
-        var t = axisResult.ticks[i];
-        var x = marginLeft + timeToX(t, 'middle');
+        var tick = axisResult.ticks[i];
+        var t = timeOrigin + timeToX(tick, 'middle');
 
-        // Create text element with multi-line support
-        var txt = svgEl('text', {
-          x: x, y: marginTop - 18, 'class': 'time-label'
-        });
+        // Create text element with multi-line support; vertical labels sit left of
+        // the lanes, right-aligned and centered on the tick
+        var txt = vertical
+          ? text(t - 4, laneOrigin - 8, 'time-label', 0, { 'text-anchor': 'end' })
+          : text(t, laneOrigin - 18, 'time-label', 0, {});
+        var x = txt.getAttribute('x');
 
-        var formatted = axisResult.format(t);
+        var formatted = axisResult.format(tick);
 
         // Add line 1 (date) if present
         if (formatted.line1) {
@@ -248,8 +320,7 @@ var SdtChart = (function () {
 
         svg.appendChild(txt);
 
-        svg.appendChild(svgEl('line', {
-          x1: x, y1: marginTop - 4, x2: x, y2: marginTop,
+        svg.appendChild(line(t, laneOrigin - 4, t, laneOrigin, {
           stroke: '#adb5bd', 'stroke-width': 1,
           'pointer-events': 'none'
         }));
@@ -264,24 +335,24 @@ var SdtChart = (function () {
         var p = segments[i];
         var li = laneMap[p.container_id];
         if (li === undefined) continue;
-        var x = marginLeft + timeToX(p.start_us, true);
-        var x2 = marginLeft + timeToX(p.end_us, false);
-        var w = Math.max(x2 - x, 2);
-        var ry = marginTop + li * laneHeight + rectPadding;
+        var t1 = timeOrigin + timeToX(p.start_us, true);
+        var t2 = timeOrigin + timeToX(p.end_us, false);
+        var len = Math.max(t2 - t1, 2);
+        var l = laneOrigin + li * laneHeight + rectPadding;
         var h = laneHeight - 2 * rectPadding;
 
-        segmentPositions[p.segment_id] = { x: x, x2: x2, y: ry, h: h, lane: li };
+        segmentPositions[p.segment_id] = { t1: t1, t2: t2, l: l, h: h, lane: li };
 
         // Get colors based on selection state
         var colors = getsegmentColors(p.segment_id);
 
-        var rect = svgEl('rect', {
-          x: x, y: ry, width: w, height: h, rx: 3,
-          fill: colors.fill,
-          stroke: colors.stroke,
-          'stroke-width': p.segment_id === selectedSegmentId ? 2 : 1,
-          cursor: 'pointer'
-        });
+        var attrs = box(t1, l, len, h);
+        attrs.rx = 3;
+        attrs.fill = colors.fill;
+        attrs.stroke = colors.stroke;
+        attrs['stroke-width'] = p.segment_id === selectedSegmentId ? 2 : 1;
+        attrs.cursor = 'pointer';
+        var rect = svgEl('rect', attrs);
 
         var tip = p.segment_id + '\n' + formatTimestamp(p.start_us) + ' → ' + formatTimestamp(p.end_us);
         if (p.tooltip) tip += '\n' + p.tooltip;
@@ -309,10 +380,9 @@ var SdtChart = (function () {
 
         svg.appendChild(rect);
 
-        if (p.label && w > 30) {
-          var lbl = svgEl('text', {
-            x: x + 4, y: ry + h / 2 + 4, 'class': 'segment-label'
-          });
+        if (p.label && len > 30) {
+          // Vertical labels run down the column, so their baseline sits left of center
+          var lbl = text(t1 + 4, l + h / 2 + (vertical ? -4 : 4), 'segment-label', 90, {});
           lbl.textContent = p.label;
           svg.appendChild(lbl);
         }
@@ -323,18 +393,27 @@ var SdtChart = (function () {
         var tr = transfers_data[i];
         var src = segmentPositions[tr.source_segment_id];
         var dst = segmentPositions[tr.dest_segment_id];
-        if (!src || !dst) continue;
-
-        var tx1 = marginLeft + timeToX(tr.transfer_time_us, false);
-        var tx2 = marginLeft + timeToX(tr.transfer_time_us, true);
-        var srcY = marginTop + src.lane * laneHeight + laneHeight / 2;
-        var dstY = marginTop + dst.lane * laneHeight + laneHeight / 2;
-
-        var el = svgEl('line', {
-          x1: tx1, y1: srcY, x2: tx2, y2: dstY,
+        // Transfers with unknown segment ids are already left out (and counted) in Rust;
+        // this only skips segments without a container lane
+        if (!(src && dst) && !(transferStubs && (src || dst))) continue;
+
+        var tt1 = timeOrigin + timeToX(tr.transfer_time_us, false);
+        var tt2 = timeOrigin + timeToX(tr.transfer_time_us, true);
+        // A stub enters from just before the destination lane or leaves to just past
+        // the source lane
+        var srcL = src
+          ? laneOrigin + src.lane * laneHeight + laneHeight / 2
+          : laneOrigin + dst.lane * laneHeight - STUB_PX;
+        var dstL = dst
+          ? laneOrigin + dst.lane * laneHeight + laneHeight / 2
+          : laneOrigin + (src.lane + 1) * laneHeight + STUB_PX;
+
+        var attrs = {
           stroke: '#e74c3c', 'stroke-width': 1.5,
           'marker-end': 'url(#arrowhead)', 'class': 'transfer-arrow'
-        });
+        };
+        if (!(src && dst)) attrs['stroke-dasharray'] = '3,2';
+        var el = line(tt1, srcL, tt2, dstL, attrs);
         var tip = tr.source_segment_id + ' → ' + tr.dest_segment_id + '\n' + formatTimestamp(tr.transfer_time_us);
         if (tr.tooltip) tip += '\n' + tr.tooltip;
         var title = svgEl('title', {});
@@ -8,12 +8,14 @@
 ///
 /// All SVG rendering is done client-side by sdt_chart.js + time_axis.js.
 /// This module extracts data from DataFrames, serializes to JSON, and emits
-/// the HTML shell.
-use std::collections::{BTreeSet, HashMap};
+/// the HTML shell. `compute_trace_layout` repeats the rectangle placement in Rust
+/// for callers without a browser.
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Write as FmtWrite;
 
 use polars::datatypes::AnyValue;
 use polars::prelude::*;
+use pyo3::prelude::*;
 
 use crate::error::SdtError;
 use crate::schema::*;
@@ -21,24 +23,123 @@ use crate::schema::*;
 const TIME_AXIS_JS: &str = include_str!("time_axis.js");
 const CHART_JS: &str = include_str!("sdt_chart.js");
 
+/// Time span drawn when all segments start and end at the same instant (e.g. a single
+/// still-active segment), so the chart does not collapse to its margins: one day.
+const MIN_SPAN_US: i64 = 86_400_000_000;
+
+/// Space between a segment rectangle and the edges of its lane.
+const RECT_PADDING_PX: u32 = 4;
+
+/// Shortest drawn segment rectangle, so zero-length segments stay visible.
+const MIN_RECT_PX: f64 = 2.0;
+
 // ── Config ──────────────────────────────────────────────────────────────────
 
 /// Configuration for the trace visualization.
+///
+/// Exposed to Python so a config can be built once and reused with
+/// `SdtModel.visualize_trace_with`. Fields are readable and writable as attributes.
+#[pyclass(name = "VisualizationConfig")]
+#[derive(Clone)]
 pub struct VisualizationConfig {
     /// Column from containers df to use as y-axis label (default: container_id)
+    #[pyo3(get, set)]
     pub container_label_col: Option<String>,
-    /// Column from segments df to display on the rectangle
+    /// Column from segments df to display on the rectangle (default: segment_id)
+    #[pyo3(get, set)]
     pub segment_label_col: Option<String>,
     /// Columns from segments df to show in tooltip on hover
+    #[pyo3(get, set)]
     pub segment_tooltip_cols: Vec<String>,
     /// Columns from transfers df to show in tooltip on transfer arrow hover
-    pub transfer_tooltip_cols: Vec<String>,
+    /// (None: stock columns, or forward share factors for factor-only files)
+    #[pyo3(get, set)]
+    pub transfer_tooltip_cols: Option<Vec<String>>,
     /// Fixed pixel width inserted at each unique transfer time
+    #[pyo3(get, set)]
     pub gap_px: u32,
     /// Fixed pixel height per container lane
+    #[pyo3(get, set)]
     pub lane_height_px: u32,
     /// Initial zoom level (pixels per microsecond of real time)
+    #[pyo3(get, set)]
     pub initial_zoom: f64,
+    /// Label each transfer gap with the real time until the next event
+    #[pyo3(get, set)]
+    pub show_gap_duration: bool,
+    /// Transfer times within this many microseconds of a gap share that gap
+    /// (0: one gap per distinct time)
+    #[pyo3(get, set)]
+    pub gap_merge_tolerance_us: i64,
+    /// Alternate the background shading of container lanes
+    #[pyo3(get, set)]
+    pub stripe_lanes: bool,
+    /// Column from containers df shown in smaller text beneath the lane label
+    #[pyo3(get, set)]
+    pub lane_sublabel_col: Option<String>,
+    /// "horizontal" (time left to right, one row per container) or "vertical"
+    /// (time top to bottom, one column per container)
+    #[pyo3(get, set)]
+    pub orientation: String,
+    /// Text shown for null and NaN values in tooltips (None: leave the field out)
+    #[pyo3(get, set)]
+    pub missing_value_text: Option<String>,
+    /// Draw transfers with only one end on the chart as short stub arrows instead of
+    /// leaving them out (set by `container_timeline`)
+    pub transfer_stubs: bool,
+}
+
+#[pymethods]
+impl VisualizationConfig {
+    #[new]
+    #[pyo3(signature = (
+        container_label_col = None,
+        segment_label_col = None,
+        segment_tooltip_cols = None,
+        transfer_tooltip_cols = None,
+        gap_px = 32,
+        lane_height_px = 24,
+        initial_zoom = 1.0,
+        show_gap_duration = false,
+        gap_merge_tolerance_us = 0,
+        stripe_lanes = true,
+        lane_sublabel_col = None,
+        orientation = "horizontal".to_string(),
+        missing_value_text = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        container_label_col: Option<String>,
+        segment_label_col: Option<String>,
+        segment_tooltip_cols: Option<Vec<String>>,
+        transfer_tooltip_cols: Option<Vec<String>>,
+        gap_px: u32,
+        lane_height_px: u32,
+        initial_zoom: f64,
+        show_gap_duration: bool,
+        gap_merge_tolerance_us: i64,
+        stripe_lanes: bool,
+        lane_sublabel_col: Option<String>,
+        orientation: String,
+        missing_value_text: Option<String>,
+    ) -> Self {
+        Self {
+            container_label_col,
+            segment_label_col,
+            segment_tooltip_cols: segment_tooltip_cols.unwrap_or_default(),
+            transfer_tooltip_cols,
+            gap_px,
+            lane_height_px,
+            initial_zoom,
+            show_gap_duration,
+            gap_merge_tolerance_us: gap_merge_tolerance_us.max(0),
+            stripe_lanes,
+            lane_sublabel_col,
+            orientation,
+            missing_value_text,
+            transfer_stubs: false,
+        }
+    }
 }
 
 // ── Intermediate data structures ────────────────────────────────────────────
@@ -59,13 +160,82 @@ struct TransferArrow {
     tooltip_fields: Vec<(String, String)>,
 }
 
+/// Output of `generate_trace_html`.
+pub struct RenderedTrace {
+    pub html: String,
+    /// Data problems worth telling the user about, e.g. transfers that were skipped
+    pub warnings: Vec<String>,
+}
+
 struct ContainerLane {
     container_id: String,
     label: String,
+    sublabel: Option<String>,
+}
+
+/// Chart margins in pixels.
+struct Margins {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+}
+
+/// Extracted chart rows with times snapped to their gaps, plus the time-axis
+/// parameters. Shared by the HTML renderer and `compute_trace_layout` so both place
+/// segments the same way.
+struct ChartData {
+    rects: Vec<SegmentRect>,
+    arrows: Vec<TransferArrow>,
+    lanes: Vec<ContainerLane>,
+    warnings: Vec<String>,
+    /// Sorted gap times
+    transfer_times: Vec<i64>,
+    t_min: i64,
+    t_max: i64,
+    /// Microseconds of real time per pixel at zoom 1.0
+    time_scale: f64,
 }
 
 // ── Data extraction ─────────────────────────────────────────────────────────
 
+/// Datetime value in microseconds, whatever the column's time unit (Arrow input may
+/// carry ns or ms). Nanoseconds round down; milliseconds saturate at the i64 range.
+fn datetime_us(value: PolarsResult<AnyValue>) -> Option<i64> {
+    match value {
+        Ok(AnyValue::Datetime(v, TimeUnit::Microseconds, _)) => Some(v),
+        Ok(AnyValue::Datetime(v, TimeUnit::Nanoseconds, _)) => Some(v.div_euclid(1_000)),
+        Ok(AnyValue::Datetime(v, TimeUnit::Milliseconds, _)) => Some(v.saturating_mul(1_000)),
+        _ => None,
+    }
+}
+
+/// Display text of a cell, or `None` for a null or NaN value. Compares the value
+/// itself, so a string that reads "null" is kept.
+fn cell_text(value: PolarsResult<AnyValue>) -> Option<String> {
+    match value.ok()? {
+        AnyValue::Null => None,
+        AnyValue::Float64(f) if f.is_nan() => None,
+        AnyValue::Float32(f) if f.is_nan() => None,
+        val => Some(format!("{}", val)),
+    }
+}
+
+/// `(name, text)` tooltip fields of row `i`; null and NaN values show as
+/// `missing_value_text` or are left out.
+fn tooltip_fields(
+    cols: &[(&str, &Series)],
+    i: usize,
+    config: &VisualizationConfig,
+) -> Vec<(String, String)> {
+    cols.iter()
+        .filter_map(|(name, col)| {
+            let text = cell_text(col.get(i)).or_else(|| config.missing_value_text.clone())?;
+            Some((name.to_string(), text))
+        })
+        .collect()
+}
+
 fn extract_segments(
     segments: &DataFrame,
     config: &VisualizationConfig,
@@ -100,37 +270,11 @@ fn extract_segments(
     for i in 0..n {
         let segment_id = segment_ids.get(i).unwrap_or("").to_string();
         let container_id = container_ids.get(i).unwrap_or("").to_string();
-        let start_us = match start_times.get(i) {
-            Ok(AnyValue::Datetime(us, _, _)) => us,
-            _ => 0,
-        };
-        let end_us = match end_times.get(i) {
-            Ok(AnyValue::Datetime(us, _, _)) => us,
-            _ => start_us,
-        };
+        let start_us = datetime_us(start_times.get(i)).unwrap_or(0);
+        let end_us = datetime_us(end_times.get(i)).unwrap_or(start_us);
 
-        let label = label_col.and_then(|col| {
-            let val = col.get(i).ok()?;
-            let s = format!("{}", val);
-            if s == "null" {
-                None
-            } else {
-                Some(s)
-            }
-        });
-
-        let tooltip_fields: Vec<(String, String)> = tooltip_cols
-            .iter()
-            .filter_map(|(name, col)| {
-                let val = col.get(i).ok()?;
-                let s = format!("{}", val);
-                if s == "null" {
-                    None
-                } else {
-                    Some((name.to_string(), s))
-                }
-            })
-            .collect();
+        let label = label_col.and_then(|col| cell_text(col.get(i)));
+        let tooltip_fields = tooltip_fields(&tooltip_cols, i, config);
 
         rects.push(SegmentRect {
             segment_id,
@@ -144,12 +288,12 @@ fn extract_segments(
     Ok(rects)
 }
 
-fn extract_transfers(
+/// Time of each transfer row in µs: the source segment's end_time, falling back to the
+/// dest segment's start_time. `None` when neither is known.
+pub(crate) fn transfer_times_us(
     transfers: &DataFrame,
     segments: &DataFrame,
-    config: &VisualizationConfig,
-) -> Result<Vec<TransferArrow>, SdtError> {
-    let n = transfers.height();
+) -> Result<Vec<Option<i64>>, SdtError> {
     let source_ids = transfers.column(transfer::SOURCE_SEGMENT_ID)?.str()?;
     let dest_ids = transfers.column(transfer::DEST_SEGMENT_ID)?.str()?;
 
@@ -166,18 +310,56 @@ fn extract_transfers(
     let mut segment_start_time: HashMap<String, i64> = HashMap::new();
     for i in 0..segments.height() {
         if let Some(pid) = segment_ids.get(i) {
-            if let Ok(AnyValue::Datetime(et, _, _)) = end_times.get(i) {
+            if let Some(et) = datetime_us(end_times.get(i)) {
                 segment_end_time.insert(pid.to_string(), et);
             }
-            if let Ok(AnyValue::Datetime(st, _, _)) = start_times.get(i) {
+            if let Some(st) = datetime_us(start_times.get(i)) {
                 segment_start_time.insert(pid.to_string(), st);
             }
         }
     }
 
+    Ok(source_ids
+        .into_iter()
+        .zip(dest_ids)
+        .map(|(src, dst)| {
+            src.and_then(|src| segment_end_time.get(src))
+                .or_else(|| dst.and_then(|dst| segment_start_time.get(dst)))
+                .copied()
+        })
+        .collect())
+}
+
+/// Transfer rows that `extract_transfers` left out, by reason.
+#[derive(Debug, Default, PartialEq)]
+struct SkippedTransfers {
+    /// A source or dest segment id is not in the segments data (with `transfer_stubs`,
+    /// only rows where both are missing)
+    unknown_segment: usize,
+    /// Source end_time and dest start_time are both null
+    no_time: usize,
+}
+
+fn extract_transfers(
+    transfers: &DataFrame,
+    segments: &DataFrame,
+    config: &VisualizationConfig,
+) -> Result<(Vec<TransferArrow>, SkippedTransfers), SdtError> {
+    let n = transfers.height();
+    let source_ids = transfers.column(transfer::SOURCE_SEGMENT_ID)?.str()?;
+    let dest_ids = transfers.column(transfer::DEST_SEGMENT_ID)?.str()?;
+    let known: HashSet<&str> = segments
+        .column(segment::SEGMENT_ID)?
+        .str()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let transfer_times = transfer_times_us(transfers, segments)?;
+
     let tooltip_cols: Vec<(&str, &Series)> = config
         .transfer_tooltip_cols
         .iter()
+        .flatten()
         .filter_map(|c| {
             transfers
                 .column(c.as_str())
@@ -187,29 +369,24 @@ fn extract_transfers(
         .collect();
 
     let mut arrows = Vec::with_capacity(n);
-    for i in 0..n {
+    let mut skipped = SkippedTransfers::default();
+    for (i, transfer_time_us) in transfer_times.into_iter().enumerate() {
         let src = source_ids.get(i).unwrap_or("").to_string();
         let dst = dest_ids.get(i).unwrap_or("").to_string();
 
-        // Transfer time = source segment end_time, fallback to dest segment start_time
-        let transfer_time_us = segment_end_time
-            .get(&src)
-            .or_else(|| segment_start_time.get(&dst))
-            .copied()
-            .unwrap_or(0);
+        // An arrow needs both ends on the chart, or one end when drawn as a stub
+        let (has_src, has_dst) = (known.contains(src.as_str()), known.contains(dst.as_str()));
+        if !(has_src && has_dst) && !(config.transfer_stubs && (has_src || has_dst)) {
+            skipped.unknown_segment += 1;
+            continue;
+        }
+        // Without a transfer time there is nowhere to draw the arrow, so the row is skipped.
+        let Some(transfer_time_us) = transfer_time_us else {
+            skipped.no_time += 1;
+            continue;
+        };
 
-        let tooltip_fields: Vec<(String, String)> = tooltip_cols
-            .iter()
-            .filter_map(|(name, col)| {
-                let val = col.get(i).ok()?;
-                let s = format!("{}", val);
-                if s == "null" {
-                    None
-                } else {
-                    Some((name.to_string(), s))
-                }
-            })
-            .collect();
+        let tooltip_fields = tooltip_fields(&tooltip_cols, i, config);
 
         arrows.push(TransferArrow {
             source_segment_id: src,
@@ -218,7 +395,7 @@ fn extract_transfers(
             tooltip_fields,
         });
     }
-    Ok(arrows)
+    Ok((arrows, skipped))
 }
 
 fn extract_container_lanes(
@@ -233,6 +410,11 @@ fn extract_container_lanes(
         .container_label_col
         .as_deref()
         .and_then(|c| containers.column(c).ok());
+    let sublabel_col = config
+        .lane_sublabel_col
+        .as_deref()
+        .and_then(|c| containers.column(c).ok());
+    let text_at = |col: Option<&Column>, i: usize| col.and_then(|col| cell_text(col.get(i)));
 
     let mut lanes = Vec::new();
     for i in 0..containers.height() {
@@ -240,33 +422,229 @@ fn extract_container_lanes(
         if !active_ids.contains(cid) {
             continue;
         }
-        let label = label_col
-            .and_then(|col| {
-                let val = col.get(i).ok()?;
-                let s = format!("{}", val);
-                if s == "null" {
-                    None
-                } else {
-                    Some(s)
-                }
-            })
-            .unwrap_or_else(|| cid.to_string());
+        let label = text_at(label_col, i).unwrap_or_else(|| cid.to_string());
 
         lanes.push(ContainerLane {
             container_id: cid.to_string(),
             label,
+            sublabel: text_at(sublabel_col, i),
         });
     }
     Ok(lanes)
 }
 
-/// Sorted unique transfer times used for gap insertion.
-fn collect_transfer_times(arrows: &[TransferArrow]) -> Vec<i64> {
-    let mut times: BTreeSet<i64> = BTreeSet::new();
-    for a in arrows {
-        times.insert(a.transfer_time_us);
+/// Sorted transfer times used for gap insertion.
+///
+/// Times within `tolerance_us` after a gap are merged into it, so each gap is the
+/// earliest time of its cluster. With a tolerance of 0 every distinct time gets a gap.
+fn collect_transfer_times(arrows: &[TransferArrow], tolerance_us: i64) -> Vec<i64> {
+    let times: BTreeSet<i64> = arrows.iter().map(|a| a.transfer_time_us).collect();
+    let mut gaps: Vec<i64> = Vec::with_capacity(times.len());
+    for t in times {
+        match gaps.last() {
+            Some(&gap) if t - gap <= tolerance_us => {}
+            _ => gaps.push(t),
+        }
+    }
+    gaps
+}
+
+/// Moves `t` onto the gap whose cluster it belongs to, if any.
+fn snap_to_gap(t: i64, gaps: &[i64], tolerance_us: i64) -> i64 {
+    match gaps.partition_point(|&g| g <= t) {
+        0 => t,
+        i if t - gaps[i - 1] <= tolerance_us => gaps[i - 1],
+        _ => t,
     }
-    times.into_iter().collect()
+}
+
+/// Real time (µs) from each transfer time to the next transfer time, or to `t_max`
+/// for the last one. Aligned with `transfer_times`.
+fn gap_durations(transfer_times: &[i64], t_max: i64) -> Vec<i64> {
+    transfer_times
+        .iter()
+        .enumerate()
+        .map(|(i, &t)| {
+            let next = transfer_times.get(i + 1).copied().unwrap_or(t_max);
+            (next - t).max(0)
+        })
+        .collect()
+}
+
+// ── Layout ──────────────────────────────────────────────────────────────────
+
+/// Lane labels sit in the margin before the time axis and time labels in the margin
+/// before the lanes, so a vertical chart swaps which margins are wide.
+fn chart_margins(orientation: &str) -> Result<Margins, SdtError> {
+    match orientation {
+        "horizontal" => Ok(Margins {
+            left: 120,
+            top: 40,
+            right: 40,
+            bottom: 20,
+        }),
+        "vertical" => Ok(Margins {
+            left: 80,
+            top: 120,
+            right: 20,
+            bottom: 40,
+        }),
+        other => Err(SdtError::Validation(format!(
+            "Invalid orientation '{other}'. Must be 'horizontal' or 'vertical'"
+        ))),
+    }
+}
+
+/// Extract the chart rows and compute the time axis.
+fn prepare_chart(
+    segments: &DataFrame,
+    containers: &DataFrame,
+    transfers: &DataFrame,
+    config: &VisualizationConfig,
+) -> Result<ChartData, SdtError> {
+    let mut rects = extract_segments(segments, config)?;
+    let (mut arrows, skipped) = extract_transfers(transfers, segments, config)?;
+    let lanes = extract_container_lanes(containers, &rects, config)?;
+
+    let mut warnings = Vec::new();
+    if skipped.unknown_segment > 0 {
+        warnings.push(format!(
+            "{} of {} transfers reference segments not in the segments data and were not drawn",
+            skipped.unknown_segment,
+            transfers.height()
+        ));
+    }
+    if skipped.no_time > 0 {
+        warnings.push(format!(
+            "{} of {} transfers have no transfer time (source end_time and dest start_time \
+             are both null) and were not drawn",
+            skipped.no_time,
+            transfers.height()
+        ));
+    }
+
+    let tolerance_us = config.gap_merge_tolerance_us.max(0);
+    let transfer_times = collect_transfer_times(&arrows, tolerance_us);
+    if tolerance_us > 0 {
+        // Segment edges and arrows at a merged time are drawn at their cluster's gap
+        for r in &mut rects {
+            r.start_us = snap_to_gap(r.start_us, &transfer_times, tolerance_us);
+            r.end_us = snap_to_gap(r.end_us, &transfer_times, tolerance_us);
+        }
+        for a in &mut arrows {
+            a.transfer_time_us = snap_to_gap(a.transfer_time_us, &transfer_times, tolerance_us);
+        }
+    }
+
+    let t_min = rects.iter().map(|r| r.start_us).min().unwrap_or(0);
+    let mut t_max = rects.iter().map(|r| r.end_us).max().unwrap_or(1);
+    if t_max <= t_min {
+        // Zero-length segments (no end time) would be invisible slivers; draw them
+        // across the padded span instead
+        t_max = t_min + MIN_SPAN_US;
+        for r in &mut rects {
+            r.end_us = t_max;
+        }
+    }
+    let time_range = (t_max - t_min) as f64;
+
+    // Scale: 1.0 zoom = ~800px for the full time range (before gaps)
+    let time_scale = time_range / 800.0;
+
+    Ok(ChartData {
+        rects,
+        arrows,
+        lanes,
+        warnings,
+        transfer_times,
+        t_min,
+        t_max,
+        time_scale,
+    })
+}
+
+/// Pixel offset of `t_us` along the time axis, as `timeToX` in sdt_chart.js: the
+/// continuous position at `zoom` plus `gap_px` for every gap before `t_us`, or at or
+/// before it with `after_gap` (where rectangles start).
+fn time_to_px(data: &ChartData, t_us: i64, after_gap: bool, zoom: f64, gap_px: u32) -> f64 {
+    let continuous = zoom * (t_us - data.t_min) as f64 / data.time_scale;
+    let gaps = if after_gap {
+        data.transfer_times.partition_point(|&g| g <= t_us)
+    } else {
+        data.transfer_times.partition_point(|&g| g < t_us)
+    };
+    continuous + gaps as f64 * f64::from(gap_px)
+}
+
+/// Pixel rectangle of every drawn segment in the `generate_trace_html` chart at the
+/// config's `initial_zoom`, in SVG coordinates (margins included).
+///
+/// Columns: `segment_id`, `container_id`, `lane` (0-based lane index), `x`, `y`,
+/// `width`, `height`. Segments whose container has no lane are left out, as in the
+/// chart.
+pub fn compute_trace_layout(
+    segments: &DataFrame,
+    containers: &DataFrame,
+    transfers: &DataFrame,
+    config: &VisualizationConfig,
+) -> Result<DataFrame, SdtError> {
+    let margins = chart_margins(&config.orientation)?;
+    let vertical = config.orientation == "vertical";
+    let (time_origin, lane_origin) = if vertical {
+        (margins.top, margins.left)
+    } else {
+        (margins.left, margins.top)
+    };
+    let data = prepare_chart(segments, containers, transfers, config)?;
+
+    let lane_of: HashMap<&str, u32> = data
+        .lanes
+        .iter()
+        .enumerate()
+        .map(|(i, lane)| (lane.container_id.as_str(), i as u32))
+        .collect();
+    let lane_height = f64::from(config.lane_height_px);
+    let padding = f64::from(RECT_PADDING_PX);
+
+    let mut segment_ids = Vec::new();
+    let mut container_ids = Vec::new();
+    let mut lanes = Vec::new();
+    let (mut xs, mut ys, mut widths, mut heights) = (vec![], vec![], vec![], vec![]);
+    for r in &data.rects {
+        let Some(&lane) = lane_of.get(r.container_id.as_str()) else {
+            continue;
+        };
+        let t1 = f64::from(time_origin)
+            + time_to_px(&data, r.start_us, true, config.initial_zoom, config.gap_px);
+        let t2 = f64::from(time_origin)
+            + time_to_px(&data, r.end_us, false, config.initial_zoom, config.gap_px);
+        let length = (t2 - t1).max(MIN_RECT_PX);
+        let l = f64::from(lane_origin) + f64::from(lane) * lane_height + padding;
+        let thickness = lane_height - 2.0 * padding;
+        let (x, y, width, height) = if vertical {
+            (l, t1, thickness, length)
+        } else {
+            (t1, l, length, thickness)
+        };
+
+        segment_ids.push(r.segment_id.as_str());
+        container_ids.push(r.container_id.as_str());
+        lanes.push(lane);
+        xs.push(x);
+        ys.push(y);
+        widths.push(width);
+        heights.push(height);
+    }
+
+    Ok(DataFrame::new(vec![
+        Column::new(segment::SEGMENT_ID.into(), segment_ids),
+        Column::new(segment::CONTAINER_ID.into(), container_ids),
+        Column::new("lane".into(), lanes),
+        Column::new("x".into(), xs),
+        Column::new("y".into(), ys),
+        Column::new("width".into(), widths),
+        Column::new("height".into(), heights),
+    ])?)
 }
 
 // ── HTML generation ─────────────────────────────────────────────────────────
@@ -275,30 +653,37 @@ fn collect_transfer_times(arrows: &[TransferArrow]) -> Vec<i64> {
 ///
 /// Extracts data from the DataFrames, serializes to JSON, and emits an HTML
 /// shell with embedded JS that handles all SVG rendering client-side.
+/// Transfers whose time cannot be placed (neither segment is known) are left
+/// out and reported in `RenderedTrace::warnings` and in the chart header.
 pub fn generate_trace_html(
     segments: &DataFrame,
     containers: &DataFrame,
     transfers: &DataFrame,
     config: &VisualizationConfig,
-) -> Result<String, SdtError> {
-    // ── Extract data ────────────────────────────────────────────────────
-    let rects = extract_segments(segments, config)?;
-    let arrows = extract_transfers(transfers, segments, config)?;
-    let lanes = extract_container_lanes(containers, &rects, config)?;
+) -> Result<RenderedTrace, SdtError> {
+    let margins = chart_margins(&config.orientation)?;
+    let ChartData {
+        rects,
+        arrows,
+        lanes,
+        warnings,
+        transfer_times,
+        t_min,
+        t_max,
+        time_scale,
+    } = prepare_chart(segments, containers, transfers, config)?;
 
     if rects.is_empty() {
-        return Ok("<div>No segments to visualize.</div>".to_string());
+        return Ok(RenderedTrace {
+            html: "<div>No segments to visualize.</div>".to_string(),
+            warnings,
+        });
     }
 
-    // ── Layout parameters (passed to JS) ────────────────────────────────
-    let transfer_times = collect_transfer_times(&arrows);
-
-    let t_min = rects.iter().map(|r| r.start_us).min().unwrap_or(0);
-    let t_max = rects.iter().map(|r| r.end_us).max().unwrap_or(1);
-    let time_range = (t_max - t_min).max(1) as f64;
-
-    // Scale: 1.0 zoom = ~800px for the full time range (before gaps)
-    let time_scale = time_range / 800.0;
+    let warning_html: String = warnings
+        .iter()
+        .map(|w| format!(r#"<span style="color:#e67700; font-size:11px;">⚠ {w}</span>"#))
+        .collect();
 
     // ── Emit HTML ───────────────────────────────────────────────────────
     let html = format!(
@@ -309,11 +694,13 @@ pub fn generate_trace_html(
     <button onclick="sdtZoom(1/1.5)" style="cursor:pointer; padding:2px 8px;">Zoom −</button>
     <button onclick="sdtResetZoom()" style="cursor:pointer; padding:2px 8px;">Reset</button>
     <span id="sdt-zoom-label" style="color:#868e96; font-size:11px;">1.0x</span>
+    {warning_html}
   </div>
   <div id="sdt-scroll-container" style="overflow:auto; max-height:600px;">
     <svg id="sdt-svg" xmlns="http://www.w3.org/2000/svg" width="100" height="100">
       <style>
         .lane-label {{ font-family: sans-serif; font-size: 12px; fill: #495057; text-anchor: end; }}
+        .lane-sublabel {{ font-family: sans-serif; font-size: 9px; fill: #868e96; text-anchor: end; }}
         .time-label {{ font-family: sans-serif; font-size: 10px; fill: #868e96; text-anchor: middle; }}
         .segment-rect {{ fill: #4dabf7; stroke: #339af0; stroke-width: 1; cursor: pointer; }}
         .segment-rect:hover {{ fill: #339af0; stroke: #228be6; stroke-width: 2; }}
@@ -336,10 +723,14 @@ SdtChart.create({{
   zoom: {zoom}, tMin: {t_min}, tMax: {t_max},
   timeScale: {time_scale}, gapPx: {gap_px},
   transferTimes: {transfer_times_json},
-  marginLeft: 120, marginTop: 40,
-  marginRight: 40, marginBottom: 20,
+  gapDurations: {gap_durations_json},
+  orientation: "{orientation}",
+  marginLeft: {margin_left}, marginTop: {margin_top},
+  marginRight: {margin_right}, marginBottom: {margin_bottom},
   laneHeight: {lane_height}, numLanes: {num_lanes},
-  rectPadding: 4,
+  stripeLanes: {stripe_lanes},
+  transferStubs: {transfer_stubs},
+  rectPadding: {rect_padding},
   segments: {segments_json},
   transfers: {transfers_json},
   lanes: {lanes_json}
@@ -351,16 +742,30 @@ SdtChart.create({{
         time_scale = time_scale,
         gap_px = config.gap_px,
         transfer_times_json = to_json_array_i64(&transfer_times),
+        gap_durations_json = if config.show_gap_duration {
+            to_json_array_i64(&gap_durations(&transfer_times, t_max))
+        } else {
+            "null".to_string()
+        },
         lane_height = config.lane_height_px,
         num_lanes = lanes.len(),
+        stripe_lanes = config.stripe_lanes,
+        transfer_stubs = config.transfer_stubs,
+        orientation = config.orientation,
+        margin_left = margins.left,
+        margin_top = margins.top,
+        margin_right = margins.right,
+        margin_bottom = margins.bottom,
+        rect_padding = RECT_PADDING_PX,
         segments_json = segments_to_json(&rects),
         transfers_json = transfers_to_json(&arrows),
         lanes_json = lanes_to_json(&lanes),
         time_axis_js = TIME_AXIS_JS,
         chart_js = CHART_JS,
+        warning_html = warning_html,
     );
 
-    Ok(html)
+    Ok(RenderedTrace { html, warnings })
 }
 
 // ── JSON serialization helpers ──────────────────────────────────────────────
@@ -448,11 +853,16 @@ fn lanes_to_json(lanes: &[ContainerLane]) -> String {
         if i > 0 {
             s.push(',');
         }
+        let sublabel = match &l.sublabel {
+            Some(sub) => format!("\"{}\"", escape_json(sub)),
+            None => "null".to_string(),
+        };
         write!(
             s,
-            r##"{{"container_id":"{}","label":"{}"}}"##,
+            r##"{{"container_id":"{}","label":"{}","sublabel":{}}}"##,
             escape_json(&l.container_id),
             escape_json(&l.label),
+            sublabel,
         )
         .unwrap();
     }
@@ -466,4 +876,110 @@ fn escape_json(s: &str) -> String {
         .replace('\n', "\\n")
         .replace('\r', "\\r")
         .replace('\t', "\\t")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> VisualizationConfig {
+        VisualizationConfig::new(
+            None,
+            None,
+            None,
+            None,
+            32,
+            24,
+            1.0,
+            false,
+            0,
+            true,
+            None,
+            "horizontal".to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn single_open_segment_spans_the_chart() {
+        let start = Column::new(segment::START_TIME.into(), [Some(1_000_000i64)])
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+            .unwrap();
+        let end = Column::new(segment::END_TIME.into(), [None::<i64>])
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+            .unwrap();
+        let segments = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["S1"]),
+            Column::new(segment::CONTAINER_ID.into(), ["C1"]),
+            start,
+            end,
+        ])
+        .unwrap();
+        let containers =
+            DataFrame::new(vec![Column::new(container::CONTAINER_ID.into(), ["C1"])]).unwrap();
+        let transfers = DataFrame::new(vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), Vec::<&str>::new()),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), Vec::<&str>::new()),
+        ])
+        .unwrap();
+
+        let layout = compute_trace_layout(&segments, &containers, &transfers, &config()).unwrap();
+        assert_eq!(layout.height(), 1);
+        let value = |name: &str| layout.column(name).unwrap().f64().unwrap().get(0).unwrap();
+        let margins = chart_margins("horizontal").unwrap();
+        assert_eq!(value("x"), f64::from(margins.left));
+        assert!(
+            (value("width") - 800.0).abs() < 1e-9,
+            "width {}",
+            value("width")
+        );
+
+        let rendered = generate_trace_html(&segments, &containers, &transfers, &config()).unwrap();
+        assert!(rendered.warnings.is_empty());
+        assert!(rendered.html.contains("\"S1\""));
+    }
+
+    #[test]
+    fn skipped_transfers_are_counted_by_reason() {
+        let times = |values: [Option<i64>; 3], name: &str| {
+            Column::new(name.into(), values)
+                .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+                .unwrap()
+        };
+        let segments = DataFrame::new(vec![
+            Column::new(segment::SEGMENT_ID.into(), ["S1", "S2", "S3"]),
+            Column::new(segment::CONTAINER_ID.into(), ["C1", "C1", "C2"]),
+            times([Some(0), None, None], segment::START_TIME),
+            times([Some(1_000_000), None, None], segment::END_TIME),
+        ])
+        .unwrap();
+        let transfers = DataFrame::new(vec![
+            Column::new(transfer::SOURCE_SEGMENT_ID.into(), ["S1", "S1", "S2", "X"]),
+            Column::new(transfer::DEST_SEGMENT_ID.into(), ["S3", "Y", "S3", "Y"]),
+        ])
+        .unwrap();
+
+        let (arrows, skipped) = extract_transfers(&transfers, &segments, &config()).unwrap();
+        assert_eq!(arrows.len(), 1);
+        assert_eq!(
+            skipped,
+            SkippedTransfers {
+                unknown_segment: 2,
+                no_time: 1
+            }
+        );
+
+        // As stubs, only the row with neither segment known is left out
+        let mut stubs = config();
+        stubs.transfer_stubs = true;
+        let (arrows, skipped) = extract_transfers(&transfers, &segments, &stubs).unwrap();
+        assert_eq!(arrows.len(), 2);
+        assert_eq!(
+            skipped,
+            SkippedTransfers {
+                unknown_segment: 1,
+                no_time: 1
+            }
+        );
+    }
+}